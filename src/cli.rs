@@ -0,0 +1,317 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use dashmap::DashMap;
+use serde_json::{json, Value};
+use tower_lsp::LspService;
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, NumberOrString, Url};
+use crate::parse::Backend;
+use crate::{components, project};
+
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Format {
+    Human,
+    Json,
+    Sarif,
+    Github,
+}
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Severity {
+    Error,
+    Warning,
+    #[value(alias = "information")]
+    Info,
+    Hint,
+}
+// Checks typst files for grammar issues, for CI pipelines and other use without an editor
+#[derive(clap::Args)]
+pub struct CheckArgs {
+    /// How the diagnostics are printed
+    #[arg(long, value_enum, default_value = "human")]
+    format: Format,
+    /// Exit with 1 when an issue this severe or worse is found
+    #[arg(long, value_enum, default_value = "warning")]
+    severity: Severity,
+    /// Issues in this baseline are not reported [default: ./.grammar-baseline.json if it exists]
+    #[arg(long, value_name = "FILE")]
+    baseline: Option<PathBuf>,
+    /// Accept every issue found, writes them to the baseline
+    #[arg(long)]
+    write_baseline: bool,
+    /// Removes fixed issues from the baseline
+    #[arg(long)]
+    prune_baseline: bool,
+    /// Files or globs like "chapters/**/*.typ"
+    #[arg(required = true, value_name = "FILES")]
+    files: Vec<String>,
+}
+// A diagnostic ready to print, positions are 1-based
+struct Issue {
+    file: String,
+    line: u32,
+    column: u32,
+    end_line: u32,
+    end_column: u32,
+    severity: DiagnosticSeverity,
+    code: String,
+    source: String,
+    message: String,
+}
+
+// Runs `typst-lsp check`, returns the exit code. 0 when no issue reaches the severity
+// threshold, 1 when some does, 2 when the arguments or files are wrong and 3 when a checker could
+// not run, like LanguageTool being unreachable, so a CI run does not pass without checking.
+pub async fn run(options :CheckArgs) -> i32 {
+    let files = match expand_all(&options.files) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        },
+    };
+    let (service, _socket) = LspService::new(|client| Backend {
+        client,
+        document_map: DashMap::new(),
+        project: project::Project::new(),
+        checkers: components::Checkers::builtin(),
+    });
+    let backend = service.inner();
+    let root = match std::env::current_dir() {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Could not read the current directory: {}", e);
+            return 2;
+        },
+    };
+    backend.project.roots.write().unwrap().push(root.clone());
+    // The entries of a baseline are relative to its directory
+    let baseline = match &options.baseline {
+        Some(c) => root.join(c),
+        None => root.join(components::BASELINE_FILE_NAME),
+    };
+    // A baseline that is about to be written may not exist yet
+    if !components::load_baseline_file(&baseline) && options.baseline.is_some() && !options.write_baseline {
+        eprintln!("Could not read the baseline {}", baseline.display());
+        return 2;
+    }
+    let mut uris :Vec<Url> = vec![];
+    for file in &files {
+        let text = match fs::read_to_string(file) {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("Could not read {}: {}", file.display(), e);
+                return 2;
+            },
+        };
+        let uri = match fs::canonicalize(file).ok().and_then(|c| Url::from_file_path(c).ok()) {
+            Some(c) => c,
+            None => {
+                eprintln!("Could not resolve {}", file.display());
+                return 2;
+            },
+        };
+        backend.create_document(&uri, 0, &text);
+        uris.push(uri);
+    }
+    for uri in &uris {
+        backend.update_dependencies(uri);
+    }
+    for uri in &uris {
+        components::check_file(backend, uri).await;
+    }
+    components::shutdown_language_tool().await;
+    let failed = components::failed_checks();
+    if failed > 0 && (options.write_baseline || options.prune_baseline) {
+        eprintln!("{} checks could not run, the baseline is left as it is", failed);
+        return 3;
+    }
+    if options.write_baseline {
+        match components::write_baseline(backend, &baseline) {
+            Some(c) => eprintln!("Wrote {} issues to the baseline", c),
+            None => {
+                eprintln!("Could not write the baseline");
+                return 2;
+            },
+        }
+    }
+    if options.prune_baseline {
+        match components::prune_baseline(backend, &baseline) {
+            Some(c) => eprintln!("Pruned {} fixed issues from the baseline", c),
+            None => {
+                eprintln!("There is no baseline to prune");
+                return 2;
+            },
+        }
+    }
+    let mut issues :Vec<Issue> = vec![];
+    for (uri, diagnostics) in components::stored_diagnostics(backend) {
+        for diagnostic in diagnostics {
+            if let Some(c) = to_issue(&uri, diagnostic) {
+                issues.push(c);
+            }
+        }
+    }
+    issues.sort_by(|a, b| (&a.file, a.line, a.column).cmp(&(&b.file, b.line, b.column)));
+    match options.format {
+        Format::Human => print_human(&issues),
+        Format::Json => println!("{}", serde_json::to_string_pretty(&to_json(&issues)).unwrap()),
+        Format::Sarif => println!("{}", serde_json::to_string_pretty(&to_sarif(&issues)).unwrap()),
+        Format::Github => print_github(&issues),
+    }
+    // A lower severity number is more severe
+    let threshold = match options.severity {
+        Severity::Error => DiagnosticSeverity::ERROR,
+        Severity::Warning => DiagnosticSeverity::WARNING,
+        Severity::Info => DiagnosticSeverity::INFORMATION,
+        Severity::Hint => DiagnosticSeverity::HINT,
+    };
+    if failed > 0 {
+        eprintln!("{} checks could not run, see the messages above", failed);
+        3
+    } else if issues.iter().any(|c| c.severity <= threshold) {
+        1
+    } else {
+        0
+    }
+}
+fn expand_all(patterns :&[String]) -> Result<Vec<PathBuf>, String> {
+    let mut out :Vec<PathBuf> = vec![];
+    for pattern in patterns {
+        let found = expand(pattern)?;
+        if found.is_empty() {
+            return Err(format!("No files match {}", pattern));
+        }
+        out.extend(found);
+    }
+    let mut seen :HashSet<PathBuf> = HashSet::new();
+    out.retain(|c| seen.insert(c.clone()));
+    Ok(out)
+}
+// A path, or every file a glob like "chapters/**/*.typ" matches
+fn expand(pattern :&str) -> Result<Vec<PathBuf>, String> {
+    if !pattern.contains(['*', '?', '[']) {
+        return Ok(vec![PathBuf::from(pattern)]);
+    }
+    let paths = glob::glob(pattern).map_err(|e| format!("Bad glob {}: {}", pattern, e))?;
+    Ok(paths.filter_map(|c| c.ok()).filter(|c| c.is_file()).collect())
+}
+fn to_issue(uri :&Url, diagnostic :Diagnostic) -> Option<Issue> {
+    let path = uri.to_file_path().ok()?;
+    let file = std::env::current_dir().ok()
+        .and_then(|c| path.strip_prefix(c).ok().map(|c| c.to_path_buf()))
+        .unwrap_or(path.clone());
+    let code = match &diagnostic.code {
+        Some(NumberOrString::String(c)) => c.clone(),
+        Some(NumberOrString::Number(c)) => c.to_string(),
+        None => String::new(),
+    };
+    Some(Issue {
+        file: file.to_string_lossy().to_string(),
+        line: diagnostic.range.start.line + 1,
+        column: diagnostic.range.start.character + 1,
+        end_line: diagnostic.range.end.line + 1,
+        end_column: diagnostic.range.end.character + 1,
+        // A diagnostic without severity is up to the client, treat it as an error
+        severity: diagnostic.severity.unwrap_or(DiagnosticSeverity::ERROR),
+        code,
+        source: diagnostic.source.unwrap_or("languagetool".to_string()),
+        message: diagnostic.message,
+    })
+}
+fn severity_name(severity :DiagnosticSeverity) -> &'static str {
+    match severity {
+        DiagnosticSeverity::ERROR => "error",
+        DiagnosticSeverity::WARNING => "warning",
+        DiagnosticSeverity::INFORMATION => "info",
+        _ => "hint",
+    }
+}
+fn print_human(issues :&[Issue]) {
+    for issue in issues {
+        println!(
+            "{}:{}:{}: {}: {} [{}]",
+            issue.file, issue.line, issue.column, severity_name(issue.severity), issue.message, issue.code
+        );
+    }
+    let files :HashSet<&String> = issues.iter().map(|c| &c.file).collect();
+    println!("{} issues in {} files", issues.len(), files.len());
+}
+fn print_github(issues :&[Issue]) {
+    for issue in issues {
+        let level = match issue.severity {
+            DiagnosticSeverity::ERROR => "error",
+            DiagnosticSeverity::WARNING => "warning",
+            _ => "notice",
+        };
+        println!(
+            "::{} file={},line={},col={},endLine={},endColumn={},title={}::{}",
+            level,
+            github_escape(&issue.file, true),
+            issue.line,
+            issue.column,
+            issue.end_line,
+            issue.end_column,
+            github_escape(&format!("{} {}", issue.source, issue.code), true),
+            github_escape(&issue.message, false),
+        );
+    }
+}
+fn github_escape(text :&str, property :bool) -> String {
+    let out = text.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A");
+    if property {
+        out.replace(':', "%3A").replace(',', "%2C")
+    } else {
+        out
+    }
+}
+fn to_json(issues :&[Issue]) -> Value {
+    Value::Array(issues.iter().map(|c| json!({
+        "file": c.file,
+        "line": c.line,
+        "column": c.column,
+        "end_line": c.end_line,
+        "end_column": c.end_column,
+        "severity": severity_name(c.severity),
+        "code": c.code,
+        "source": c.source,
+        "message": c.message,
+    })).collect())
+}
+fn to_sarif(issues :&[Issue]) -> Value {
+    let mut rules :Vec<&String> = issues.iter().map(|c| &c.code).collect();
+    rules.sort();
+    rules.dedup();
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "typst-lsp",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules.iter().map(|c| json!({"id": c})).collect::<Vec<Value>>(),
+                }
+            },
+            "results": issues.iter().map(|c| json!({
+                "ruleId": c.code,
+                "level": match c.severity {
+                    DiagnosticSeverity::ERROR => "error",
+                    DiagnosticSeverity::WARNING => "warning",
+                    _ => "note",
+                },
+                "message": { "text": c.message },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": c.file.replace('\\', "/") },
+                        "region": {
+                            "startLine": c.line,
+                            "startColumn": c.column,
+                            "endLine": c.end_line,
+                            "endColumn": c.end_column,
+                        }
+                    }
+                }],
+            })).collect::<Vec<Value>>(),
+        }]
+    })
+}
@@ -0,0 +1,316 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::{NumberOrString, Url};
+use super::{Diagnostic, DIAGNOSTICS};
+use crate::parse;
+
+pub const FILE_NAME :&str = ".grammar-baseline.json";
+pub const WRITE_COMMAND :&str = "typst-grammar.writeBaseline";
+pub const PRUNE_COMMAND :&str = "typst-grammar.pruneBaseline";
+//How many characters around the issue are part of the fingerprint
+const CONTEXT_CHARS :usize = 30;
+
+lazy_static! {
+    //The loaded baselines, keyed by the directory they are in. The set holds (file, fingerprint)
+    //with the file relative to that directory.
+    static ref BASELINES :DashMap<PathBuf, HashSet<(String, String)>> = DashMap::new();
+}
+
+#[derive(Serialize, Deserialize)]
+struct BaselineFile {
+    version: u32,
+    entries: Vec<Entry>,
+}
+#[derive(Serialize, Deserialize, Clone)]
+struct Entry {
+    //Relative to the directory of the baseline, with / as separator
+    file: String,
+    //The checker, only entries of checkers that ran on the file are pruned
+    source: String,
+    rule: String,
+    fingerprint: String,
+    //Not used for matching, it is there to make the file readable in reviews
+    message: String,
+}
+
+// Loads `.grammar-baseline.json` from a workspace folder, if there is one
+pub fn load(root :&Path) {
+    load_file(&root.join(FILE_NAME));
+}
+pub fn load_file(path :&Path) -> bool {
+    let root = match path.parent() {
+        Some(c) => c.to_path_buf(),
+        None => return false,
+    };
+    let file = match read(path) {
+        Some(c) => c,
+        None => {
+            BASELINES.remove(&root);
+            return false;
+        },
+    };
+    BASELINES.insert(root, file.entries.into_iter().map(|c| (c.file, c.fingerprint)).collect());
+    true
+}
+pub fn unload(root :&Path) {
+    BASELINES.remove(root);
+}
+// If the issue was there when the baseline was written and should not be reported
+pub fn is_baselined(document :&parse::Document, diagnostic :&Diagnostic) -> bool {
+    if BASELINES.is_empty() {
+        return false;
+    }
+    let (root, file) = match baseline_of(&diagnostic.uri) {
+        Some(c) => c,
+        None => return false,
+    };
+    let fingerprint = match fingerprint(document, diagnostic) {
+        Some(c) => c,
+        None => return false,
+    };
+    BASELINES.get(&root).is_some_and(|c| c.contains(&(file, fingerprint)))
+}
+// Writes every stored diagnostic of the files below the directory of the baseline to it. Entries
+// of checkers that have not run on a file are kept. Returns the number of entries written.
+pub fn write(backend :&crate::Backend, path :&Path) -> Option<usize> {
+    let root = path.parent()?;
+    let current = current_entries(backend, root);
+    let checked = checked_files(backend, root);
+    let mut entries :Vec<Entry> = read(path)
+        .map_or(vec![], |c| c.entries)
+        .into_iter()
+        .filter(|c| !checked.contains(&(c.file.clone(), c.source.clone())))
+        .collect();
+    entries.extend(current);
+    save(path, entries)
+}
+// Removes the entries of the baseline whose issue is gone, either because it was fixed or
+// because the file no longer exists. Only files that have been checked are pruned.
+// Returns the number of entries removed.
+pub fn prune(backend :&crate::Backend, path :&Path) -> Option<usize> {
+    let root = path.parent()?;
+    let old = read(path)?.entries;
+    let current :HashSet<(String, String)> = current_entries(backend, root).into_iter()
+        .map(|c| (c.file, c.fingerprint))
+        .collect();
+    let checked = checked_files(backend, root);
+    let before = old.len();
+    let entries :Vec<Entry> = old.into_iter()
+        .filter(|c| root.join(&c.file).is_file())
+        .filter(|c| {
+            !checked.contains(&(c.file.clone(), c.source.clone()))
+                || current.contains(&(c.file.clone(), c.fingerprint.clone()))
+        })
+        .collect();
+    let removed = before - entries.len();
+    save(path, entries)?;
+    Some(removed)
+}
+fn source_name(diagnostic :&Diagnostic) -> String {
+    diagnostic.source.to_string()
+}
+// The rule of a diagnostic, the language tool rule id or the code of the native checkers
+pub fn rule_id(diagnostic :&Diagnostic) -> String {
+    if let Some(c) = diagnostic.data::<super::language_tool::LTDiagnostic>() {
+        return c.rule.id.clone();
+    }
+    match &diagnostic.diagnostics_lsp.code {
+        Some(NumberOrString::String(c)) => c.clone(),
+        Some(NumberOrString::Number(c)) => c.to_string(),
+        None => diagnostic.diagnostics_lsp.source.clone().unwrap_or_default(),
+    }
+}
+// The rule and the text around the issue, lowercased and with whitespace collapsed, hashed. Byte
+// offsets are left out so edits elsewhere in the file do not change it.
+pub fn fingerprint(document :&parse::Document, diagnostic :&Diagnostic) -> Option<String> {
+    let range = document.correct_range(diagnostic.version, diagnostic.range.clone())?;
+    let text = document.typst_source.text();
+    let flagged = text.get(range.clone())?;
+    let before :String = text.get(..range.start)?.chars().rev().take(CONTEXT_CHARS).collect::<Vec<char>>()
+        .into_iter().rev().collect();
+    let after :String = text.get(range.end..)?.chars().take(CONTEXT_CHARS).collect();
+    let context = format!("{}[{}]{}", before, flagged, after)
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase();
+    Some(format!("{:016x}", fnv1a(&format!("{}\0{}", rule_id(diagnostic), context))))
+}
+// FNV-1a, unlike the std hasher it gives the same result on every platform and rust version
+pub fn fnv1a(text :&str) -> u64 {
+    let mut hash :u64 = 0xcbf29ce484222325;
+    for byte in text.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+// The directory of the loaded baseline that holds the file and the path of the file relative to it
+fn baseline_of(uri :&Url) -> Option<(PathBuf, String)> {
+    let path = uri.to_file_path().ok()?;
+    let root = BASELINES.iter()
+        .map(|c| c.key().clone())
+        .filter(|c| path.starts_with(c))
+        .max_by_key(|c| c.as_os_str().len())?;
+    let file = relative(&root, &path)?;
+    Some((root, file))
+}
+fn relative(root :&Path, path :&Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    Some(relative.components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect::<Vec<String>>()
+        .join("/"))
+}
+fn current_entries(backend :&crate::Backend, root :&Path) -> Vec<Entry> {
+    let diagnostics :Vec<Diagnostic> = DIAGNOSTICS.lock().unwrap().clone();
+    let mut out = vec![];
+    for diagnostic in diagnostics {
+        let file = match diagnostic.uri.to_file_path().ok().and_then(|c| relative(root, &c)) {
+            Some(c) => c,
+            None => continue,
+        };
+        let fingerprint = match backend.with_document(&diagnostic.uri, |c| fingerprint(c, &diagnostic)) {
+            Some(Some(c)) => c,
+            _ => continue,
+        };
+        out.push(Entry {
+            file,
+            source: source_name(&diagnostic),
+            rule: rule_id(&diagnostic),
+            fingerprint,
+            message: diagnostic.diagnostics_lsp.message.clone(),
+        });
+    }
+    out
+}
+// The (file, checker) pairs below root whose diagnostics are up to date. A checker has run on a
+// file when it stored diagnostics for it, the checkers that are not on demand always run on open
+// files.
+fn checked_files(backend :&crate::Backend, root :&Path) -> HashSet<(String, String)> {
+    let mut checked :Vec<(Url, String)> = DIAGNOSTICS.lock().unwrap().iter()
+        .map(|c| (c.uri.clone(), source_name(c)))
+        .collect();
+    for document in backend.document_map.iter() {
+        for checker in backend.checkers.iter().filter(|c| !c.on_demand()) {
+            checked.push((document.key().clone(), checker.name().to_string()));
+        }
+    }
+    checked.into_iter()
+        .filter_map(|(uri, source)| {
+            let file = relative(root, &uri.to_file_path().ok()?)?;
+            Some((file, source))
+        })
+        .collect()
+}
+fn read(path :&Path) -> Option<BaselineFile> {
+    let text = fs::read_to_string(path).ok()?;
+    match serde_json::from_str(&text) {
+        Ok(c) => Some(c),
+        Err(e) => {
+            log::warn!("Could not parse the baseline {}: {}", path.display(), e);
+            None
+        },
+    }
+}
+fn save(path :&Path, mut entries :Vec<Entry>) -> Option<usize> {
+    entries.sort_by(|a, b| (&a.file, &a.source, &a.rule, &a.fingerprint).cmp(&(&b.file, &b.source, &b.rule, &b.fingerprint)));
+    let count = entries.len();
+    let text = serde_json::to_string_pretty(&BaselineFile {
+        version: 1,
+        entries,
+    }).ok()?;
+    if let Err(e) = fs::write(path, text + "\n") {
+        log::warn!("Could not write the baseline {}: {}", path.display(), e);
+        return None;
+    }
+    load_file(path);
+    Some(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn found(document :&parse::Document, uri :&Url, text :&str, word :&str) -> Diagnostic {
+        let start = text.find(word).unwrap();
+        Diagnostic::new(
+            document,
+            uri,
+            start..start + word.len(),
+            tower_lsp::lsp_types::Diagnostic {
+                code: Some(NumberOrString::String(String::from("test-rule"))),
+                message: format!("About {}", word),
+                ..Default::default()
+            },
+            super::super::heading::NAME,
+            (),
+        ).unwrap()
+    }
+    fn fingerprint_of(text :&str, word :&str) -> String {
+        let document = parse::Document::new(0, &text.to_string());
+        let uri = Url::parse("file:///tmp/test.typ").unwrap();
+        fingerprint(&document, &found(&document, &uri, text, word)).unwrap()
+    }
+
+    #[test]
+    fn fingerprints_ignore_edits_elsewhere() {
+        // Longer than the context on both sides of "has"
+        let issue = "In the first part of this long sentence we has a problem in this sentence, it is flagged.";
+        let original = fingerprint_of(&format!("= Intro\n\n{}\n", issue), "has");
+        let edited = format!("= A longer intro\n\nWith a new paragraph before it.\n\n{}\n\nAnd one after.", issue);
+        assert_eq!(fingerprint_of(&edited, "has"), original);
+        // Case and line breaks in the context do not matter either
+        let wrapped = format!("= Intro\n\n{}\n", issue.replace("long sentence we", "Long sentence\nwe"));
+        assert_eq!(fingerprint_of(&wrapped, "has"), original);
+    }
+
+    #[test]
+    fn fingerprints_change_with_their_context() {
+        let original = fingerprint_of("We has a problem in this sentence.", "has");
+        assert_ne!(fingerprint_of("We has two problems in this sentence.", "has"), original);
+        assert_ne!(fingerprint_of("They has a problem in this sentence.", "has"), original);
+    }
+
+    #[test]
+    fn prune_keeps_matching_entries() {
+        let root = std::env::temp_dir().join(format!("typst-lsp-baseline-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let path = root.join(FILE_NAME);
+        let text = "We has a problem. They was here.";
+        let file = root.join("main.typ");
+        fs::write(&file, text).unwrap();
+        let uri = Url::from_file_path(&file).unwrap();
+        let (service, _socket) = tower_lsp::LspService::new(|client| crate::Backend {
+            client,
+            document_map: DashMap::new(),
+            project: crate::project::Project::new(),
+            checkers: super::super::Checkers::builtin(),
+        });
+        let backend = service.inner();
+        backend.create_document(&uri, 0, &text.to_string());
+        let document = parse::Document::new(0, &text.to_string());
+        let has = found(&document, &uri, text, "has");
+        let was = found(&document, &uri, text, "was");
+        DIAGNOSTICS.lock().unwrap().extend([has.clone(), was]);
+        assert_eq!(write(backend, &path), Some(2));
+
+        // "was" got fixed
+        DIAGNOSTICS.lock().unwrap().retain(|c| c.uri != uri);
+        DIAGNOSTICS.lock().unwrap().push(has.clone());
+        assert_eq!(prune(backend, &path), Some(1));
+        let entries = read(&path).unwrap().entries;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].file, "main.typ");
+        assert_eq!(Some(entries[0].fingerprint.clone()), fingerprint(&document, &has));
+        assert!(is_baselined(&document, &has));
+
+        DIAGNOSTICS.lock().unwrap().retain(|c| c.uri != uri);
+        unload(&root);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,261 @@
+use std::ops::Range;
+use super::{Entry, Field, Bibliography};
+
+// A small BibTeX parser that keeps the byte ranges of keys and fields so diagnostics can point
+// into the .bib file. Malformed entries are reported in `Bibliography::errors` and skipped.
+pub fn parse(text :&str) -> Bibliography {
+    let mut parser = Parser {
+        text,
+        bytes: text.as_bytes(),
+        at: 0,
+        out: Bibliography {
+            entries: vec![],
+            errors: vec![],
+        },
+    };
+    parser.parse();
+    parser.out
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    bytes: &'a [u8],
+    at: usize,
+    out: Bibliography,
+}
+impl<'a> Parser<'a> {
+    fn parse(&mut self) {
+        while let Some(start) = self.next_entry() {
+            self.at = start + 1;
+            let entry_type = self.identifier();
+            if entry_type.is_empty() {
+                self.out.errors.push((start..self.at, "Expected an entry type after @".to_string()));
+                continue;
+            }
+            self.skip_whitespace();
+            let close = match self.peek() {
+                Some(b'{') => b'}',
+                Some(b'(') => b')',
+                _ => {
+                    self.out.errors.push((start..self.at, format!("Expected {{ after @{}", entry_type)));
+                    continue;
+                },
+            };
+            self.at += 1;
+            match entry_type.to_lowercase().as_str() {
+                "comment" | "preamble" | "string" => {
+                    if self.skip_balanced(close).is_none() {
+                        self.out.errors.push((start..self.text.len(), format!("Unclosed @{}", entry_type)));
+                    }
+                    continue;
+                },
+                _ => {},
+            }
+            match self.entry(start, entry_type, close) {
+                Ok(c) => self.out.entries.push(c),
+                Err((range, message)) => {
+                    self.out.errors.push((range, message));
+                    // Continue at the next entry
+                    self.at = self.next_entry().unwrap_or(self.text.len());
+                },
+            }
+        }
+    }
+    // The next @ that comes first on its line, text between entries is a comment and may contain
+    // an @ like in "contact me@x.org"
+    fn next_entry(&self) -> Option<usize> {
+        let mut from = self.at;
+        while let Some(at) = self.text[from..].find('@').map(|c| c + from) {
+            let line_start = self.text[..at].rfind('\n').map_or(0, |c| c + 1);
+            if self.text[line_start..at].trim().is_empty() {
+                return Some(at);
+            }
+            from = at + 1;
+        }
+        None
+    }
+    fn entry(&mut self, start :usize, entry_type :String, close :u8) -> Result<Entry, (Range<usize>, String)> {
+        self.skip_whitespace();
+        let key_start = self.at;
+        while let Some(c) = self.peek() {
+            if c == b',' || c == close || c.is_ascii_whitespace() {
+                break;
+            }
+            self.at += 1;
+        }
+        let key = self.text[key_start..self.at].to_string();
+        if key.is_empty() {
+            return Err((start..self.at, format!("@{} entry without a key", entry_type)));
+        }
+        let key_range = key_start..self.at;
+        let mut fields :Vec<Field> = vec![];
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => {
+                    self.at += 1;
+                    continue;
+                },
+                Some(c) if c == close => {
+                    self.at += 1;
+                    break;
+                },
+                None => return Err((start..self.text.len(), format!("Entry {} is never closed", key))),
+                _ => {},
+            }
+            let name_start = self.at;
+            let name = self.identifier();
+            if name.is_empty() {
+                return Err((self.at..self.at+1, format!("Expected a field name in entry {}", key)));
+            }
+            let name_range = name_start..self.at;
+            self.skip_whitespace();
+            if self.peek() != Some(b'=') {
+                return Err((name_range, format!("Expected = after field {} in entry {}", name, key)));
+            }
+            self.at += 1;
+            self.skip_whitespace();
+            let value_start = self.at;
+            let value = self.value(&key)?;
+            fields.push(Field {
+                name: name.to_lowercase(),
+                value,
+                value_range: value_start..self.at,
+            });
+        }
+        Ok(Entry {
+            key,
+            key_range,
+            entry_type: entry_type.to_lowercase(),
+            range: start..self.at,
+            fields,
+        })
+    }
+    // A field value, `{..}`, `".."`, a number or a macro name, possibly concatenated with #
+    fn value(&mut self, key :&str) -> Result<String, (Range<usize>, String)> {
+        let mut out = String::new();
+        loop {
+            let start = self.at;
+            match self.peek() {
+                Some(b'{') => {
+                    self.at += 1;
+                    match self.skip_balanced(b'}') {
+                        Some(end) => out.push_str(&self.text[start+1..end]),
+                        None => return Err((start..self.text.len(), format!("Unclosed {{ in entry {}", key))),
+                    }
+                },
+                Some(b'"') => {
+                    self.at += 1;
+                    let mut depth = 0;
+                    loop {
+                        match self.peek() {
+                            Some(b'{') => depth += 1,
+                            Some(b'}') => depth -= 1,
+                            Some(b'"') if depth == 0 => break,
+                            None => return Err((start..self.text.len(), format!("Unclosed \" in entry {}", key))),
+                            _ => {},
+                        }
+                        self.at += 1;
+                    }
+                    out.push_str(&self.text[start+1..self.at]);
+                    self.at += 1;
+                },
+                _ => {
+                    let word = self.identifier();
+                    if word.is_empty() {
+                        return Err((start..start+1, format!("Expected a value in entry {}", key)));
+                    }
+                    out.push_str(&word);
+                },
+            }
+            self.skip_whitespace();
+            if self.peek() != Some(b'#') {
+                break;
+            }
+            self.at += 1;
+            self.skip_whitespace();
+        }
+        Ok(out.split_whitespace().collect::<Vec<&str>>().join(" "))
+    }
+    // Skips to after the matching close, returns the position of it
+    fn skip_balanced(&mut self, close :u8) -> Option<usize> {
+        let open = if close == b')' {b'('} else {b'{'};
+        let mut depth = 1;
+        while let Some(c) = self.peek() {
+            self.at += 1;
+            if c == open {
+                depth += 1;
+            } else if c == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(self.at-1);
+                }
+            }
+        }
+        None
+    }
+    fn identifier(&mut self) -> String {
+        let start = self.at;
+        while let Some(c) = self.peek() {
+            if !(c.is_ascii_alphanumeric() || matches!(c, b'_' | b'-' | b':' | b'.' | b'+' | b'/')) {
+                break;
+            }
+            self.at += 1;
+        }
+        self.text[start..self.at].to_string()
+    }
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if !c.is_ascii_whitespace() {
+                break;
+            }
+            self.at += 1;
+        }
+    }
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.at).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_with_fields() {
+        let text = "@Article{knuth84,\n  title = {Literate {P}rogramming},\n  year = 1984,\n}\n";
+        let bibliography = parse(text);
+        assert!(bibliography.errors.is_empty());
+        let entry = &bibliography.entries[0];
+        assert_eq!(entry.key, "knuth84");
+        assert_eq!(&text[entry.key_range.clone()], "knuth84");
+        assert_eq!(entry.entry_type, "article");
+        assert_eq!(entry.range, 0..text.len()-1);
+        let fields :Vec<(&str, &str)> = entry.fields.iter().map(|c| (c.name.as_str(), c.value.as_str())).collect();
+        assert_eq!(fields, vec![("title", "Literate {P}rogramming"), ("year", "1984")]);
+    }
+
+    #[test]
+    fn at_in_comment_text() {
+        let text = "Questions go to me@example.org\n@book{a, title = \"A\"}\n";
+        let bibliography = parse(text);
+        assert!(bibliography.errors.is_empty());
+        let keys :Vec<&str> = bibliography.entries.iter().map(|c| c.key.as_str()).collect();
+        assert_eq!(keys, vec!["a"]);
+    }
+
+    #[test]
+    fn concatenated_values() {
+        let bibliography = parse("@misc{a, note = \"one\" # { two }}");
+        assert_eq!(bibliography.entries[0].fields[0].value, "one two");
+    }
+
+    #[test]
+    fn recovers_after_errors() {
+        let text = "@book{a, title {A}}\n@book{b, title = {B}}\n@comment{x@y}\n";
+        let bibliography = parse(text);
+        assert_eq!(bibliography.errors.len(), 1);
+        let keys :Vec<&str> = bibliography.entries.iter().map(|c| c.key.as_str()).collect();
+        assert_eq!(keys, vec!["b"]);
+    }
+}
@@ -0,0 +1,137 @@
+use super::{Entry, Field, Bibliography};
+
+// A line based reader for Hayagriva YAML files. Top level keys are entries and the keys indented
+// below them are fields. Nested values, like `parent:` or block lists, are kept as one field whose
+// value is the nested lines joined by ", ".
+pub fn parse(text :&str) -> Bibliography {
+    let mut out = Bibliography {
+        entries: vec![],
+        errors: vec![],
+    };
+    let mut field_indent :Option<usize> = None;
+    let mut line_start = 0;
+    for line in text.split_inclusive('\n') {
+        let start = line_start;
+        line_start += line.len();
+        let content = line.trim_end();
+        let trimmed = content.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed == "---" {
+            continue;
+        }
+        let indent = content.len() - trimmed.len();
+        if indent == 0 {
+            field_indent = None;
+            let (key, rest) = match split_key(trimmed) {
+                Some(c) => c,
+                None => {
+                    out.errors.push((start..start+content.len(), "Expected an entry key followed by :".to_string()));
+                    continue;
+                },
+            };
+            if !rest.is_empty() {
+                out.errors.push((start..start+content.len(), format!("Entry {} must be a mapping of fields", key)));
+                continue;
+            }
+            out.entries.push(Entry {
+                key: key.to_string(),
+                key_range: start..start+key.len(),
+                entry_type: String::new(),
+                range: start..start+content.len(),
+                fields: vec![],
+            });
+            continue;
+        }
+        let entry = match out.entries.last_mut() {
+            Some(c) => c,
+            None => {
+                out.errors.push((start..start+content.len(), "Field outside of an entry".to_string()));
+                continue;
+            },
+        };
+        entry.range.end = start+content.len();
+        let field_indent = *field_indent.get_or_insert(indent);
+        if indent > field_indent {
+            // Part of the value of the last field
+            if let Some(field) = entry.fields.last_mut() {
+                let value = unquote(trimmed.trim_start_matches("- "));
+                if !field.value.is_empty() {
+                    field.value.push_str(", ");
+                }
+                field.value.push_str(&value);
+                field.value_range.end = start+content.len();
+            }
+            continue;
+        }
+        if indent < field_indent {
+            out.errors.push((start..start+content.len(), format!("Inconsistent indentation in entry {}", entry.key)));
+            continue;
+        }
+        let (name, rest) = match split_key(trimmed) {
+            Some(c) => c,
+            None => {
+                out.errors.push((start..start+content.len(), format!("Expected a field name followed by : in entry {}", entry.key)));
+                continue;
+            },
+        };
+        let value_start = start+content.len()-rest.len();
+        let value = unquote(rest.trim_start_matches('[').trim_end_matches(']'));
+        if name == "type" {
+            entry.entry_type = value.to_lowercase();
+        }
+        entry.fields.push(Field {
+            name: name.to_lowercase(),
+            value,
+            value_range: value_start..start+content.len(),
+        });
+    }
+    out
+}
+fn split_key(line :&str) -> Option<(&str, &str)> {
+    let colon = line.find(':')?;
+    let key = line[..colon].trim();
+    if key.is_empty() || key.contains(' ') {
+        return None;
+    }
+    Some((key, line[colon+1..].trim()))
+}
+fn unquote(value :&str) -> String {
+    value.split(',')
+        .map(|c| c.trim().trim_matches('"').trim_matches('\'').to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_and_fields() {
+        let text = "# Sources\nharry:\n  type: Book\n  title: \"Harry Potter\"\n  author: [Rowling, J. K.]\n";
+        let bibliography = parse(text);
+        assert!(bibliography.errors.is_empty());
+        let entry = &bibliography.entries[0];
+        assert_eq!(entry.key, "harry");
+        assert_eq!(&text[entry.key_range.clone()], "harry");
+        assert_eq!(entry.entry_type, "book");
+        let fields :Vec<(&str, &str)> = entry.fields.iter().map(|c| (c.name.as_str(), c.value.as_str())).collect();
+        assert_eq!(fields, vec![("type", "Book"), ("title", "Harry Potter"), ("author", "Rowling, J. K.")]);
+        assert_eq!(&text[entry.fields[1].value_range.clone()], "\"Harry Potter\"");
+    }
+
+    #[test]
+    fn nested_values() {
+        let text = "a:\n  parent:\n    title: Journal\n    volume: 3\n  page-range: 1-10\n";
+        let bibliography = parse(text);
+        let fields :Vec<(&str, &str)> = bibliography.entries[0].fields.iter().map(|c| (c.name.as_str(), c.value.as_str())).collect();
+        assert_eq!(fields, vec![("parent", "title: Journal, volume: 3"), ("page-range", "1-10")]);
+    }
+
+    #[test]
+    fn errors() {
+        let text = "  title: Orphan\nbad key: 1\nflat: value\n";
+        let bibliography = parse(text);
+        assert!(bibliography.entries.is_empty());
+        assert_eq!(bibliography.errors.len(), 3);
+    }
+}
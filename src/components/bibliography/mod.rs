@@ -0,0 +1,386 @@
+mod bibtex;
+mod hayagriva;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::sync::Arc;
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use tower_lsp::lsp_types::{
+    DiagnosticRelatedInformation,
+    DiagnosticSeverity,
+    DiagnosticTag,
+    Location,
+    NumberOrString,
+    Url,
+};
+use crate::components::{Checked, Checker, Diagnostic, QuickFix};
+use crate::parse;
+
+// Required fields of the standard BibTeX entry types, alternatives are separated by |
+const REQUIRED_FIELDS: &[(&str, &[&str])] = &[
+    ("article", &["author", "title", "journal", "year|date"]),
+    ("book", &["author|editor", "title", "publisher", "year|date"]),
+    ("booklet", &["title"]),
+    ("inbook", &["author|editor", "title", "chapter|pages", "publisher", "year|date"]),
+    ("incollection", &["author", "title", "booktitle", "publisher", "year|date"]),
+    ("inproceedings", &["author", "title", "booktitle", "year|date"]),
+    ("conference", &["author", "title", "booktitle", "year|date"]),
+    ("manual", &["title"]),
+    ("mastersthesis", &["author", "title", "school", "year|date"]),
+    ("phdthesis", &["author", "title", "school", "year|date"]),
+    ("proceedings", &["title", "year|date"]),
+    ("techreport", &["author", "title", "institution", "year|date"]),
+    ("unpublished", &["author", "title", "note"]),
+    ("online", &["author|editor", "title", "url|doi", "year|date"]),
+];
+// Hayagriva only needs a type and a title
+const HAYAGRIVA_REQUIRED_FIELDS: &[&str] = &["type", "title"];
+const SPELL_CHECKED_FIELDS: &[&str] = &["title", "abstract"];
+pub const NAME :&str = "bibliography";
+
+lazy_static! {
+    //The parsed bibliographies with the text they were parsed from, parsed again when it changes
+    static ref PARSED :DashMap<Url, (String, Arc<Bibliography>)> = DashMap::new();
+}
+
+#[derive(Clone)]
+pub struct BibliographyDiagnostic {
+    pub fixes: Vec<QuickFix>,
+}
+
+pub struct Bibliography {
+    pub entries: Vec<Entry>,
+    //Byte ranges in the bibliography file with a message about what is malformed
+    pub errors: Vec<(Range<usize>, String)>,
+}
+pub struct Entry {
+    pub key: String,
+    pub key_range: Range<usize>,
+    //Lowercase, e.g. "article". Empty if a Hayagriva entry has no type
+    pub entry_type: String,
+    pub range: Range<usize>,
+    pub fields: Vec<Field>,
+}
+pub struct Field {
+    //Lowercase
+    pub name: String,
+    pub value: String,
+    pub value_range: Range<usize>,
+}
+
+impl Bibliography {
+    pub fn parse(uri :&Url, text :&str) -> Self {
+        if is_hayagriva(uri) {
+            hayagriva::parse(text)
+        } else {
+            bibtex::parse(text)
+        }
+    }
+}
+impl Entry {
+    pub fn field(&self, name :&str) -> Option<&Field> {
+        self.fields.iter().find(|c| c.name == name)
+    }
+    pub fn title(&self) -> Option<&str> {
+        self.field("title").map(|c| c.value.trim_matches(|c| c == '{' || c == '}'))
+    }
+    pub fn authors(&self) -> Option<String> {
+        let authors = self.field("author")?;
+        // BibTeX separates names with "and", Hayagriva uses a list
+        Some(authors.value.split(" and ").collect::<Vec<&str>>().join("; "))
+    }
+    // "Title — Authors" for completion and hover details
+    pub fn describe(&self) -> String {
+        match (self.title(), self.authors()) {
+            (Some(t), Some(a)) => format!("{} — {}", t, a),
+            (Some(t), None) => t.to_string(),
+            (None, Some(a)) => a,
+            (None, None) => self.entry_type.clone(),
+        }
+    }
+}
+pub fn is_bibliography(uri :&Url) -> bool {
+    let path = uri.path();
+    path.ends_with(".bib") || is_hayagriva(uri)
+}
+fn is_hayagriva(uri :&Url) -> bool {
+    let path = uri.path();
+    path.ends_with(".yml") || path.ends_with(".yaml")
+}
+// The bibliography in the file, the open one or the one on disk. It is only parsed again when the
+// text changed.
+pub fn parsed(backend :&crate::Backend, uri :&Url) -> Option<Arc<Bibliography>> {
+    backend.with_document(uri, |document| {
+        let text = document.typst_source.text();
+        if let Some(c) = PARSED.get(uri) {
+            if c.0 == text {
+                return c.1.clone();
+            }
+        }
+        let bibliography = Arc::new(Bibliography::parse(uri, text));
+        PARSED.insert(uri.clone(), (text.to_string(), bibliography.clone()));
+        bibliography
+    })
+}
+
+pub struct BibliographyChecker;
+#[tower_lsp::async_trait]
+impl Checker for BibliographyChecker {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+    fn config_keys(&self) -> &'static [&'static str] {
+        &["bibliography"]
+    }
+    async fn check(&self, backend :&crate::Backend, uri :&Url, _range :Option<Range<usize>>) -> Checked {
+        check(backend, uri).await
+    }
+    fn quick_fixes(&self, diagnostic :&Diagnostic) -> Vec<QuickFix> {
+        diagnostic.data::<BibliographyDiagnostic>().map_or(vec![], |c| c.fixes.clone())
+    }
+}
+// Validates the bibliographies used by a typst document. Diagnostics are found for both the typst
+// document and the bibliography files, every touched file is among the checked files.
+pub async fn check(backend :&crate::Backend, uri :&Url) -> Checked {
+    let (enabled, unused_entries, spell_check) = {
+        let config = crate::settings::config_for(uri);
+        (config.bibliography.enabled, config.bibliography.unused_entries, config.bibliography.spell_check)
+    };
+    // A bibliography is checked through the documents that use it
+    if is_bibliography(uri) {
+        return Checked {
+            diagnostics: vec![],
+            files: vec![],
+        };
+    }
+    if !enabled {
+        return Checked::file(uri, vec![]);
+    }
+    let mut out :Vec<Diagnostic> = vec![];
+    let mut spelling_jobs :Vec<(Url, parse::Document, Arc<Bibliography>)> = vec![];
+    let mut touched :Vec<Url> = vec![uri.clone()];
+    let files = super::references::project_index(backend, uri);
+    // The document could not be read
+    if files.first().map(|c| &c.uri) != Some(uri) {
+        return Checked::file(uri, vec![]);
+    }
+    // The references of the document are checked against the keys by the references checker, a
+    // path that can't be read leaves them unresolved
+    let not_found :Vec<&(String, Range<usize>)> = files[0].bibliographies.iter()
+        .filter(|(path, _)| backend.resolve_path(uri, path).and_then(|c| parsed(backend, &c)).is_none())
+        .collect();
+    let missing = backend.with_document(uri, |document| {
+        not_found.iter()
+            .filter_map(|(path, range)| new_diagnostic(
+                document,
+                uri,
+                range.clone(),
+                format!("Bibliography file {} not found", path),
+                "bibliography-not-found",
+                DiagnosticSeverity::ERROR,
+                vec![],
+            ))
+            .collect::<Vec<Diagnostic>>()
+    });
+    out.extend(missing.unwrap_or_default());
+    let bibliography_uris = super::references::bibliography_uris(backend, &files);
+    let used :HashSet<&str> = files.iter()
+        .flat_map(|c| c.refs.iter())
+        .map(|c| c.name.as_str())
+        .collect();
+    let mut keys :HashMap<String, Location> = HashMap::new();
+    for bibliography_uri in bibliography_uris {
+        touched.push(bibliography_uri.clone());
+        // An open bibliography may differ from the one on disk
+        let (text, version) = match backend.with_document(&bibliography_uri, |c| (c.typst_source.text().to_string(), c.latest_version)) {
+            Some(c) => c,
+            None => continue,
+        };
+        let bibliography = match parsed(backend, &bibliography_uri) {
+            Some(c) => c,
+            None => continue,
+        };
+        let bibliography_document = parse::Document::new(version, &text);
+        out.extend(check_file(
+            &bibliography_document,
+            &bibliography_uri,
+            &bibliography,
+            &used,
+            &mut keys,
+            unused_entries,
+        ));
+        if spell_check {
+            spelling_jobs.push((bibliography_uri, bibliography_document, bibliography));
+        }
+    }
+    for (bibliography_uri, bibliography_document, bibliography) in spelling_jobs {
+        out.extend(spell_check_fields(&bibliography_document, &bibliography_uri, &bibliography).await);
+    }
+    Checked {
+        diagnostics: out,
+        files: touched,
+    }
+}
+fn check_file(
+    document :&parse::Document,
+    uri :&Url,
+    bibliography :&Bibliography,
+    used :&HashSet<&str>,
+    keys :&mut HashMap<String, Location>,
+    unused_entries :bool,
+    ) -> Vec<Diagnostic> {
+    let mut out = vec![];
+    for (range, message) in &bibliography.errors {
+        out.extend(new_diagnostic(document, uri, range.clone(), message.clone(), "malformed-entry", DiagnosticSeverity::ERROR, vec![]));
+    }
+    for entry in &bibliography.entries {
+        match keys.get(&entry.key) {
+            Some(first) => {
+                if let Some(mut c) = new_diagnostic(
+                    document,
+                    uri,
+                    entry.key_range.clone(),
+                    format!("Duplicate bibliography key {}", entry.key),
+                    "duplicate-key",
+                    DiagnosticSeverity::ERROR,
+                    vec![],
+                ) {
+                    c.diagnostics_lsp.related_information = Some(vec![DiagnosticRelatedInformation {
+                        location: first.clone(),
+                        message: format!("{} is first defined here", entry.key),
+                    }]);
+                    out.push(c);
+                }
+            },
+            None => {
+                if let Some(range) = document.byte_range_to_lsp_range(&entry.key_range) {
+                    keys.insert(entry.key.clone(), Location {
+                        uri: uri.clone(),
+                        range,
+                    });
+                }
+            },
+        }
+        let missing :Vec<&str> = required_fields(uri, &entry.entry_type).into_iter()
+            .filter(|required| !required.split('|').any(|c| entry.field(c).is_some()))
+            .collect();
+        if !missing.is_empty() {
+            let entry_type = if entry.entry_type.is_empty() {"entry".to_string()} else {entry.entry_type.clone()};
+            out.extend(new_diagnostic(
+                document,
+                uri,
+                entry.key_range.clone(),
+                format!("{} {} is missing required fields: {}", entry_type, entry.key, missing.join(", ").replace('|', " or ")),
+                "missing-field",
+                DiagnosticSeverity::WARNING,
+                vec![],
+            ));
+        }
+        if unused_entries && !used.contains(entry.key.as_str()) {
+            if let Some(mut c) = new_diagnostic(
+                document,
+                uri,
+                entry.key_range.clone(),
+                format!("{} is never cited", entry.key),
+                "unused-entry",
+                DiagnosticSeverity::HINT,
+                vec![],
+            ) {
+                c.diagnostics_lsp.tags = Some(vec![DiagnosticTag::UNNECESSARY]);
+                out.push(c);
+            }
+        }
+    }
+    out
+}
+fn required_fields(uri :&Url, entry_type :&str) -> Vec<&'static str> {
+    if is_hayagriva(uri) {
+        return HAYAGRIVA_REQUIRED_FIELDS.to_vec();
+    }
+    match REQUIRED_FIELDS.iter().find(|c| c.0 == entry_type) {
+        Some(c) => c.1.to_vec(),
+        None => vec![],
+    }
+}
+// Sends every title and abstract to language tool in one request and keeps the misspellings
+async fn spell_check_fields(document :&parse::Document, uri :&Url, bibliography :&Bibliography) -> Vec<Diagnostic> {
+    let text = document.typst_source.text();
+    // (character offset in the checked text, the field value range in the file)
+    let mut segments :Vec<(usize, Range<usize>)> = vec![];
+    let mut checked = String::new();
+    let mut checked_chars = 0;
+    for entry in &bibliography.entries {
+        for field in entry.fields.iter().filter(|c| SPELL_CHECKED_FIELDS.contains(&c.name.as_str())) {
+            let raw = match text.get(field.value_range.clone()) {
+                Some(c) => c,
+                None => continue,
+            };
+            segments.push((checked_chars, field.value_range.clone()));
+            // Braces and quotes are replaced with spaces to keep the offsets
+            let cleaned :String = raw.chars().map(|c| if matches!(c, '{' | '}' | '"') {' '} else {c}).collect();
+            checked_chars += cleaned.chars().count() + 2;
+            checked.push_str(&cleaned);
+            checked.push_str("\n\n");
+        }
+    }
+    if segments.is_empty() {
+        return vec![];
+    }
+    let matches = match super::language_tool::check_plain_text(checked).await {
+        Some(c) => c,
+        None => return vec![],
+    };
+    let mut out = vec![];
+    for m in matches {
+        if m.rule.issue_type != "misspelling" {
+            continue;
+        }
+        let (segment_start, value_range) = match segments.iter().rfind(|c| c.0 <= m.offset) {
+            Some(c) => c,
+            None => continue,
+        };
+        let raw = &text[value_range.clone()];
+        let char_to_byte = |chars :usize| raw.char_indices().nth(chars).map_or(raw.len(), |c| c.0);
+        let start = value_range.start + char_to_byte(m.offset - segment_start);
+        let end = value_range.start + char_to_byte(m.offset - segment_start + m.length);
+        if end > value_range.end {
+            continue;
+        }
+        let fixes :Vec<QuickFix> = m.replacements.iter()
+            .take(3)
+            .map(|c| (format!("Replace with {}", c.value), vec![(start..end, c.value.clone())]))
+            .collect();
+        out.extend(new_diagnostic(document, uri, start..end, m.message.clone(), "spelling", DiagnosticSeverity::INFORMATION, fixes));
+    }
+    out
+}
+fn new_diagnostic(
+    document :&parse::Document,
+    uri :&Url,
+    range :Range<usize>,
+    message :String,
+    code :&str,
+    severity :DiagnosticSeverity,
+    fixes :Vec<QuickFix>,
+    ) -> Option<Diagnostic> {
+    Diagnostic::new(
+        document,
+        uri,
+        range,
+        tower_lsp::lsp_types::Diagnostic {
+            range: Default::default(),
+            severity: Some(severity),
+            code: Some(NumberOrString::String(code.to_string())),
+            code_description: None,
+            source: Some("bibliography".to_string()),
+            message,
+            related_information: None,
+            tags: None,
+            data: None,
+        },
+        NAME,
+        BibliographyDiagnostic {
+            fixes,
+        },
+    )
+}
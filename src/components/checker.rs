@@ -0,0 +1,128 @@
+use std::ops::Range;
+use tower_lsp::lsp_types::{CodeActionOrCommand, ExecuteCommandParams, Url};
+use super::{Diagnostic, QuickFix, DIAGNOSTICS};
+use crate::config::RootConfig;
+use crate::parse;
+
+// What a check found, the diagnostics replace the ones the checker stored for `files` before
+pub struct Checked {
+    pub diagnostics: Vec<Diagnostic>,
+    //Every file that was checked, also those without diagnostics
+    pub files: Vec<Url>,
+}
+impl Checked {
+    pub fn file(uri :&Url, diagnostics :Vec<Diagnostic>) -> Self {
+        Checked {
+            diagnostics,
+            files: vec![uri.clone()],
+        }
+    }
+}
+
+// A source of diagnostics. The checkers are registered in `Checkers::builtin` and run by the
+// functions in components, which store and publish what they find.
+#[tower_lsp::async_trait]
+pub trait Checker :Send + Sync {
+    // The source of its diagnostics and its key in the `checkers` setting
+    fn name(&self) -> &'static str;
+    // The settings that change what the checker finds, the open files are checked again when
+    // one of them changes
+    fn config_keys(&self) -> &'static [&'static str] {
+        &[]
+    }
+    // Checkers cheap enough to run on every change
+    fn on_change(&self) -> bool {
+        false
+    }
+    // Checkers too slow to run on open and save, they run when asked with a code action or the
+    // workspace check
+    fn on_demand(&self) -> bool {
+        false
+    }
+    // Checks a file. `range` is the part the user asked about, the diagnostics outside it are kept.
+    // A checker that can only check whole files ignores it.
+    async fn check(&self, backend :&crate::Backend, uri :&Url, range :Option<Range<usize>>) -> Checked;
+    // The quick fixes of one of its diagnostics
+    fn quick_fixes(&self, _diagnostic :&Diagnostic) -> Vec<QuickFix> {
+        vec![]
+    }
+    // If its quick fixes may be applied without being looked at, by `source.fixAll` on save and
+    // the fix all actions. Fixes that delete or add markup are not.
+    fn fix_all(&self) -> bool {
+        false
+    }
+    // The code actions at `range`, by default the quick fixes of the diagnostics covering it
+    async fn code_actions(&self, _backend :&crate::Backend, document :&parse::Document, uri :&Url, range :&Range<usize>)
+        -> Vec<CodeActionOrCommand> {
+        super::quick_fix_actions(document, uri, range, self.name(), |c| self.quick_fixes(c))
+    }
+    // The commands `execute` handles, announced to the client
+    fn commands(&self) -> Vec<String> {
+        vec![]
+    }
+    // Runs a command, returns false if it is not one of the checker's
+    async fn execute(&self, _backend :&crate::Backend, _params :&ExecuteCommandParams) -> bool {
+        false
+    }
+    // Drops stored diagnostics the configuration no longer allows, without checking again
+    fn refilter(&self, _backend :&crate::Backend) {}
+}
+
+pub struct Checkers {
+    checkers: Vec<Box<dyn Checker>>,
+}
+impl Checkers {
+    pub fn new(checkers :Vec<Box<dyn Checker>>) -> Self {
+        Checkers {
+            checkers,
+        }
+    }
+    pub fn builtin() -> Self {
+        Self::new(vec![
+            Box::new(super::heading::HeadingChecker),
+            Box::new(super::references::ReferencesChecker),
+            Box::new(super::bibliography::BibliographyChecker),
+            Box::new(super::language_tool::LanguageToolChecker),
+            Box::new(super::external::ExternalChecker),
+        ])
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Checker> {
+        self.checkers.iter().map(|c| c.as_ref())
+    }
+    pub fn get(&self, name :&str) -> Option<&dyn Checker> {
+        self.iter().find(|c| c.name() == name)
+    }
+    pub fn commands(&self) -> Vec<String> {
+        self.iter().flat_map(|c| c.commands()).collect()
+    }
+}
+// If the `checkers` setting leaves the checker on
+pub fn is_enabled(name :&str, config :&RootConfig) -> bool {
+    config.checkers.get(name).copied().unwrap_or(true)
+}
+// Runs a checker on a file if it is enabled there and stores the result. Returns every file
+// whose diagnostics changed.
+pub async fn run(backend :&crate::Backend, checker :&dyn Checker, uri :&Url, range :Option<Range<usize>>) -> Vec<Url> {
+    let name = checker.name();
+    if !is_enabled(name, &crate::settings::config_for(uri)) {
+        DIAGNOSTICS.lock().unwrap().retain(|x| x.source != name || &x.uri != uri);
+        return vec![uri.clone()];
+    }
+    let checked = checker.check(backend, uri, range.clone()).await;
+    let mut diagnostics = DIAGNOSTICS.lock().unwrap();
+    diagnostics.retain(|x| {
+        if x.source != name || !checked.files.contains(&x.uri) {
+            return true;
+        }
+        // Outside the range that was checked, the stored range may be a few edits behind
+        match &range {
+            Some(c) if &x.uri == uri => x.range.end < c.start || x.range.start > c.end,
+            _ => false,
+        }
+    });
+    diagnostics.extend(checked.diagnostics.into_iter().filter(|x| match &range {
+        Some(c) if &x.uri == uri => x.range.start <= c.end && x.range.end >= c.start,
+        _ => true,
+    }));
+    checked.files
+}
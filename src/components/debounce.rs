@@ -0,0 +1,37 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+//How long to wait for more changes before the request is sent
+const DELAY :Duration = Duration::from_millis(100);
+
+// A refresh request to the client that changes close together share. It is sent once, a while
+// after the first of them.
+pub struct Debounced {
+    //A request is waiting to be sent
+    pending: AtomicBool,
+}
+
+impl Debounced {
+    pub fn new() -> Self {
+        Debounced {
+            pending: AtomicBool::new(false),
+        }
+    }
+    // Sends the request unless one is waiting already, `what` names it in the log when it fails
+    pub fn send<F>(&'static self, what :&'static str, request :F)
+    where
+        F :Future<Output = tower_lsp::jsonrpc::Result<()>> + Send + 'static,
+    {
+        if self.pending.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        tokio::spawn(async move {
+            tokio::time::sleep(DELAY).await;
+            self.pending.store(false, Ordering::Relaxed);
+            if let Err(e) = request.await {
+                log::warn!("{} refresh failed: {}", what, e);
+            }
+        });
+    }
+}
@@ -0,0 +1,203 @@
+use std::ops::Range;
+use std::process::Stdio;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tower_lsp::lsp_types::{DiagnosticSeverity, MessageType, NumberOrString, Url};
+use super::language_tool::{extract_prose, Prose, Segment};
+use super::{Checked, Checker, Diagnostic, QuickFix, DIAGNOSTICS};
+use crate::config::ExternalCheckerConfig;
+use crate::parse;
+
+pub const NAME :&str = "external";
+
+#[derive(Clone)]
+pub struct ExternalDiagnostic {
+    //The name of the linter that found it
+    pub tool: String,
+    pub replacements: Vec<String>,
+}
+#[derive(Serialize)]
+struct Request<'a> {
+    file: String,
+    text: &'a str,
+    segments: &'a [Segment],
+}
+#[derive(Deserialize)]
+struct Match {
+    offset: usize,
+    length: usize,
+    message: String,
+    #[serde(default)]
+    replacements: Vec<String>,
+    #[serde(default)]
+    rule_id: Option<String>,
+    #[serde(default)]
+    severity: Option<String>,
+}
+
+// Runs the linters of the `external` setting on the prose of a file
+pub struct ExternalChecker;
+#[tower_lsp::async_trait]
+impl Checker for ExternalChecker {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+    fn config_keys(&self) -> &'static [&'static str] {
+        &["external"]
+    }
+    async fn check(&self, backend :&crate::Backend, uri :&Url, _range :Option<Range<usize>>) -> Checked {
+        let tools = crate::settings::config_for(uri).external.clone();
+        let copy = backend.with_document(uri, |c| (c.latest_version, c.typst_source.text().to_string()));
+        let (version, text) = match copy {
+            Some(c) if !tools.is_empty() => c,
+            _ => return Checked::file(uri, vec![]),
+        };
+        let document = parse::Document::new(version, &text);
+        let prose = extract_prose(&text);
+        let mut out :Vec<Diagnostic> = vec![];
+        for tool in &tools {
+            match run(tool, uri, &prose).await {
+                Ok(matches) => {
+                    out.extend(matches.into_iter().filter_map(|c| to_diagnostic(&document, uri, &text, &prose, tool, c)));
+                },
+                Err(e) => {
+                    // A broken linter keeps its old diagnostics and does not stop the others
+                    let message = format!("{} failed on {}: {}", tool.name, uri.path(), e);
+                    super::check_failed();
+                    log::warn!("{}", message);
+                    backend.client.log_message(MessageType::WARNING, message).await;
+                    out.extend(DIAGNOSTICS.lock().unwrap().iter()
+                        .filter(|x| x.source == NAME && &x.uri == uri)
+                        .filter(|x| x.data::<ExternalDiagnostic>().is_some_and(|c| c.tool == tool.name))
+                        .cloned());
+                },
+            }
+        }
+        Checked::file(uri, out)
+    }
+    fn fix_all(&self) -> bool {
+        true
+    }
+    fn quick_fixes(&self, diagnostic :&Diagnostic) -> Vec<QuickFix> {
+        let data = match diagnostic.data::<ExternalDiagnostic>() {
+            Some(c) => c,
+            None => return vec![],
+        };
+        data.replacements.iter()
+            .map(|c| (format!("Replace with \"{}\"", c), vec![(diagnostic.range.clone(), c.clone())]))
+            .collect()
+    }
+}
+async fn run(tool :&ExternalCheckerConfig, uri :&Url, prose :&Prose) -> Result<Vec<Match>, String> {
+    let input = serde_json::to_vec(&Request {
+        file: uri.to_file_path().map_or(uri.to_string(), |c| c.to_string_lossy().to_string()),
+        text: &prose.text,
+        segments: &prose.segments,
+    }).map_err(|e| e.to_string())?;
+    let mut child = Command::new(&tool.command)
+        .args(&tool.args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("could not start {}: {}", tool.command, e))?;
+    let mut stdin = child.stdin.take().ok_or("no stdin")?;
+    // Written while the output is read, a linter that answers before reading everything would
+    // otherwise block on a full pipe
+    let writer = tokio::spawn(async move {
+        let _ = stdin.write_all(&input).await;
+    });
+    let output = match tokio::time::timeout(Duration::from_secs(tool.timeout_secs), child.wait_with_output()).await {
+        Ok(c) => c.map_err(|e| e.to_string())?,
+        // The child is killed when dropped
+        Err(_) => return Err(format!("no answer within {} seconds", tool.timeout_secs)),
+    };
+    writer.abort();
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("exited with {}: {}", output.status, stderr.trim()));
+    }
+    serde_json::from_slice(&output.stdout).map_err(|e| format!("bad output: {}", e))
+}
+fn to_diagnostic(
+    document :&parse::Document,
+    uri :&Url,
+    text :&str,
+    prose :&Prose,
+    tool :&ExternalCheckerConfig,
+    found :Match,
+    ) -> Option<Diagnostic> {
+    let start = prose.source_offset(found.offset, false);
+    let end = prose.source_offset(found.offset + found.length, true).max(start);
+    let range = char_to_byte(text, start)?..char_to_byte(text, end)?;
+    let severity = match found.severity.as_deref() {
+        Some("error") => DiagnosticSeverity::ERROR,
+        Some("info") | Some("information") => DiagnosticSeverity::INFORMATION,
+        Some("hint") => DiagnosticSeverity::HINT,
+        _ => DiagnosticSeverity::WARNING,
+    };
+    Diagnostic::new(
+        document,
+        uri,
+        range,
+        tower_lsp::lsp_types::Diagnostic {
+            range: Default::default(),
+            severity: Some(severity),
+            code: found.rule_id.map(NumberOrString::String),
+            code_description: None,
+            source: Some(tool.name.clone()),
+            message: found.message,
+            related_information: None,
+            tags: None,
+            data: None,
+        },
+        NAME,
+        ExternalDiagnostic {
+            tool: tool.name.clone(),
+            replacements: found.replacements,
+        },
+    )
+}
+fn char_to_byte(text :&str, offset :usize) -> Option<usize> {
+    if offset == text.chars().count() {
+        return Some(text.len());
+    }
+    text.char_indices().nth(offset).map(|c| c.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostic(text :&str, found :&str) -> Option<Diagnostic> {
+        let document = parse::Document::new(0, &text.to_string());
+        let uri = Url::parse("file:///tmp/test.typ").unwrap();
+        let tool = ExternalCheckerConfig { name: String::from("lint"), ..Default::default() };
+        let found :Match = serde_json::from_str(found).unwrap();
+        to_diagnostic(&document, &uri, text, &extract_prose(text), &tool, found)
+    }
+
+    #[test]
+    fn char_offsets_become_byte_ranges() {
+        let text = "Grüße, _schön_ @intro.";
+        // "schön" is at chars 7..12 of the prose "Grüße, schön X."
+        let found = diagnostic(text, r#"{"offset": 7, "length": 5, "message": "m"}"#).unwrap();
+        assert_eq!(&text[found.range.clone()], "schön");
+        assert_eq!(found.diagnostics_lsp.severity, Some(DiagnosticSeverity::WARNING));
+        assert_eq!(found.diagnostics_lsp.source.as_deref(), Some("lint"));
+        let found = diagnostic(text, r#"{"offset": 13, "length": 1, "message": "m", "severity": "hint"}"#)
+            .unwrap();
+        assert_eq!(&text[found.range.clone()], "@intro");
+        assert_eq!(found.diagnostics_lsp.severity, Some(DiagnosticSeverity::HINT));
+    }
+
+    #[test]
+    fn char_to_byte_offsets() {
+        assert_eq!(char_to_byte("aüb", 2), Some(3));
+        assert_eq!(char_to_byte("aüb", 3), Some(4));
+        assert_eq!(char_to_byte("aüb", 4), None);
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use tower_lsp::lsp_types::{
+    CodeAction,
+    CodeActionKind,
+    CodeActionOrCommand,
+    TextEdit,
+    Url,
+    WorkspaceEdit,
+};
+use super::{baseline, checker, Diagnostic, DIAGNOSTICS};
+use crate::parse;
+
+// The first fix of a diagnostic, with the ranges up to date with the document
+struct Fix {
+    rule: String,
+    edits: Vec<(Range<usize>, String)>,
+}
+
+// `source.fixAll` for the whole document and an action per rule of the diagnostics at `range`
+// that fixes every issue of that rule. Only the kinds in `only` are made when it is given.
+pub fn code_actions(
+    backend :&crate::Backend,
+    document :&parse::Document,
+    uri :&Url,
+    range :&Range<usize>,
+    only :Option<&Vec<CodeActionKind>>,
+    ) -> Vec<CodeActionOrCommand> {
+    let wants = |kind :&CodeActionKind| only.is_none_or(|c| c.iter().any(|x| kind.as_str().starts_with(x.as_str())));
+    let (fixes, hovering) = fixes(backend, document, uri, range);
+    let mut out = vec![];
+    if wants(&CodeActionKind::QUICKFIX) {
+        let mut rules :Vec<&String> = hovering.iter().collect();
+        rules.sort();
+        rules.dedup();
+        for rule in rules {
+            let of_rule :Vec<&Fix> = fixes.iter().filter(|c| &c.rule == rule).collect();
+            if of_rule.len() < 2 {
+                continue;
+            }
+            out.extend(action(
+                document,
+                uri,
+                format!("Apply all fixes for {} in document", rule),
+                CodeActionKind::QUICKFIX,
+                &of_rule,
+            ));
+        }
+    }
+    if wants(&CodeActionKind::SOURCE_FIX_ALL) {
+        let all :Vec<&Fix> = fixes.iter().collect();
+        out.extend(action(document, uri, "Fix all auto-fixable issues".to_string(), CodeActionKind::SOURCE_FIX_ALL, &all));
+    }
+    out
+}
+// The fixes of the shown diagnostics of the document and the rules of those covering `range`.
+// Only checkers that allow it take part, diagnostics with several fixes are left out unless
+// fix_all_ambiguous is set.
+fn fixes(backend :&crate::Backend, document :&parse::Document, uri :&Url, range :&Range<usize>) -> (Vec<Fix>, Vec<String>) {
+    let config = crate::settings::config_for(uri);
+    let diagnostics :Vec<Diagnostic> = DIAGNOSTICS.lock().unwrap().iter()
+        .filter(|x| &x.uri == uri && checker::is_enabled(x.source, &config))
+        .cloned()
+        .collect();
+    let mut fixes = vec![];
+    let mut hovering = vec![];
+    for diagnostic in diagnostics {
+        if baseline::is_baselined(document, &diagnostic) {
+            continue;
+        }
+        let corrected = match document.correct_range(diagnostic.version, diagnostic.range.clone()) {
+            Some(c) => c,
+            None => continue,
+        };
+        let found = match backend.checkers.get(diagnostic.source) {
+            Some(c) if c.fix_all() => c.quick_fixes(&diagnostic),
+            _ => continue,
+        };
+        if found.is_empty() || (found.len() > 1 && !config.fix_all_ambiguous) {
+            continue;
+        }
+        let (_, edits) = found.into_iter().next().unwrap();
+        let edits :Option<Vec<(Range<usize>, String)>> = edits.into_iter()
+            .map(|(r, text)| Some((document.correct_range(diagnostic.version, r)?, text)))
+            .collect();
+        let edits = match edits {
+            Some(c) if !c.is_empty() => c,
+            _ => continue,
+        };
+        let rule = baseline::rule_id(&diagnostic);
+        if corrected.start <= range.start && range.end <= corrected.end {
+            hovering.push(rule.clone());
+        }
+        fixes.push(Fix {
+            rule,
+            edits,
+        });
+    }
+    (fixes, hovering)
+}
+// One code action with the edits of the fixes, a fix that overlaps one before it is skipped
+fn action(document :&parse::Document, uri :&Url, title :String, kind :CodeActionKind, fixes :&[&Fix]) -> Option<CodeActionOrCommand> {
+    let mut sorted :Vec<&Fix> = fixes.to_vec();
+    sorted.sort_by_key(|c| c.edits.iter().map(|x| x.0.start).min());
+    let mut taken :Vec<Range<usize>> = vec![];
+    let mut edits :Vec<TextEdit> = vec![];
+    for fix in sorted {
+        let overlaps = fix.edits.iter()
+            .any(|(r, _)| taken.iter().any(|x| r.start < x.end && x.start < r.end || r == x));
+        if overlaps {
+            continue;
+        }
+        let converted :Option<Vec<TextEdit>> = fix.edits.iter()
+            .map(|(r, text)| Some(TextEdit {
+                range: document.byte_range_to_lsp_range(r)?,
+                new_text: text.clone(),
+            }))
+            .collect();
+        if let Some(c) = converted {
+            taken.extend(fix.edits.iter().map(|c| c.0.clone()));
+            edits.extend(c);
+        }
+    }
+    if edits.is_empty() {
+        return None;
+    }
+    let mut changes :HashMap<Url, Vec<TextEdit>> = HashMap::new();
+    changes.insert(uri.clone(), edits);
+    Some(CodeActionOrCommand::CodeAction(CodeAction {
+        title,
+        kind: Some(kind),
+        diagnostics: None,
+        edit: Some(WorkspaceEdit {
+            changes: Some(changes),
+            document_changes: None,
+            change_annotations: None,
+        }),
+        command: None,
+        is_preferred: None,
+        disabled: None,
+        data: None,
+    }))
+}
@@ -0,0 +1,162 @@
+use std::ops::Range;
+use tower_lsp::lsp_types::{FoldingRange, FoldingRangeKind};
+use typst_syntax::{LinkedNode, SyntaxKind};
+use super::heading;
+use crate::parse;
+
+//How much of a comment is shown when it is folded
+const PREVIEW_CHARS :usize = 40;
+
+// What can be folded: each heading section, runs of `#set` and `#show` rules, code blocks, raw
+// blocks, function calls over several lines and block comments
+pub fn folding_ranges(document :&parse::Document) -> Vec<FoldingRange> {
+    let mut out = vec![];
+    for (heading, section) in heading::sections(document) {
+        let title = match &heading.number {
+            Some(c) => format!("{} {}", c, heading.title),
+            None => heading.title.clone(),
+        };
+        out.extend(fold(document, section, false, None, format!("{} …", title)));
+    }
+    let root = LinkedNode::new(document.typst_source.root());
+    out.extend(preambles(document, &root));
+    walk(document, &root, &mut out);
+    // Clients keep one range per start line, the outer one is the one to keep
+    out.sort_by_key(|c| (c.start_line, std::cmp::Reverse(c.end_line)));
+    out.dedup_by_key(|c| c.start_line);
+    out
+}
+fn walk(document :&parse::Document, node :&LinkedNode, out :&mut Vec<FoldingRange>) {
+    match node.kind() {
+        SyntaxKind::CodeBlock => {
+            out.extend(fold(document, node.range(), true, None, "{ … }".to_string()));
+        },
+        SyntaxKind::FuncCall => {
+            let name = parse::func_call_name(node.get()).unwrap_or_default();
+            out.extend(fold(document, node.range(), true, None, format!("{}(…)", name)));
+        },
+        SyntaxKind::Raw => {
+            let raw = node.get().clone().into_text();
+            let lang :String = raw.trim_start_matches('`').chars().take_while(|c| c.is_alphanumeric()).collect();
+            let preview = if raw.starts_with("```") {format!("```{} … ```", lang)} else {"` … `".to_string()};
+            out.extend(fold(document, node.range(), true, None, preview));
+            return;
+        },
+        SyntaxKind::BlockComment => {
+            let comment = node.get().clone().into_text();
+            let words = comment.trim_start_matches("/*").trim_end_matches("*/").split_whitespace().collect::<Vec<&str>>().join(" ");
+            let mut preview :String = words.chars().take(PREVIEW_CHARS).collect();
+            if preview.len() < words.len() {
+                preview.push('…');
+            }
+            out.extend(fold(document, node.range(), false, Some(FoldingRangeKind::Comment), format!("/* {} */", preview)));
+            return;
+        },
+        SyntaxKind::LineComment => return,
+        _ => {},
+    }
+    for child in node.children() {
+        walk(document, &child, out);
+    }
+}
+// The runs of `#set` and `#show` rules at the top of the document or between its paragraphs.
+// Spaces, paragraph breaks and line comments between the rules do not end a run.
+fn preambles(document :&parse::Document, root :&LinkedNode) -> Vec<FoldingRange> {
+    let mut out = vec![];
+    let mut run :Option<(Range<usize>, usize)> = None;
+    for child in root.children() {
+        match child.kind() {
+            SyntaxKind::SetRule | SyntaxKind::ShowRule => {
+                run = match run {
+                    Some((c, count)) => Some((c.start..child.range().end, count + 1)),
+                    None => Some((child.range(), 1)),
+                };
+            },
+            SyntaxKind::Hash
+            | SyntaxKind::Space
+            | SyntaxKind::Parbreak
+            | SyntaxKind::LineComment => {},
+            _ => {
+                out.extend(run.take().and_then(|c| preamble(document, c)));
+            },
+        }
+    }
+    out.extend(run.and_then(|c| preamble(document, c)));
+    out
+}
+fn preamble(document :&parse::Document, (range, count) :(Range<usize>, usize)) -> Option<FoldingRange> {
+    if count < 2 {
+        return None;
+    }
+    fold(document, range, false, Some(FoldingRangeKind::Imports), format!("#set/#show … ({} rules)", count))
+}
+// A range folding the lines from the start of `range` to its end, None if it is on one line. The
+// line of a closing bracket stays visible when `keep_last` is set.
+fn fold(
+    document :&parse::Document,
+    range :Range<usize>,
+    keep_last :bool,
+    kind :Option<FoldingRangeKind>,
+    preview :String,
+    ) -> Option<FoldingRange> {
+    let (start_line, _) = document.range_to_line_character(range.start)?;
+    let (mut end_line, _) = document.range_to_line_character(range.end)?;
+    if keep_last && end_line > start_line + 1 {
+        end_line -= 1;
+    }
+    if end_line <= start_line {
+        return None;
+    }
+    Some(FoldingRange {
+        start_line: start_line as u32,
+        start_character: None,
+        end_line: end_line as u32,
+        end_character: None,
+        kind: Some(kind.unwrap_or(FoldingRangeKind::Region)),
+        collapsed_text: Some(preview),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(ranges :&[FoldingRange]) -> Vec<(u32, u32)> {
+        ranges.iter().map(|c| (c.start_line, c.end_line)).collect()
+    }
+
+    #[test]
+    fn rule_runs_split_by_text() {
+        let text = "#set page(width: 10cm)\n\
+            #set text(size: 11pt)\n\
+            // Headings\n\
+            #show heading: set text(blue)\n\
+            \n\
+            Some text.\n\
+            \n\
+            #set par(justify: true)\n\
+            #show link: underline\n\
+            More text.\n\
+            #set text(lang: \"de\")\n";
+        let document = parse::Document::new(0, &text.to_string());
+        let found = preambles(&document, &LinkedNode::new(document.typst_source.root()));
+        assert_eq!(lines(&found), vec![(0, 3), (7, 8)]);
+        assert_eq!(found[0].collapsed_text.as_deref(), Some("#set/#show … (3 rules)"));
+        assert_eq!(found[0].kind, Some(FoldingRangeKind::Imports));
+    }
+
+    #[test]
+    fn closing_line_stays_visible() {
+        let text = "#f(\n  a,\n  b,\n)\n#g(\n)\n";
+        let document = parse::Document::new(0, &text.to_string());
+        let call = 0..text.find(")\n#g").unwrap() + 1;
+        let short = text.find("#g").unwrap()..text.len() - 1;
+        let fold = |range :Range<usize>, keep_last :bool| fold(&document, range, keep_last, None, String::new());
+        assert_eq!(lines(&[fold(call.clone(), true).unwrap()]), vec![(0, 2)]);
+        assert_eq!(lines(&[fold(call, false).unwrap()]), vec![(0, 3)]);
+        // Two lines are folded whole, there is nothing to hide otherwise
+        assert_eq!(lines(&[fold(short, true).unwrap()]), vec![(4, 5)]);
+        assert!(fold(0..3, true).is_none());
+        assert_eq!(lines(&folding_ranges(&document)), vec![(0, 2), (4, 5)]);
+    }
+}
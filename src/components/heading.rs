@@ -0,0 +1,621 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use tower_lsp::lsp_types::{
+    DiagnosticRelatedInformation,
+    DiagnosticSeverity,
+    Location,
+    NumberOrString,
+    Url,
+};
+use typst_syntax::{LinkedNode, SyntaxKind, SyntaxNode};
+use crate::components::{Checked, Checker, Diagnostic, QuickFix};
+use crate::config::{HeadingCase, HeadingConfig};
+use crate::parse;
+
+// Words that stay lowercase in title case unless they are first or last
+const SMALL_WORDS: &[&str] = &[
+    "a", "an", "the", "and", "but", "or", "nor", "for", "so", "yet", "as", "at", "by", "in",
+    "of", "on", "per", "to", "up", "via", "vs", "from", "into", "with",
+];
+const TRAILING_PUNCTUATION: &[char] = &['.', ',', ':', ';'];
+pub const NAME :&str = "heading";
+
+#[derive(Clone)]
+pub enum HeadingIssue {
+    Case(HeadingCase),
+    TrailingPunctuation,
+    Duplicate,
+}
+#[derive(Clone)]
+pub struct HeadingDiagnostic {
+    pub issue: HeadingIssue,
+    //Byte ranges and their replacement, only touching the text nodes of the heading so inline
+    //markup is kept intact
+    pub edits: Vec<(Range<usize>, String)>,
+}
+pub struct HeadingInfo {
+    pub level: usize,
+    //The whole heading, marker included
+    pub range: Range<usize>,
+    //The heading body, without the marker
+    pub body_range: Range<usize>,
+    pub title: String,
+    //The number the heading gets from `#set heading(numbering: ..)`, None if unnumbered
+    pub number: Option<String>,
+    pub words: Vec<Word>,
+    pub text_nodes: Vec<Range<usize>>,
+}
+pub struct Word {
+    pub range: Range<usize>,
+    pub text: String,
+    //If the word follows a colon, it starts a new sentence in both cases
+    pub after_colon: bool,
+}
+
+pub fn check(document :&parse::Document, uri :&Url) -> Vec<Diagnostic> {
+    let config = crate::settings::config_for(uri);
+    if !config.heading.enabled {
+        return Vec::new();
+    }
+    let headings = collect_headings(document);
+    let mut out :Vec<Diagnostic> = vec![];
+    for heading in &headings {
+        out.extend(check_case(document, uri, heading, &config.heading));
+        if config.heading.trailing_punctuation {
+            out.extend(check_trailing_punctuation(document, uri, heading));
+        }
+    }
+    if config.heading.duplicates {
+        out.extend(check_duplicates(document, uri, &headings));
+    }
+    out
+}
+pub struct HeadingChecker;
+#[tower_lsp::async_trait]
+impl Checker for HeadingChecker {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+    fn config_keys(&self) -> &'static [&'static str] {
+        &["heading"]
+    }
+    fn on_change(&self) -> bool {
+        true
+    }
+    async fn check(&self, backend :&crate::Backend, uri :&Url, _range :Option<Range<usize>>) -> Checked {
+        if super::bibliography::is_bibliography(uri) {
+            return Checked::file(uri, vec![]);
+        }
+        Checked::file(uri, backend.with_document(uri, |c| check(c, uri)).unwrap_or_default())
+    }
+    fn quick_fixes(&self, diagnostic :&Diagnostic) -> Vec<QuickFix> {
+        let data = match diagnostic.data::<HeadingDiagnostic>() {
+            Some(c) => c,
+            None => return vec![],
+        };
+        let title = match data.issue {
+            HeadingIssue::Case(HeadingCase::Title) => "Rewrite heading in title case",
+            HeadingIssue::Case(_) => "Rewrite heading in sentence case",
+            HeadingIssue::TrailingPunctuation => "Remove trailing punctuation",
+            HeadingIssue::Duplicate => return vec![],
+        };
+        vec![(title.to_string(), data.edits.clone())]
+    }
+}
+// Walks the document in order and collects every markup heading, keeping track of the
+// numbering set by `#set heading(numbering: ..)` rules
+pub fn collect_headings(document :&parse::Document) -> Vec<HeadingInfo> {
+    let root = LinkedNode::new(document.typst_source.root());
+    let mut walker = HeadingWalker {
+        numbering: None,
+        counters: vec![],
+        headings: vec![],
+    };
+    walker.walk(&root);
+    walker.headings
+}
+// Each heading with its section, which runs until the next heading of the same or a higher level.
+// The blank lines before that heading are not part of it.
+pub fn sections(document :&parse::Document) -> Vec<(HeadingInfo, Range<usize>)> {
+    let text = document.typst_source.text();
+    let headings = collect_headings(document);
+    let ends :Vec<usize> = headings.iter().enumerate()
+        .map(|(i, c)| {
+            let end = headings[i + 1..].iter()
+                .find(|x| x.level <= c.level)
+                .map_or(text.len(), |x| x.range.start);
+            c.range.start + text[c.range.start..end].trim_end().len()
+        })
+        .collect();
+    headings.into_iter()
+        .zip(ends)
+        .map(|(c, end)| {
+            let start = c.range.start;
+            (c, start..end)
+        })
+        .collect()
+}
+struct HeadingWalker {
+    numbering: Option<String>,
+    counters: Vec<usize>,
+    headings: Vec<HeadingInfo>,
+}
+impl HeadingWalker {
+    fn walk(&mut self, node :&LinkedNode) {
+        match node.kind() {
+            SyntaxKind::SetRule => {
+                self.set_rule(node.get());
+                return;
+            },
+            SyntaxKind::Heading => {
+                self.heading(node);
+                return;
+            },
+            SyntaxKind::Raw
+            | SyntaxKind::Equation
+            | SyntaxKind::LineComment
+            | SyntaxKind::BlockComment => return,
+            _ => {},
+        }
+        for child in node.children() {
+            self.walk(&child);
+        }
+    }
+    fn set_rule(&mut self, node :&SyntaxNode) {
+        match node.children().find(|c| c.kind() == SyntaxKind::Ident) {
+            Some(c) if c.text() == "heading" => {},
+            _ => return,
+        }
+        let value = match parse::named_arg(node, "numbering") {
+            Some(c) => c,
+            None => return,
+        };
+        self.numbering = parse::str_value(value);
+    }
+    fn heading(&mut self, node :&LinkedNode) {
+        let level = node.children()
+            .find(|c| c.kind() == SyntaxKind::HeadingMarker)
+            .map(|c| c.get().text().chars().filter(|&c| c == '=').count())
+            .unwrap_or(1);
+        let body = match node.children().find(|c| c.kind() == SyntaxKind::Markup) {
+            Some(c) => c,
+            None => return,
+        };
+        self.counters.truncate(level);
+        while self.counters.len() < level {
+            self.counters.push(0);
+        }
+        self.counters[level-1] += 1;
+        let number = self.numbering.as_ref().map(|c| format_numbering(c, &self.counters));
+
+        let mut words = vec![];
+        let mut text_nodes = vec![];
+        let mut after_colon = false;
+        collect_words(&body, &mut words, &mut text_nodes, &mut after_colon);
+        self.headings.push(HeadingInfo {
+            level,
+            range: node.range(),
+            body_range: body.range(),
+            title: body.get().children()
+                .filter(|c| c.kind() != SyntaxKind::Label)
+                .map(|c| c.clone().into_text().to_string())
+                .collect::<String>()
+                .trim()
+                .to_string(),
+            number,
+            words,
+            text_nodes,
+        });
+    }
+}
+fn collect_words(node :&LinkedNode, words :&mut Vec<Word>, text_nodes :&mut Vec<Range<usize>>, after_colon :&mut bool) {
+    match node.kind() {
+        SyntaxKind::Text => {
+            text_nodes.push(node.range());
+            let text = node.get().text();
+            let mut word_start :Option<usize> = None;
+            for (i, c) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+                if is_word_char(c) {
+                    if word_start.is_none() {
+                        word_start = Some(i);
+                    }
+                    continue;
+                }
+                if let Some(start) = word_start.take() {
+                    let range = node.offset()+start..node.offset()+i;
+                    // The rest of a word that a smart quote joined, like the "t" of "Don't"
+                    match words.last_mut() {
+                        Some(c) if start == 0 && c.range.end == range.start && c.text.ends_with(['\'', '’']) => {
+                            c.range.end = range.end;
+                            c.text.push_str(&text[start..i]);
+                        },
+                        _ => {
+                            words.push(Word {
+                                range,
+                                text: text[start..i].to_string(),
+                                after_colon: *after_colon,
+                            });
+                            *after_colon = false;
+                        },
+                    }
+                }
+                if c == ':' {
+                    *after_colon = true;
+                }
+            }
+        },
+        // typst parses the apostrophe in "Don't" as a smart quote between two text nodes, between
+        // word characters it is part of the word
+        SyntaxKind::SmartQuote => {
+            let joins = node.get().text() == "'" && node.next_sibling().is_some_and(|c| {
+                c.kind() == SyntaxKind::Text && c.offset() == node.range().end && c.get().text().starts_with(is_word_char)
+            });
+            match words.last_mut() {
+                Some(c) if joins && c.range.end == node.offset() => {
+                    c.range.end = node.range().end;
+                    c.text.push_str(node.get().text());
+                    text_nodes.push(node.range());
+                },
+                _ => {},
+            }
+        },
+        SyntaxKind::Markup | SyntaxKind::Strong | SyntaxKind::Emph => {
+            for child in node.children() {
+                collect_words(&child, words, text_nodes, after_colon);
+            }
+        },
+        _ => {},
+    }
+}
+fn is_word_char(c :char) -> bool {
+    c.is_alphanumeric() || c == '\'' || c == '’' || c == '-'
+}
+// Formats a typst numbering pattern like "1.a)" for the given heading counters
+pub fn format_numbering(pattern :&str, counters :&[usize]) -> String {
+    let mut symbols :Vec<(String, char)> = vec![];
+    let mut prefix = String::new();
+    for c in pattern.chars() {
+        if matches!(c, '1' | 'a' | 'A' | 'i' | 'I') {
+            symbols.push((prefix.clone(), c));
+            prefix.clear();
+        } else {
+            prefix.push(c);
+        }
+    }
+    let suffix = prefix;
+    if symbols.is_empty() {
+        return pattern.to_string();
+    }
+    let mut out = String::new();
+    for (i, counter) in counters.iter().enumerate() {
+        let (prefix, symbol) = if i < symbols.len() {
+            symbols[i].clone()
+        } else {
+            let last = symbols.last().unwrap().clone();
+            (if symbols.len() > 1 {last.0} else {".".to_string()}, last.1)
+        };
+        out.push_str(&prefix);
+        out.push_str(&format_counter(*counter, symbol));
+    }
+    out.push_str(&suffix);
+    out
+}
+fn format_counter(counter :usize, symbol :char) -> String {
+    match symbol {
+        'a' | 'A' => {
+            let mut n = counter;
+            let mut letters = String::new();
+            while n > 0 {
+                n -= 1;
+                letters.insert(0, (b'a' + (n % 26) as u8) as char);
+                n /= 26;
+            }
+            if symbol == 'A' {letters.to_uppercase()} else {letters}
+        },
+        'i' | 'I' => {
+            let mut n = counter;
+            let mut roman = String::new();
+            for (value, numeral) in [(1000, "m"), (900, "cm"), (500, "d"), (400, "cd"), (100, "c"), (90, "xc"),
+                (50, "l"), (40, "xl"), (10, "x"), (9, "ix"), (5, "v"), (4, "iv"), (1, "i")] {
+                while n >= value {
+                    roman.push_str(numeral);
+                    n -= value;
+                }
+            }
+            if symbol == 'I' {roman.to_uppercase()} else {roman}
+        },
+        _ => counter.to_string(),
+    }
+}
+fn heading_name(heading :&HeadingInfo) -> String {
+    match &heading.number {
+        Some(c) => format!("Heading {}", c.trim_end_matches(|c :char| !c.is_alphanumeric())),
+        None => format!("Level {} heading", heading.level),
+    }
+}
+fn check_case(document :&parse::Document, uri :&Url, heading :&HeadingInfo, config :&HeadingConfig) -> Option<Diagnostic> {
+    let case = config.case_for_level(heading.level);
+    if case == HeadingCase::Ignore || heading.words.is_empty() {
+        return None;
+    }
+    let mut changed_words :Vec<(Range<usize>, String)> = vec![];
+    let last = heading.words.len()-1;
+    for (i, word) in heading.words.iter().enumerate() {
+        let expected = match case {
+            HeadingCase::Title => title_case_word(&word.text, i == 0 || i == last || word.after_colon),
+            _ => sentence_case_word(&word.text, i == 0 || word.after_colon, &config.proper_nouns),
+        };
+        if expected != word.text {
+            changed_words.push((word.range.clone(), expected));
+        }
+    }
+    if changed_words.is_empty() {
+        return None;
+    }
+    let edits = text_node_edits(document, &heading.text_nodes, &changed_words);
+    let expected_title = apply_edits(
+        document.get_chunk_by_range(heading.body_range.clone()).unwrap_or_default(),
+        heading.body_range.start,
+        &changed_words
+    );
+    let case_name = if case == HeadingCase::Title {"title case"} else {"sentence case"};
+    Diagnostic::new(
+        document,
+        uri,
+        heading.body_range.clone(),
+        lsp_diagnostic(
+            format!("{} should be in {}: \"{}\"", heading_name(heading), case_name, expected_title.trim()),
+            "heading-case",
+        ),
+        NAME,
+        HeadingDiagnostic {
+            issue: HeadingIssue::Case(case),
+            edits,
+        },
+    )
+}
+fn check_trailing_punctuation(document :&parse::Document, uri :&Url, heading :&HeadingInfo) -> Option<Diagnostic> {
+    let last_text = match heading.text_nodes.last() {
+        Some(c) => c.clone(),
+        None => return None,
+    };
+    // Anything but whitespace and labels after the last text node means the heading ends in markup
+    let after = document.get_chunk_by_range(last_text.end..heading.body_range.end).unwrap_or_default();
+    let after = after.trim();
+    let is_label = after.starts_with('<') && after.ends_with('>');
+    if !after.is_empty() && !is_label {
+        return None;
+    }
+    let text = document.get_chunk_by_range(last_text.clone())?;
+    let trimmed = text.trim_end();
+    let punctuation = match trimmed.chars().last() {
+        Some(c) if TRAILING_PUNCTUATION.contains(&c) => c,
+        _ => return None,
+    };
+    let without = trimmed.trim_end_matches(TRAILING_PUNCTUATION);
+    let range = last_text.start+without.len()..last_text.start+trimmed.len();
+    Diagnostic::new(
+        document,
+        uri,
+        range.clone(),
+        lsp_diagnostic(
+            format!("{} ends with \"{}\", headings should not have trailing punctuation", heading_name(heading), punctuation),
+            "heading-trailing-punctuation",
+        ),
+        NAME,
+        HeadingDiagnostic {
+            issue: HeadingIssue::TrailingPunctuation,
+            edits: vec![(range, String::new())],
+        },
+    )
+}
+// Flags headings with the same title as an earlier heading under the same parent
+fn check_duplicates(document :&parse::Document, uri :&Url, headings :&[HeadingInfo]) -> Vec<Diagnostic> {
+    let mut out = vec![];
+    let mut seen :HashMap<(Option<usize>, String), usize> = HashMap::new();
+    let mut parents :Vec<usize> = vec![];
+    for (i, heading) in headings.iter().enumerate() {
+        while let Some(&p) = parents.last() {
+            if headings[p].level < heading.level {
+                break;
+            }
+            parents.pop();
+        }
+        let key = (parents.last().copied(), normalize_title(&heading.title));
+        parents.push(i);
+        if key.1.is_empty() {
+            continue;
+        }
+        let first = match seen.get(&key) {
+            Some(c) => &headings[*c],
+            None => {
+                seen.insert(key, i);
+                continue;
+            },
+        };
+        let mut lsp = lsp_diagnostic(
+            format!("Duplicate heading title \"{}\"", heading.title),
+            "heading-duplicate",
+        );
+        if let Some(first_range) = document.byte_range_to_lsp_range(&first.range) {
+            lsp.related_information = Some(vec![DiagnosticRelatedInformation {
+                location: Location {
+                    uri: uri.clone(),
+                    range: first_range,
+                },
+                message: "First heading with this title".to_string(),
+            }]);
+        }
+        if let Some(c) = Diagnostic::new(
+            document,
+            uri,
+            heading.body_range.clone(),
+            lsp,
+            NAME,
+            HeadingDiagnostic {
+                issue: HeadingIssue::Duplicate,
+                edits: vec![],
+            },
+        ) {
+            out.push(c);
+        }
+    }
+    out
+}
+fn lsp_diagnostic(message :String, code :&str) -> tower_lsp::lsp_types::Diagnostic {
+    tower_lsp::lsp_types::Diagnostic {
+        range: Default::default(),
+        severity: Some(DiagnosticSeverity::WARNING),
+        code: Some(NumberOrString::String(code.to_string())),
+        code_description: None,
+        source: Some("heading".to_string()),
+        message,
+        related_information: None,
+        tags: None,
+        data: None,
+    }
+}
+fn normalize_title(title :&str) -> String {
+    title.split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
+// Words like "LaTeX", "NASA" or "iPhone" are left as written in both cases
+fn has_inner_capital(word :&str) -> bool {
+    word.chars().skip(1).any(|c| c.is_uppercase())
+}
+fn capitalize(word :&str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().collect::<String>() + chars.as_str(),
+        None => String::new(),
+    }
+}
+fn uncapitalize(word :&str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(c) => c.to_lowercase().collect::<String>() + chars.as_str(),
+        None => String::new(),
+    }
+}
+fn title_case_word(word :&str, is_edge :bool) -> String {
+    if has_inner_capital(word) || word.chars().any(|c| c.is_numeric()) {
+        return word.to_string();
+    }
+    if !is_edge && SMALL_WORDS.contains(&word.to_lowercase().as_str()) {
+        return word.to_lowercase();
+    }
+    // The small words after a hyphen stay lowercase, like in "State-of-the-Art"
+    word.split('-')
+        .enumerate()
+        .map(|(i, c)| if i > 0 && SMALL_WORDS.contains(&c.to_lowercase().as_str()) {c.to_lowercase()} else {capitalize(c)})
+        .collect::<Vec<String>>()
+        .join("-")
+}
+fn sentence_case_word(word :&str, is_first :bool, proper_nouns :&[String]) -> String {
+    if let Some(c) = proper_nouns.iter().find(|c| c.to_lowercase() == word.to_lowercase()) {
+        return c.clone();
+    }
+    if has_inner_capital(word) || word == "I" || word.starts_with("I'") || word.starts_with("I’") {
+        return word.to_string();
+    }
+    if is_first {
+        return capitalize(word);
+    }
+    uncapitalize(word)
+}
+// Turns word replacements into replacements of whole text nodes. Nodes next to each other are
+// replaced together since a word joined over a smart quote spans them.
+fn text_node_edits(document :&parse::Document, text_nodes :&[Range<usize>], words :&[(Range<usize>, String)]) -> Vec<(Range<usize>, String)> {
+    let mut runs :Vec<Range<usize>> = vec![];
+    for node in text_nodes {
+        match runs.last_mut() {
+            Some(c) if c.end == node.start => c.end = node.end,
+            _ => runs.push(node.clone()),
+        }
+    }
+    let mut out = vec![];
+    for node in &runs {
+        let in_node :Vec<(Range<usize>, String)> = words.iter()
+            .filter(|(r, _)| node.start <= r.start && r.end <= node.end)
+            .cloned()
+            .collect();
+        if in_node.is_empty() {
+            continue;
+        }
+        let text = document.get_chunk_by_range(node.clone()).unwrap_or_default();
+        out.push((node.clone(), apply_edits(text, node.start, &in_node)));
+    }
+    out
+}
+// Applies sorted, non overlapping byte range edits to text starting at byte `base`
+fn apply_edits(mut text :String, base :usize, edits :&[(Range<usize>, String)]) -> String {
+    for (range, new_text) in edits.iter().rev() {
+        if range.start < base || range.end - base > text.len() {
+            continue;
+        }
+        text.replace_range(range.start-base..range.end-base, new_text);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headings(text :&str) -> Vec<HeadingInfo> {
+        collect_headings(&parse::Document::new(0, &text.to_string()))
+    }
+
+    #[test]
+    fn numbering_patterns() {
+        assert_eq!(format_numbering("1.", &[3]), "3.");
+        assert_eq!(format_numbering("1.a)", &[1, 2]), "1.b)");
+        assert_eq!(format_numbering("I.1", &[4, 2]), "IV.2");
+        assert_eq!(format_numbering("1", &[2, 1, 5]), "2.1.5");
+        assert_eq!(format_numbering("a", &[27]), "aa");
+    }
+
+    #[test]
+    fn numbering_from_set_rule() {
+        let headings = headings("#set heading(numbering: \"1.a)\")\n= One\n== Two\n== Three\n");
+        let numbers :Vec<Option<String>> = headings.iter().map(|c| c.number.clone()).collect();
+        assert_eq!(numbers, vec![Some("1)".to_string()), Some("1.a)".to_string()), Some("1.b)".to_string())]);
+    }
+
+    #[test]
+    fn apostrophes_stay_in_words() {
+        let headings = headings("= don't stop rock'n'roll\n");
+        let words :Vec<&str> = headings[0].words.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(words, vec!["don't", "stop", "rock'n'roll"]);
+        assert_eq!(title_case_word("don't", true), "Don't");
+        assert_eq!(sentence_case_word("Don't", false, &[]), "don't");
+        assert_eq!(sentence_case_word("I'm", false, &[]), "I'm");
+    }
+
+    #[test]
+    fn title_case_words() {
+        assert_eq!(title_case_word("of", false), "of");
+        assert_eq!(title_case_word("of", true), "Of");
+        assert_eq!(title_case_word("LaTeX", false), "LaTeX");
+        assert_eq!(title_case_word("state-of-the-art", false), "State-of-the-Art");
+        assert_eq!(title_case_word("of-the-shelf", true), "Of-the-Shelf");
+        assert_eq!(title_case_word("well-known", false), "Well-Known");
+    }
+
+    #[test]
+    fn case_edits_keep_markup() {
+        let document = parse::Document::new(0, &"= the *big* dog\n".to_string());
+        let headings = collect_headings(&document);
+        let words :Vec<(Range<usize>, String)> = headings[0].words.iter()
+            .map(|c| (c.range.clone(), title_case_word(&c.text, true)))
+            .collect();
+        let edits = text_node_edits(&document, &headings[0].text_nodes, &words);
+        let mut text = document.typst_source.text().to_string();
+        for (range, new_text) in edits.iter().rev() {
+            text.replace_range(range.clone(), new_text);
+        }
+        assert_eq!(text, "= The *Big* Dog\n");
+    }
+}
@@ -0,0 +1,390 @@
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::{
+    ClientCapabilities,
+    InlayHint,
+    InlayHintLabel,
+    InlayHintTooltip,
+    MarkupContent,
+    MarkupKind,
+    Url,
+};
+use typst_syntax::{LinkedNode, SyntaxKind};
+use super::debounce::Debounced;
+use super::language_tool::{self, LTDiagnostic};
+use super::{baseline, checker, heading, DIAGNOSTICS};
+use crate::parse;
+
+lazy_static! {
+    //If tooltips are left out, to be made in inlayHint/resolve when the client shows them
+    static ref RESOLVE_TOOLTIPS :AtomicBool = AtomicBool::new(false);
+    //If the client can be asked for the hints again when the diagnostics change
+    static ref REFRESH :AtomicBool = AtomicBool::new(false);
+    static ref REFRESH_REQUEST :Debounced = Debounced::new();
+}
+
+// What inlayHint/resolve needs to make the tooltip
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum HintData {
+    //The language tool diagnostic is found again by its range
+    Suggestion {
+        uri: Url,
+        version: isize,
+        range: Range<usize>,
+    },
+    WordCount {
+        words: usize,
+        sentences: usize,
+    },
+    Readability {
+        words: usize,
+        sentences: usize,
+        syllables: usize,
+    },
+}
+// A piece of prose, text or what separates it
+struct Piece {
+    range: Range<usize>,
+    text: String,
+    //Text nodes, the others are spaces, quotes and paragraph breaks
+    is_text: bool,
+}
+struct Counts {
+    words: usize,
+    sentences: usize,
+    syllables: usize,
+}
+
+pub fn set_support(capabilities :&ClientCapabilities) {
+    let resolve = capabilities.text_document.as_ref()
+        .and_then(|c| c.inlay_hint.as_ref())
+        .and_then(|c| c.resolve_support.as_ref())
+        .is_some_and(|c| c.properties.iter().any(|x| x == "tooltip"));
+    let refresh = capabilities.workspace.as_ref()
+        .and_then(|c| c.inlay_hint.as_ref())
+        .and_then(|c| c.refresh_support)
+        .unwrap_or(false);
+    RESOLVE_TOOLTIPS.store(resolve, Ordering::Relaxed);
+    REFRESH.store(refresh, Ordering::Relaxed);
+}
+// Asks the client for the hints again, the suggestions follow the language tool diagnostics.
+// Changes close together are sent as one refresh.
+pub fn refresh(backend :&crate::Backend) {
+    if !REFRESH.load(Ordering::Relaxed) {
+        return;
+    }
+    let client = backend.client.clone();
+    REFRESH_REQUEST.send("Inlay hint", async move {client.inlay_hint_refresh().await});
+}
+// The hints in `range` of the kinds turned on in `inlay_hints`
+pub fn hints(document :&parse::Document, uri :&Url, range :&Range<usize>) -> Vec<InlayHint> {
+    let config = crate::settings::config_for(uri);
+    let mut out :Vec<(usize, String, HintData)> = vec![];
+    if config.inlay_hints.suggestions && checker::is_enabled(language_tool::NAME, &config) {
+        out.extend(suggestions(document, uri));
+    }
+    if config.inlay_hints.word_counts || config.inlay_hints.readability {
+        let pieces = pieces(document);
+        if config.inlay_hints.word_counts {
+            out.extend(word_counts(&pieces));
+        }
+        if config.inlay_hints.readability {
+            out.extend(readability(document, &pieces));
+        }
+    }
+    let lazy = RESOLVE_TOOLTIPS.load(Ordering::Relaxed);
+    out.into_iter()
+        .filter(|c| range.start <= c.0 && c.0 <= range.end)
+        .filter_map(|(offset, label, data)| {
+            let position = document.byte_range_to_lsp_range(&(offset..offset))?.start;
+            let (tooltip, data) = if lazy {
+                (None, serde_json::to_value(data).ok())
+            } else {
+                (tooltip(Some(document), &data), None)
+            };
+            Some(InlayHint {
+                position,
+                label: InlayHintLabel::String(label),
+                kind: None,
+                text_edits: None,
+                tooltip,
+                padding_left: Some(true),
+                padding_right: None,
+                data,
+            })
+        })
+        .collect()
+}
+// Fills in the tooltip of a hint handed out without one
+pub fn resolve(backend :&crate::Backend, mut hint :InlayHint) -> InlayHint {
+    let data :HintData = match hint.data.clone().and_then(|c| serde_json::from_value(c).ok()) {
+        Some(c) => c,
+        None => return hint,
+    };
+    hint.tooltip = match &data {
+        HintData::Suggestion { uri, .. } => backend.with_document(uri, |c| tooltip(Some(c), &data)).flatten(),
+        _ => tooltip(None, &data),
+    };
+    hint
+}
+// The tooltip of a hint, `document` is the one a suggestion is in
+fn tooltip(document :Option<&parse::Document>, data :&HintData) -> Option<InlayHintTooltip> {
+    let text = match data {
+        HintData::Suggestion { uri, version, range } => {
+            let document = document?;
+            let range = document.correct_range(*version, range.clone())?;
+            let diagnostics = DIAGNOSTICS.lock().unwrap();
+            let found = diagnostics.iter()
+                .filter(|x| x.source == language_tool::NAME && &x.uri == uri)
+                .find(|x| document.correct_range(x.version, x.range.clone()) == Some(range.clone()))?;
+            let lt = found.data::<LTDiagnostic>()?;
+            let mut text = format!("**{}**\n\n{}", lt.rule.description, found.diagnostics_lsp.message);
+            if lt.replacements.len() > 1 {
+                let others :Vec<&str> = lt.replacements[1..].iter().map(|c| c.value.as_str()).collect();
+                text.push_str(&format!("\n\nAlso: {}", others.join(", ")));
+            }
+            text
+        },
+        HintData::WordCount { words, sentences } => {
+            format!("{} words in {} sentences", words, sentences)
+        },
+        HintData::Readability { words, sentences, syllables } => {
+            let score = flesch(&Counts { words: *words, sentences: *sentences, syllables: *syllables });
+            format!(
+                "Flesch reading ease {:.0}, {}\n\n{} words, {:.1} words per sentence, {:.2} syllables per word",
+                score,
+                difficulty(score),
+                words,
+                *words as f64 / *sentences as f64,
+                *syllables as f64 / *words as f64,
+            )
+        },
+    };
+    Some(InlayHintTooltip::MarkupContent(MarkupContent {
+        kind: MarkupKind::Markdown,
+        value: text,
+    }))
+}
+// The first replacement of each language tool diagnostic, after the text it replaces
+fn suggestions(document :&parse::Document, uri :&Url) -> Vec<(usize, String, HintData)> {
+    let diagnostics = DIAGNOSTICS.lock().unwrap();
+    diagnostics.iter()
+        .filter(|x| x.source == language_tool::NAME && &x.uri == uri)
+        .filter(|x| !baseline::is_baselined(document, x))
+        .filter_map(|x| {
+            let replacement = x.data::<LTDiagnostic>()?.replacements.first()?;
+            let range = document.correct_range(x.version, x.range.clone())?;
+            let data = HintData::Suggestion {
+                uri: uri.clone(),
+                version: document.latest_version,
+                range: range.clone(),
+            };
+            Some((range.end, format!("→ {}", replacement.value), data))
+        })
+        .collect()
+}
+// The word count of each paragraph, after its last word
+fn word_counts(pieces :&[(usize, Piece)]) -> Vec<(usize, String, HintData)> {
+    let mut out = vec![];
+    let mut start = 0;
+    while start < pieces.len() {
+        let paragraph = pieces[start].0;
+        let end = start + pieces[start..].iter().take_while(|c| c.0 == paragraph).count();
+        let part :Vec<&Piece> = pieces[start..end].iter().map(|c| &c.1).collect();
+        let counts = count(&part);
+        let last = part.iter().rev().find(|c| c.is_text).map(|c| c.range.end);
+        if let (Some(offset), true) = (last, counts.words > 0) {
+            let label = if counts.words == 1 {"1 word".to_string()} else {format!("{} words", counts.words)};
+            out.push((offset, label, HintData::WordCount {
+                words: counts.words,
+                sentences: counts.sentences,
+            }));
+        }
+        start = end;
+    }
+    out
+}
+// The reading ease of each heading section, subsections included, shown after the heading
+fn readability(document :&parse::Document, pieces :&[(usize, Piece)]) -> Vec<(usize, String, HintData)> {
+    let mut out = vec![];
+    for (heading, section) in heading::sections(document) {
+        let section :Vec<&Piece> = pieces.iter()
+            .map(|c| &c.1)
+            .filter(|c| c.range.start >= heading.range.end && c.range.end <= section.end)
+            .collect();
+        let counts = count(&section);
+        if counts.words == 0 {
+            continue;
+        }
+        let score = flesch(&counts);
+        out.push((heading.range.end, format!("readability {:.0}", score), HintData::Readability {
+            words: counts.words,
+            sentences: counts.sentences,
+            syllables: counts.syllables,
+        }));
+    }
+    out
+}
+// The number of words of the prose in each range
+pub fn words_in(document :&parse::Document, ranges :&[Range<usize>]) -> Vec<usize> {
+    let pieces = pieces(document);
+    ranges.iter()
+        .map(|range| {
+            let part :Vec<&Piece> = pieces.iter()
+                .map(|c| &c.1)
+                .filter(|c| range.start <= c.range.start && c.range.end <= range.end)
+                .collect();
+            count(&part).words
+        })
+        .collect()
+}
+// The prose of the document with the paragraph each piece is in. Headings end a paragraph and
+// are left out, like code, math and raw text.
+fn pieces(document :&parse::Document) -> Vec<(usize, Piece)> {
+    let mut out = vec![];
+    let mut paragraph = 0;
+    walk(&LinkedNode::new(document.typst_source.root()), &mut paragraph, &mut out);
+    out
+}
+fn walk(node :&LinkedNode, paragraph :&mut usize, out :&mut Vec<(usize, Piece)>) {
+    let piece = |text :&str, is_text :bool| Piece {
+        range: node.range(),
+        text: text.to_string(),
+        is_text,
+    };
+    match node.kind() {
+        SyntaxKind::Text => {
+            out.push((*paragraph, piece(node.text(), true)));
+            return;
+        },
+        SyntaxKind::Space => {
+            out.push((*paragraph, piece(" ", false)));
+            return;
+        },
+        SyntaxKind::SmartQuote => {
+            out.push((*paragraph, piece(node.text(), false)));
+            return;
+        },
+        SyntaxKind::Parbreak => {
+            out.push((*paragraph, piece(" ", false)));
+            *paragraph += 1;
+            return;
+        },
+        SyntaxKind::Heading => {
+            *paragraph += 1;
+            return;
+        },
+        SyntaxKind::Raw
+        | SyntaxKind::Equation
+        | SyntaxKind::LineComment
+        | SyntaxKind::BlockComment => return,
+        _ => {},
+    }
+    for child in node.children() {
+        walk(&child, paragraph, out);
+    }
+}
+fn count(pieces :&[&Piece]) -> Counts {
+    let text :String = pieces.iter().map(|c| c.text.as_str()).collect();
+    let words :Vec<&str> = text.split_whitespace()
+        .filter(|c| c.chars().any(|x| x.is_alphanumeric()))
+        .collect();
+    let sentences = words.iter()
+        .filter(|c| c.trim_end_matches(|x :char| "\"')]*_".contains(x)).ends_with(['.', '!', '?']))
+        .count();
+    Counts {
+        words: words.len(),
+        // Text without a full stop at the end is still a sentence
+        sentences: sentences.max(1),
+        syllables: words.iter().map(|c| syllables(c)).sum(),
+    }
+}
+// Counts the groups of vowels, a silent e at the end does not count
+fn syllables(word :&str) -> usize {
+    let word :String = word.chars().filter(|c| c.is_alphabetic()).flat_map(|c| c.to_lowercase()).collect();
+    let vowel = |c :char| "aeiouyåäöéè".contains(c);
+    let mut count = 0;
+    let mut previous = false;
+    for c in word.chars() {
+        let is_vowel = vowel(c);
+        if is_vowel && !previous {
+            count += 1;
+        }
+        previous = is_vowel;
+    }
+    if count > 1 && word.ends_with('e') && !word.ends_with("le") {
+        count -= 1;
+    }
+    count.max(1)
+}
+fn flesch(counts :&Counts) -> f64 {
+    let words = counts.words.max(1) as f64;
+    206.835 - 1.015 * (words / counts.sentences.max(1) as f64) - 84.6 * (counts.syllables as f64 / words)
+}
+fn difficulty(score :f64) -> &'static str {
+    match score {
+        c if c >= 90.0 => "very easy",
+        c if c >= 80.0 => "easy",
+        c if c >= 70.0 => "fairly easy",
+        c if c >= 60.0 => "standard",
+        c if c >= 50.0 => "fairly difficult",
+        c if c >= 30.0 => "difficult",
+        _ => "very difficult",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(text :&str) -> parse::Document {
+        parse::Document::new(0, &text.to_string())
+    }
+
+    #[test]
+    fn word_counts_of_paragraphs() {
+        let text = "The first paragraph has five words.\n\nA *strong* and `raw code` one.\n\n= A heading\nAfter the heading.";
+        let hints :Vec<(usize, String)> = word_counts(&pieces(&document(text))).into_iter()
+            .map(|(offset, label, _)| (offset, label))
+            .collect();
+        assert_eq!(hints, vec![
+            (text.find(" words.").unwrap() + " words.".len(), String::from("6 words")),
+            (text.find(" one.").unwrap() + " one.".len(), String::from("4 words")),
+            (text.len(), String::from("3 words")),
+        ]);
+    }
+
+    #[test]
+    fn words_around_markup() {
+        let text = "A #emph[nested] word, $x + y$ and _emphasis_ -- \"quoted\" too. // not this";
+        let word = text.find("word").unwrap();
+        assert_eq!(words_in(&document(text), &[0..text.len(), 0..word, word..text.len()]), vec![7, 2, 5]);
+    }
+
+    #[test]
+    fn syllable_counts() {
+        assert_eq!(syllables("cat"), 1);
+        assert_eq!(syllables("make"), 1);
+        assert_eq!(syllables("table"), 2);
+        assert_eq!(syllables("Beautiful,"), 3);
+        assert_eq!(syllables("readability"), 5);
+    }
+
+    #[test]
+    fn flesch_reading_ease() {
+        // 6 words, 1 sentence and 6 syllables: 206.835 - 1.015 * 6 - 84.6 * 1
+        let text = "= Title\n\nThe cat sat on the mat.";
+        let hints = readability(&document(text), &pieces(&document(text)));
+        assert_eq!(hints.len(), 1);
+        assert_eq!(hints[0].0, "= Title".len());
+        assert_eq!(hints[0].1, "readability 116");
+        let score = flesch(&Counts { words: 6, sentences: 1, syllables: 6 });
+        assert!((score - 116.145).abs() < 1e-9);
+        assert_eq!(difficulty(score), "very easy");
+        // Two sentences of 10 words with 15 syllables
+        assert!((flesch(&Counts { words: 20, sentences: 2, syllables: 30 }) - 69.785).abs() < 1e-9);
+    }
+}
@@ -1,57 +1,61 @@
 use serde::{Deserialize, Serialize};
-use tower_lsp::lsp_types::{Position, Range};
+use tower_lsp::lsp_types::{
+    CodeDescription,
+    DiagnosticSeverity,
+    DiagnosticTag,
+    NumberOrString,
+    Position,
+    Range,
+};
 use std::str::Chars;
-use crate::components::{self, Diagnostic};
+use crate::components::Diagnostic;
 use crate::components::language_tool::LTDiagnostic;
 use std::{
     collections::HashMap, 
-    error::Error, 
-    fs::File, 
-    io::BufReader,
 	collections::HashSet,
 };
 use languagetool_rust::{
     check::{
         DataAnnotation,
-        CheckRequest,
         Data,
+        Rule,
     },
 
     server::ServerClient,
     CheckResponse
 };
+use crate::config::{RootConfig, Severity};
 use crate::parse;
 use typst_syntax::SyntaxNode;
 use typst_syntax::SyntaxKind;
-use crate::CONFIG;
 
 pub async fn check(
     document :&parse::Document,
+    uri :&tower_lsp::lsp_types::Url,
     ) -> (Vec<Diagnostic>, Vec<tower_lsp::lsp_types::Diagnostic>) {
     let typst_text :String = document.typst_source.text().to_string();
-    let client :ServerClient;
-    {
-        let config = CONFIG.read().unwrap();
-        client = ServerClient::new(&config.lt_api_hostname.clone(), &config.lt_api_port.clone());
-    }
+    let config = crate::settings::config_for(uri);
+    let client = ServerClient::new(&config.lt_api_hostname.clone(), &config.lt_api_port.clone());
+    let dictionary :HashSet<String> = config.lt_dictionary.iter().cloned().collect();
+    let max_length = config.lt_max_request_chars;
     let typst_nodes = typst_syntax::parse(&typst_text);
-    let converted_nodes = convert(&typst_nodes, &Rules::new(), 10000);
+    let converted_nodes = convert(&typst_nodes, &Rules::new(), max_length);
 
     let mut position :PositionLogic = PositionLogic::new(&typst_text);
 
     let mut out :(Vec<Diagnostic>, Vec<tower_lsp::lsp_types::Diagnostic>) =(vec!{}, vec!{}); 
 	for items in converted_nodes {
-		let req = CheckRequest::default()
-			.with_language("auto".to_string())
+		let req = super::request(&config)
 			.with_data(Data::from_iter(items.0));
 
-		let mut response = match client.check(&req).await {
-		    Ok(c) => {c},
-		    Err(_) => {continue;},
+		// The positions of later chunks depend on this one, stop when it can not be checked
+		let mut response = match super::server::check(&client, &req).await {
+		    Some(c) => {c},
+		    None => {break;},
 		};
-        filter_response(&mut response, &Default::default());
+        filter_response(&mut response, &dictionary);
 
-        let mut out_pulls = add_chunk(document, &mut position, &response, items.1);
+        let mut out_pulls = add_chunk(document, uri, &config, &mut position, &response, items.1);
         out.0.append(&mut out_pulls.0);
         out.1.append(&mut out_pulls.1);
 	}
@@ -60,6 +64,8 @@ pub async fn check(
 }
 fn add_chunk(
     document :&parse::Document,
+    uri :&tower_lsp::lsp_types::Url,
+    config :&RootConfig,
     start : &mut PositionLogic, 
     response :&CheckResponse,
     total: usize,
@@ -86,16 +92,19 @@ fn add_chunk(
             },
         };
 
+        let redundant = info.rule.category.id == "REDUNDANCY" || info.rule.issue_type == "duplication";
         let towe_lsp_val = tower_lsp::lsp_types::Diagnostic {
             range: r,
-            severity: None,
-            code: None,
-            code_description: None,
+            severity: Some(severity(config, &info.rule)),
+            code: Some(NumberOrString::String(info.rule.id.clone())),
+            code_description: rule_page(&info.rule.id, &response.language.code).map(|href| CodeDescription {
+                href,
+            }),
             message: info.message.clone(),
             related_information: None,
-            tags: None,
+            tags: if redundant {Some(vec![DiagnosticTag::UNNECESSARY])} else {None},
             data: None,
-            source: None
+            source: Some(super::NAME.to_string()),
         };
         out.1.push(
             towe_lsp_val.clone()
@@ -107,14 +116,15 @@ fn add_chunk(
             
         out.0.push(
             Diagnostic {
+                uri: uri.clone(),
                 range: typst_range,
                 version: document.latest_version,
                 diagnostics_lsp: towe_lsp_val.clone(),
-                source_data: components::DiagnosticSourceData::LanguageTool(LTDiagnostic {
+                source_data: std::sync::Arc::new(LTDiagnostic {
                     replacements: info.replacements.clone(),
                     rule: info.rule.clone()
                 }),
-                source: components::DiagnosticSource::LanguageTool,
+                source: super::NAME,
         });
 		last = info.offset;
 	}
@@ -122,6 +132,29 @@ fn add_chunk(
 
     out
 }
+// The severity lt_severity gives the category or issue type of the rule
+fn severity(config :&RootConfig, rule :&Rule) -> DiagnosticSeverity {
+	let configured = config.lt_severity.get(&rule.category.id)
+		.or_else(|| config.lt_severity.get(&rule.issue_type));
+	match configured {
+		Some(Severity::Error) => DiagnosticSeverity::ERROR,
+		Some(Severity::Warning) => DiagnosticSeverity::WARNING,
+		Some(Severity::Information) => DiagnosticSeverity::INFORMATION,
+		Some(Severity::Hint) => DiagnosticSeverity::HINT,
+		None => match rule.issue_type.as_str() {
+			"misspelling" => DiagnosticSeverity::ERROR,
+			"style" | "typographical" => DiagnosticSeverity::INFORMATION,
+			_ => DiagnosticSeverity::WARNING,
+		},
+	}
+}
+// The page of the rule on the LanguageTool community site, which takes "en" for "en-US"
+fn rule_page(rule_id :&str, language :&str) -> Option<tower_lsp::lsp_types::Url> {
+	let mut url = tower_lsp::lsp_types::Url::parse("https://community.languagetool.org/rule/show/").ok()?;
+	url.path_segments_mut().ok()?.pop_if_empty().push(rule_id);
+	url.query_pairs_mut().append_pair("lang", language.split('-').next().unwrap_or(language));
+	Some(url)
+}
 fn filter_response(response: &mut CheckResponse, dict: &HashSet<String>) {
 	for m in std::mem::take(&mut response.matches).into_iter() {
 		// Only handle misspellings
@@ -149,15 +182,91 @@ fn convert(
 	max_length: usize,
 ) -> Vec<(Vec<DataAnnotation>, usize)> {
 	let state = State { mode: Mode::Markdown };
-	let mut output = Output::new();
-	for child in node.children() {
+	let mut output = Output::new(max_length);
+	let children :Vec<&SyntaxNode> = node.children().collect();
+	for (i, child) in children.iter().enumerate() {
 		state.convert(child, &mut output, rules);
 		if child.kind() == SyntaxKind::Parbreak {
-			output.maybe_seperate(max_length);
+			// The byte length of the next paragraph, it is never less than its length in chars
+			let next :usize = children[i + 1..].iter()
+				.take_while(|c| c.kind() != SyntaxKind::Parbreak)
+				.map(|c| c.len())
+				.sum();
+			output.maybe_seperate(next);
 		}
 	}
 	output.result()
 }
+// The prose of a typst document as language tool sees it, markup left out or replaced by what it
+// stands for
+pub struct Prose {
+	pub text: String,
+	//Where each piece of the prose comes from, sorted by prose offset. Offsets count chars.
+	pub segments: Vec<Segment>,
+}
+#[derive(Serialize, Clone, Copy)]
+pub struct Segment {
+	pub prose_offset: usize,
+	pub source_offset: usize,
+	//In the prose
+	pub length: usize,
+	//In the source, differs from length when markup is replaced, like a reference by "X"
+	pub source_length: usize,
+}
+impl Prose {
+	// The source char offset of a prose char offset. An offset inside replaced markup maps to
+	// the start of the markup, or its end when `end` is set.
+	pub fn source_offset(&self, offset: usize, end: bool) -> usize {
+		let index = self.segments.partition_point(|c| c.prose_offset + c.length < offset + (!end) as usize);
+		let segment = match self.segments.get(index) {
+			Some(c) => c,
+			None => return self.segments.last().map_or(0, |c| c.source_offset + c.source_length),
+		};
+		let inside = offset.saturating_sub(segment.prose_offset);
+		if segment.length == segment.source_length {
+			segment.source_offset + inside.min(segment.length)
+		} else if end {
+			segment.source_offset + segment.source_length
+		} else {
+			segment.source_offset
+		}
+	}
+}
+pub fn extract_prose(typst_text: &str) -> Prose {
+	let typst_nodes = typst_syntax::parse(typst_text);
+	let mut prose = Prose {
+		text: String::new(),
+		segments: vec![],
+	};
+	let mut prose_offset = 0;
+	let mut source_offset = 0;
+	for (items, _) in convert(&typst_nodes, &Rules::new(), usize::MAX) {
+		for item in items {
+			let (source, shown) = match (&item.text, &item.markup, &item.interpret_as) {
+				(Some(t), _, _) => (t.chars().count(), Some(t.as_str())),
+				(None, Some(m), interpret_as) => (m.chars().count(), interpret_as.as_deref()),
+				_ => continue,
+			};
+			if let Some(shown) = shown.filter(|c| !c.is_empty()) {
+				let length = shown.chars().count();
+				prose.text.push_str(shown);
+				prose.segments.push(Segment {
+					prose_offset,
+					source_offset,
+					length,
+					source_length: source,
+				});
+				prose_offset += length;
+			} else if let Some(last) = prose.segments.last_mut()
+				.filter(|c| c.source_length == 0 && c.source_offset == source_offset) {
+				// The markup a replacement stands for, like the "@label" of a reference
+				last.source_length = source;
+			}
+			source_offset += source;
+		}
+	}
+	prose
+}
 #[derive(Serialize, Deserialize)]
 struct Rules {
 	functions: HashMap<String, Function>,
@@ -173,13 +282,6 @@ impl Rules {
 	fn new() -> Self {
 		Self { functions: HashMap::new() }
 	}
-
-	fn load(path: &String) -> Result<Self, Box<dyn Error>> {
-		let file = File::open(path)?;
-		let reader = BufReader::new(file);
-		let rules = serde_json::from_reader(reader)?;
-		Ok(rules)
-	}
 }
 enum OutputState {
 	Text(String),
@@ -190,13 +292,16 @@ enum OutputState {
 struct Output {
 	items: Vec<(Vec<DataAnnotation>, usize)>,
 	state: OutputState,
+	//The most chars in one chunk, the server rejects longer requests
+	max: usize,
 }
 
 impl Output {
-	fn new() -> Self {
+	fn new(max: usize) -> Self {
 		Self {
 			items: vec![(Vec::new(), 0)],
 			state: OutputState::Text(String::new()),
+			max,
 		}
 	}
 
@@ -222,6 +327,51 @@ impl Output {
 				self.add_item(DataAnnotation::new_interpreted_markup(t.clone(), a.clone()));
 				OutputState::Text(text)
 			},
+		};
+		self.split_text();
+	}
+
+	// Moves the text that does not fit into the chunk to a new one, after the last sentence that
+	// fits. A sentence longer than a whole chunk is split between words.
+	fn split_text(&mut self) {
+		loop {
+			let text = match &self.state {
+				OutputState::Text(t) => t.clone(),
+				_ => return,
+			};
+			let used = self.items.last().unwrap().1;
+			let room = self.max.saturating_sub(used);
+			if text.chars().count() <= room {
+				return;
+			}
+			// Byte offsets after the last sentence end and the last space within room
+			let mut sentence = None;
+			let mut word = None;
+			let mut previous = ' ';
+			for (c, (i, x)) in text.char_indices().enumerate() {
+				if c >= room {
+					break;
+				}
+				if x.is_whitespace() {
+					if matches!(previous, '.' | '!' | '?') {
+						sentence = Some(i + x.len_utf8());
+					}
+					word = Some(i + x.len_utf8());
+				}
+				previous = x;
+			}
+			let at = match (sentence, word) {
+				(Some(c), _) => c,
+				// The whole text may fit into a chunk of its own
+				(None, _) if used > 0 => 0,
+				(None, Some(c)) => c,
+				(None, None) => return,
+			};
+			if at > 0 {
+				self.add_item(DataAnnotation::new_text(text[..at].to_string()));
+			}
+			self.items.push((Vec::new(), 0));
+			self.state = OutputState::Text(text[at..].to_string());
 		}
 	}
 
@@ -262,8 +412,11 @@ impl Output {
 		}
 	}
 
-	fn maybe_seperate(&mut self, max: usize) {
-		if self.items.last().unwrap().1 > max {
+	// Starts a new chunk when the next paragraph would not fit in this one. A paragraph longer
+	// than max is split by add_text.
+	fn maybe_seperate(&mut self, next: usize) {
+		let current = self.items.last().unwrap().1;
+		if current > 0 && current + next > self.max {
 			self.flush();
 			self.state = OutputState::Text(String::new());
 			self.items.push((Vec::new(), 0));
@@ -396,9 +549,71 @@ impl<'a> PositionLogic<'a> {
 	}
     fn clone(&self) -> Self {
         PositionLogic {
-            line: self.line.clone(),
-            column: self.column.clone(),
+            line: self.line,
+            column: self.column,
             content: self.content.clone(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn long_paragraphs_split_after_sentences() {
+		let text = "The first sentence is here. A second one follows it. And a third one ends it.";
+		let chunks = convert(&typst_syntax::parse(text), &Rules::new(), 40);
+		let texts :Vec<String> = chunks.iter()
+			.map(|(items, _)| items.iter().filter_map(|i| i.text.clone()).collect())
+			.collect();
+		assert_eq!(texts, vec![
+			"The first sentence is here. ",
+			"A second one follows it. ",
+			"And a third one ends it.",
+		]);
+		assert!(chunks.iter().all(|(_, length)| *length <= 40));
+	}
+
+	#[test]
+	fn rule_pages_take_the_language() {
+		assert_eq!(
+			rule_page("MORFOLOGIK_RULE_EN_US", "en-US").unwrap().as_str(),
+			"https://community.languagetool.org/rule/show/MORFOLOGIK_RULE_EN_US?lang=en",
+		);
+		assert_eq!(rule_page("UPPERCASE", "de").unwrap().as_str(), "https://community.languagetool.org/rule/show/UPPERCASE?lang=de");
+	}
+
+	#[test]
+	fn prose_of_references_and_emphasis() {
+		let prose = extract_prose("See @intro and _this_ word.");
+		assert_eq!(prose.text, "See X and this word.");
+		// The X covers the whole reference
+		assert_eq!(prose.source_offset(4, false), 4);
+		assert_eq!(prose.source_offset(5, true), 10);
+		assert_eq!(prose.source_offset(5, false), 10);
+		// "this" without the underscores
+		assert_eq!(prose.source_offset(10, false), 16);
+		assert_eq!(prose.source_offset(14, true), 20);
+	}
+
+	#[test]
+	fn prose_of_function_calls() {
+		let text = "A #box(width: 1pt)[text] here.";
+		let prose = extract_prose(text);
+		assert_eq!(prose.text, "A \n\ntext\n\n here.");
+		let start = prose.source_offset(4, false);
+		let end = prose.source_offset(8, true);
+		assert_eq!(&text[start..end], "text");
+	}
+
+	#[test]
+	fn prose_offsets_count_chars() {
+		let prose = extract_prose("Grüße, schön. Ende");
+		assert_eq!(prose.text, "Grüße, schön. Ende");
+		assert_eq!(prose.source_offset(7, false), 7);
+		assert_eq!(prose.source_offset(12, true), 12);
+		// Past the end
+		assert_eq!(prose.source_offset(30, true), 18);
+	}
+}
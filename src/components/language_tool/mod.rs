@@ -6,86 +6,149 @@
 
 
 mod check_text;
-use std::collections::HashMap;
+pub mod server;
+pub use check_text::{extract_prose, Prose, Segment};
 use languagetool_rust::check::{
+        CheckRequest,
+        Level,
         Replacement, 
         Rule,
     };
 use tower_lsp::lsp_types::MessageType;
-use tower_lsp::lsp_types::TextEdit;
 use tower_lsp::lsp_types::Url;
-use tower_lsp::lsp_types::WorkspaceEdit;
-use crate::components::Diagnostic;
-use uuid::Uuid;
+use crate::components::{Checked, Checker, Diagnostic};
 use std::ops::Range;
-use std::ops::Deref;
 use std::clone::Clone;
 
+pub const NAME :&str = "languagetool";
+// Arguments: the uri and optionally the LSP range to check, the whole file when it is null
+pub const CHECK_COMMAND :&str = "typst-grammar.checkLanguageTool";
+// Arguments: the uri, null and optionally the rule id whose diagnostics are removed
+pub const CLEAR_COMMAND :&str = "typst-grammar.clearLanguageTool";
+
 #[derive(Clone)]
 pub struct LTDiagnostic {
     pub replacements: Vec<Replacement>,
     pub rule: Rule,
 
 }
-pub struct LTCodeActionCheckText {
-    pub uri :Url,
-}
-pub struct LTCodeActionRemoveDiagnostic {
-    pub uri :Url,
-}
-pub struct TextCheck {
-    pub diagnostic: Vec<Diagnostic>,
-    pub matches: Vec<MatchChunk>,
-
-}
-pub struct MatchChunk {
-    matches: Vec<Match>,
-    language_code: String
+pub struct LanguageToolChecker;
+#[tower_lsp::async_trait]
+impl Checker for LanguageToolChecker {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+    fn on_demand(&self) -> bool {
+        true
+    }
+    async fn check(&self, backend :&crate::Backend, uri :&Url, _range :Option<Range<usize>>) -> Checked {
+        // language tool is slow, check a copy so no document is locked while waiting for it
+        let copy = backend.with_document(uri, |c| (c.latest_version, c.typst_source.text().to_string()));
+        let found = match copy {
+            Some((version, text)) => check(&crate::parse::Document::new(version, &text), uri).await,
+            None => vec![],
+        };
+        Checked::file(uri, found)
+    }
+    async fn code_actions(&self, backend :&crate::Backend, document :&crate::parse::Document, uri :&Url, range :&Range<usize>)
+        -> Vec<tower_lsp::lsp_types::CodeActionOrCommand> {
+        code_actions(&backend.client, document, uri.clone(), range).await
+    }
+    fn fix_all(&self) -> bool {
+        true
+    }
+    fn quick_fixes(&self, diagnostic :&Diagnostic) -> Vec<crate::components::QuickFix> {
+        quick_fixes(diagnostic)
+    }
+    fn commands(&self) -> Vec<String> {
+        vec![CHECK_COMMAND.to_string(), CLEAR_COMMAND.to_string()]
+    }
+    async fn execute(&self, backend :&crate::Backend, params :&tower_lsp::lsp_types::ExecuteCommandParams) -> bool {
+        if params.command != CHECK_COMMAND && params.command != CLEAR_COMMAND {
+            return false;
+        }
+        let uri :Url = match params.arguments.first().and_then(|c| serde_json::from_value(c.clone()).ok()) {
+            Some(c) => c,
+            None => {
+                log::warn!("{} needs the uri of a document", params.command);
+                return true;
+            },
+        };
+        if params.command == CHECK_COMMAND {
+            let range :Option<tower_lsp::lsp_types::Range> = params.arguments.get(1)
+                .and_then(|c| serde_json::from_value(c.clone()).ok());
+            let byte_range = range.and_then(|r| backend.with_document(&uri, |c| c.lsp_range_to_byte_range(&r)).flatten());
+            backend.client.show_message(MessageType::LOG, "Laddar med language tools".to_string()).await;
+            crate::components::run_checker(backend, NAME, &uri, byte_range).await;
+        } else {
+            let rule = params.arguments.get(2).and_then(|c| c.as_str());
+            remove_diagnostics(&uri, rule);
+            crate::components::publish_file_diagnostics(backend, &uri).await;
+        }
+        true
+    }
+    fn refilter(&self, backend :&crate::Backend) {
+        refilter(backend);
+    }
 }
-pub struct Match {
-    match_data: languagetool_rust::check::Match,
+// Removes the language tool diagnostics of a file, only those of `rule` if given
+pub fn remove_diagnostics(uri :&Url, rule :Option<&str>) {
+    crate::components::DIAGNOSTICS.lock().unwrap().retain(|x| {
+        if x.source != NAME || &x.uri != uri {
+            return true;
+        }
+        match (rule, x.data::<LTDiagnostic>()) {
+            (Some(rule), Some(c)) => c.rule.id != rule,
+            _ => false,
+        }
+    });
 }
-
-pub fn remove_diagnostics() {
-    crate::components::remove_lsp_diagnostics_of_type(
-        crate::components::DiagnosticSource::LanguageTool
-    );
-
+// Drops the stored diagnostics the configuration no longer allows, all of them when language
+// tool is disabled, disabled rules and misspellings of words added to the dictionary
+pub fn refilter(backend :&crate::Backend) {
+    crate::components::DIAGNOSTICS.lock().unwrap().retain(|x| {
+        if x.source != NAME {
+            return true;
+        }
+        let lt_dia = match x.data::<LTDiagnostic>() {
+            Some(c) => c,
+            None => return true,
+        };
+        let config = crate::settings::config_for(&x.uri);
+        if !config.lt_enabled || config.lt_disabled_rules.contains(&lt_dia.rule.id) {
+            return false;
+        }
+        if lt_dia.rule.issue_type != "misspelling" {
+            return true;
+        }
+        let word = backend.with_document(&x.uri, |document| {
+            let range = document.correct_range(x.version, x.range.clone())?;
+            document.typst_source.text().get(range).map(|c| c.to_string())
+        }).flatten();
+        !word.is_some_and(|c| config.lt_dictionary.contains(&c))
+    });
 }
-pub async fn check(document :&crate::parse::Document) -> Vec<Diagnostic> {
-    if !crate::CONFIG.read().unwrap().lt_enabled {
+pub async fn check(document :&crate::parse::Document, uri :&Url) -> Vec<Diagnostic> {
+    if !crate::settings::config_for(uri).lt_enabled {
         return Vec::new();
     }
-    check_text::check(document).await.0
-}
-pub async fn code_action_check_text(backend :&crate::Backend, values :&LTCodeActionCheckText)
-    -> Option<(Url, Vec<Diagnostic>)> {
-    if !crate::CONFIG.read().unwrap().lt_enabled {
-        return None;
-    }
-    let working_doc_ref = match backend.document_map.get(&values.uri.clone()) {
-        Some(c) => {c},
-        None => {return None}
-    };
-    let working_doc = working_doc_ref.deref().to_owned();
-    backend.client.show_message(tower_lsp::lsp_types::MessageType::LOG, "Laddar med language tools".to_string()).await;
-    let checks = check(&working_doc).await;
-    Some((values.uri.clone(), checks))
+    check_text::check(document, uri).await.0
 }
 pub async fn code_actions(client :&tower_lsp::Client, document :&crate::parse::Document, uri :Url, range :&Range<usize>) 
-    -> (Vec<tower_lsp::lsp_types::CodeActionOrCommand>, Vec<(String, crate::components::CodeActionSource)> ) {
-    if !crate::CONFIG.read().unwrap().lt_enabled {
-        return (Vec::new(), Vec::new());
+    -> Vec<tower_lsp::lsp_types::CodeActionOrCommand> {
+    if !crate::settings::config_for(&uri).lt_enabled {
+        return Vec::new();
     }
     let hovering_error :Vec<crate::components::Diagnostic> =  crate::components::DIAGNOSTICS
         .lock().unwrap()
         .iter()
         .filter_map( |x| {
-            let x_range = match document.correct_range(x.version.clone(), x.range.clone()) {
+            let x_range = match document.correct_range(x.version, x.range.clone()) {
                 Some(c) => c,
                 None => return None,
             };
-            if x.source == crate::components::DiagnosticSource::LanguageTool
+            if x.source == NAME
+                && x.uri == uri
                 && x_range.end >= range.end
                 && x_range.start <= range.start
             {
@@ -97,87 +160,89 @@ pub async fn code_actions(client :&tower_lsp::Client, document :&crate::parse::D
         .collect();
     client.log_message(MessageType::INFO, format!("Hovering error length: {}", hovering_error.len())).await;
     client.log_message(MessageType::INFO, format!("Range: start: {}, stop: {}", range.start, range.end)).await;
-    
-    let uuid1 = Uuid::new_v4().to_string();
-    let uuid2 = Uuid::new_v4().to_string();
+
+    // A selection is checked on its own, a cursor checks the whole file
+    let selection = match document.byte_range_to_lsp_range(range) {
+        Some(c) if !range.is_empty() => serde_json::to_value(c).unwrap(),
+        _ => serde_json::Value::Null,
+    };
+    let check_title = if selection.is_null() {"Evaluate with language tools"} else {"Evaluate selection with language tools"};
     let mut tower_lsp_diagnostics = vec![
         tower_lsp::lsp_types::CodeActionOrCommand::Command(tower_lsp::lsp_types::Command {
-            title: "Evaluate with language tools".to_string(),
-                command: uuid1.clone(),
-                arguments: None,
+            title: check_title.to_string(),
+                command: CHECK_COMMAND.to_string(),
+                arguments: Some(vec![serde_json::json!(uri), selection]),
             }),
         tower_lsp::lsp_types::CodeActionOrCommand::Command(tower_lsp::lsp_types::Command {
             title: "Remove language tools diagnostics".to_string(),
-                command: uuid2.clone(),
-                arguments: None,
+                command: CLEAR_COMMAND.to_string(),
+                arguments: Some(vec![serde_json::json!(uri)]),
             }),
     ];
-    let component_diagnostic = vec![
-        (
-            uuid1,
-            crate::components::CodeActionSource::LanguageToolCheckText(LTCodeActionCheckText {
-                uri: uri.clone()
-            })
-        ),
-        (
-            uuid2,
-            crate::components::CodeActionSource::LanguageToolRemoveDiagnostics(LTCodeActionRemoveDiagnostic {
-                uri: uri.clone()
-            })
-        )
-    ];
-    for err in hovering_error {
-        tower_lsp_diagnostics.extend(
-            diagnostic_code_action(document, &err, &uri)
-            )
+    let mut rules :Vec<Rule> = hovering_error.iter()
+        .filter_map(|c| c.data::<LTDiagnostic>().map(|c| c.rule.clone()))
+        .collect();
+    rules.dedup_by(|a, b| a.id == b.id);
+    for rule in rules {
+        tower_lsp_diagnostics.push(tower_lsp::lsp_types::CodeActionOrCommand::Command(tower_lsp::lsp_types::Command {
+            title: format!("Remove \"{}\" diagnostics", rule.description),
+            command: CLEAR_COMMAND.to_string(),
+            arguments: Some(vec![serde_json::json!(uri), serde_json::Value::Null, serde_json::json!(rule.id)]),
+        }));
     }
-    (tower_lsp_diagnostics, component_diagnostic)
+    tower_lsp_diagnostics.extend(crate::components::quick_fix_actions(document, &uri, range, NAME, quick_fixes));
+    tower_lsp_diagnostics
 }
-fn diagnostic_code_action(document :&crate::parse::Document, diagnostic :&crate::components::Diagnostic, uri :&Url) 
-    -> Vec<tower_lsp::lsp_types::CodeActionOrCommand> {
-    let lt_dia :LTDiagnostic = match &diagnostic.source_data {
-        super::DiagnosticSourceData::LanguageTool(c) => c.clone(),
-        _ => {return Vec::new()}
-    };
-    let mut return_var :Vec<tower_lsp::lsp_types::CodeActionOrCommand> = Vec::new();
-    let corrected_range = match document.correct_range(diagnostic.version, diagnostic.range.clone()) {
-        Some(c) => {c},
-        None => {return Vec::new();},
+// The replacements language tool suggested
+fn quick_fixes(diagnostic :&Diagnostic) -> Vec<crate::components::QuickFix> {
+    let lt_dia = match diagnostic.data::<LTDiagnostic>() {
+        Some(c) => c,
+        None => return Vec::new(),
     };
-    let lsp_range = match document.byte_range_to_lsp_range(&corrected_range) {
-        Some(c)  => {c},
-        None => {return Vec::new();}
+    lt_dia.replacements.iter()
+        .map(|c| (
+            format!("Rule: {} | Replacement: {}", lt_dia.rule.description, c.value),
+            vec![(diagnostic.range.clone(), c.value.clone())],
+        ))
+        .collect()
+}
+// Checks plain text that is not part of a typst document, like the fields of a bibliography
+pub async fn check_plain_text(text :String) -> Option<Vec<languagetool_rust::check::Match>> {
+    let client :languagetool_rust::server::ServerClient;
+    let req :CheckRequest;
+    {
+        let config = crate::CONFIG.read().unwrap();
+        if !config.lt_enabled {
+            return None;
+        }
+        client = languagetool_rust::server::ServerClient::new(&config.lt_api_hostname, &config.lt_api_port);
+        req = request(&config).with_text(text);
+    }
+    server::check(&client, &req).await.map(|c| c.matches)
+}
+// A request with the account and rule options of the config, without the text
+pub fn request(config :&crate::config::RootConfig) -> CheckRequest {
+    let mut req = CheckRequest::default()
+        .with_language("auto".to_string());
+    req.username = config.lt_username.clone();
+    req.api_key = config.lt_api_key.clone();
+    req.level = match config.lt_level {
+        crate::config::LtLevel::Default => Level::Default,
+        crate::config::LtLevel::Picky => Level::Picky,
     };
-    let lsp_diagnostics :Vec<tower_lsp::lsp_types::Diagnostic> = vec![
-        diagnostic.corrected_diagnostics_lsp(&document).unwrap()
-    ];
-    for lt_replacement in lt_dia.replacements {
-        let mut replacement :HashMap<tower_lsp::lsp_types::Url, Vec<TextEdit>> = HashMap::new();
-        replacement.insert(uri.clone(), vec![
-            tower_lsp::lsp_types::TextEdit {
-                range: lsp_range.clone(),
-                new_text: lt_replacement.value.clone()
-            }
-        ]);
-
-        return_var.push(
-            tower_lsp::lsp_types::CodeActionOrCommand::CodeAction(tower_lsp::lsp_types::CodeAction {
-                title: format!("Rule: {} | Replacement: {}", lt_dia.rule.description.clone(), lt_replacement.value.clone()).to_string(),
-                kind: None,
-                diagnostics: Some(lsp_diagnostics.clone()),
-                edit: Some( WorkspaceEdit {
-                    changes: Some(replacement),
-                    document_changes: None,
-                    change_annotations: None,
-                }),
-                command: None,
-                is_preferred: None,
-                disabled: None,
-                data: None
-            })
-        );
-
+    req.mother_tongue = config.lt_mother_tongue.clone();
+    if !config.lt_preferred_variants.is_empty() {
+        req.preferred_variants = Some(config.lt_preferred_variants.clone());
     }
-    return_var
-
+    if !config.lt_disabled_rules.is_empty() {
+        req.disabled_rules = Some(config.lt_disabled_rules.clone());
+    }
+    if !config.lt_enabled_rules.is_empty() {
+        req.enabled_rules = Some(config.lt_enabled_rules.clone());
+    }
+    if !config.lt_enabled_categories.is_empty() {
+        req.enabled_categories = Some(config.lt_enabled_categories.clone());
+    }
+    req.enabled_only = config.lt_enabled_only;
+    req
 }
@@ -0,0 +1,251 @@
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use languagetool_rust::check::CheckRequest;
+use languagetool_rust::server::ServerClient;
+use languagetool_rust::CheckResponse;
+use lazy_static::lazy_static;
+use tokio::process::{Child, Command};
+use tokio::sync::{Mutex, Notify};
+use tower_lsp::lsp_types::{MessageActionItem, MessageType};
+use crate::config::LtServerConfig;
+
+//How many times a check request is sent before giving up, the wait between doubles each time
+const MAX_ATTEMPTS :u32 = 3;
+//How many failed starts in a row before the managed server is left alone until the config changes
+const MAX_STARTS :u32 = 5;
+
+struct Managed {
+    child: Option<Child>,
+    //The settings and port the server was last started with, it is restarted when they change
+    started_with: Option<(LtServerConfig, String)>,
+    //Failed starts in a row
+    failures: u32,
+    //No new start before this, grows with the failures
+    next_start: Option<Instant>,
+    //A check is waiting for the server to answer, the lock is not held meanwhile
+    starting: bool,
+}
+
+lazy_static! {
+    static ref SERVER :Mutex<Managed> = Mutex::new(Managed {
+        child: None,
+        started_with: None,
+        failures: 0,
+        next_start: None,
+        starting: false,
+    });
+    //Wakes the checks waiting for a start to finish
+    static ref STARTED :Notify = Notify::new();
+    //The editor to tell about failures, None in the check subcommand
+    static ref CLIENT :RwLock<Option<tower_lsp::Client>> = RwLock::new(None);
+    //If the user has been told the server can not be reached, reset when a request succeeds
+    static ref REPORTED :AtomicBool = AtomicBool::new(false);
+}
+
+pub fn set_client(client :tower_lsp::Client) {
+    *CLIENT.write().unwrap() = Some(client);
+}
+// Starts the managed server if it is enabled and not running. Returns false if it is enabled but
+// could not be started.
+pub async fn ensure_running() -> bool {
+    loop {
+        let (settings, hostname, port) = {
+            let config = crate::CONFIG.read().unwrap();
+            (config.lt_server.clone(), config.lt_api_hostname.clone(), config.lt_api_port.clone())
+        };
+        let mut server = SERVER.lock().await;
+        if server.starting {
+            // Starting takes up to startup_timeout_secs, wait for it without holding the lock
+            let started = STARTED.notified();
+            drop(server);
+            started.await;
+            continue;
+        }
+        if !settings.managed {
+            stop(&mut server).await;
+            return true;
+        }
+        let wanted = Some((settings.clone(), port.clone()));
+        if server.started_with != wanted {
+            // New settings, start over
+            stop(&mut server).await;
+        }
+        if let Some(child) = server.child.as_mut() {
+            match child.try_wait() {
+                Ok(None) => return true,
+                Ok(Some(status)) => report(MessageType::WARNING, format!("LanguageTool exited with {}, restarting it", status)),
+                Err(e) => report(MessageType::WARNING, format!("Lost track of LanguageTool ({}), restarting it", e)),
+            }
+            server.child = None;
+        }
+        if server.failures >= MAX_STARTS {
+            return false;
+        }
+        if server.next_start.is_some_and(|c| Instant::now() < c) {
+            return false;
+        }
+        server.started_with = wanted.clone();
+        server.starting = true;
+        drop(server);
+        let started = start(&settings, &hostname, &port).await;
+        let mut server = SERVER.lock().await;
+        server.starting = false;
+        STARTED.notify_waiters();
+        if server.started_with != wanted {
+            // Stopped or the config changed while it was starting
+            if let Ok(mut c) = started {
+                let _ = c.kill().await;
+            }
+            continue;
+        }
+        return match started {
+            Ok(c) => {
+                log::info!("Started LanguageTool on port {}", port);
+                server.child = Some(c);
+                server.failures = 0;
+                server.next_start = None;
+                true
+            },
+            Err(e) => {
+                server.failures += 1;
+                server.next_start = Some(Instant::now() + Duration::from_secs(2u64.pow(server.failures).min(60)));
+                let message = if server.failures >= MAX_STARTS {
+                    format!("Could not start LanguageTool: {}. Giving up until the config changes", e)
+                } else {
+                    format!("Could not start LanguageTool: {}", e)
+                };
+                report(MessageType::ERROR, message);
+                false
+            },
+        };
+    }
+}
+async fn start(settings :&LtServerConfig, hostname :&str, port :&str) -> Result<Child, String> {
+    if settings.jar.is_empty() {
+        return Err("lt_server.jar is not set".to_string());
+    }
+    let mut child = Command::new(&settings.java)
+        .arg("-cp")
+        .arg(&settings.jar)
+        .arg("org.languagetool.server.HTTPServer")
+        .arg("--port")
+        .arg(port)
+        .args(&settings.args)
+        // stdout is the connection to the editor, the server must not write to it
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("{} -cp {}: {}", settings.java, settings.jar, e))?;
+    let client = ServerClient::new(hostname, port);
+    let deadline = Instant::now() + Duration::from_secs(settings.startup_timeout_secs);
+    loop {
+        if client.ping().await.is_ok() {
+            return Ok(child);
+        }
+        if let Ok(Some(status)) = child.try_wait() {
+            return Err(format!("it exited with {}", status));
+        }
+        if Instant::now() > deadline {
+            let _ = child.kill().await;
+            return Err(format!("it did not answer within {} seconds", settings.startup_timeout_secs));
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+}
+async fn stop(server :&mut Managed) {
+    if let Some(mut c) = server.child.take() {
+        let _ = c.kill().await;
+    }
+    server.started_with = None;
+    server.failures = 0;
+    server.next_start = None;
+}
+pub async fn shutdown() {
+    let mut server = SERVER.lock().await;
+    stop(&mut server).await;
+}
+// Runs for as long as the language server, checks now and then that the managed server answers
+// and restarts it when it has crashed or hangs
+pub async fn watch() {
+    loop {
+        let (managed, interval, hostname, port) = {
+            let config = crate::CONFIG.read().unwrap();
+            (config.lt_server.managed, config.lt_server.health_check_secs.max(1), config.lt_api_hostname.clone(), config.lt_api_port.clone())
+        };
+        if managed && ensure_running().await {
+            if ServerClient::new(&hostname, &port).ping().await.is_err() {
+                let mut server = SERVER.lock().await;
+                if let Some(mut c) = server.child.take() {
+                    report(MessageType::WARNING, "LanguageTool stopped answering, restarting it".to_string());
+                    let _ = c.kill().await;
+                }
+            }
+        } else if !managed {
+            // Stops a server that was managed before the config changed
+            ensure_running().await;
+        }
+        tokio::time::sleep(Duration::from_secs(interval)).await;
+    }
+}
+// Sends a check request, starting the managed server first and retrying with a growing wait when
+// the server can not be reached. The user is told once when every attempt fails.
+pub async fn check(client :&ServerClient, request :&CheckRequest) -> Option<CheckResponse> {
+    let mut delay = Duration::from_millis(500);
+    let mut last_error = "the managed server is not running".to_string();
+    for attempt in 0..MAX_ATTEMPTS {
+        if attempt > 0 {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+        if !ensure_running().await {
+            continue;
+        }
+        match client.check(request).await {
+            Ok(c) => {
+                REPORTED.store(false, Ordering::Relaxed);
+                return Some(c);
+            },
+            Err(e) => last_error = e.to_string(),
+        }
+    }
+    crate::components::check_failed();
+    if !REPORTED.swap(true, Ordering::Relaxed) {
+        report_with_retry(format!("Could not reach LanguageTool: {}", last_error));
+    }
+    None
+}
+fn report(typ :MessageType, message :String) {
+    log::warn!("{}", message);
+    let client = match CLIENT.read().unwrap().clone() {
+        Some(c) => c,
+        None => return,
+    };
+    tokio::spawn(async move {
+        client.show_message(typ, message).await;
+    });
+}
+// Shows the error with a Retry button that lets the next check start the server again at once
+fn report_with_retry(message :String) {
+    log::error!("{}", message);
+    let client = match CLIENT.read().unwrap().clone() {
+        Some(c) => c,
+        None => return,
+    };
+    tokio::spawn(async move {
+        let retry = MessageActionItem {
+            title: "Retry".to_string(),
+            properties: Default::default(),
+        };
+        let picked = client.show_message_request(MessageType::ERROR, message, Some(vec![retry])).await;
+        if let Ok(Some(_)) = picked {
+            REPORTED.store(false, Ordering::Relaxed);
+            let mut server = SERVER.lock().await;
+            server.failures = 0;
+            server.next_start = None;
+        }
+    });
+}
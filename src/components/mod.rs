@@ -1,125 +1,505 @@
 mod language_tool;
-use std::ops::{Range, Deref};
-use std::cmp::PartialEq;
-use std::sync::Mutex;
+mod heading;
+mod references;
+mod bibliography;
+mod workspace;
+mod baseline;
+mod checker;
+mod external;
+mod fix_all;
+mod pull;
+mod inlay_hints;
+mod outline;
+mod folding;
+mod navigation;
+mod selection;
+mod debounce;
+use std::any::Any;
+use std::collections::HashSet;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::clone::Clone;
 
-use dashmap::DashMap;
 use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
 use tower_lsp::lsp_types::MessageType;
+use tower_lsp::lsp_types::Url;
 
 use crate::parse;
+pub use workspace::CHECK_WORKSPACE_COMMAND;
+pub use baseline::{FILE_NAME as BASELINE_FILE_NAME, WRITE_COMMAND as WRITE_BASELINE_COMMAND, PRUNE_COMMAND as PRUNE_BASELINE_COMMAND};
+pub use checker::{Checked, Checker, Checkers};
+pub use references::forget as forget_labels;
 
 lazy_static! {
-    static ref CODE_ACTIONS :DashMap<String, CodeActionSource> = DashMap::new();
     static ref DIAGNOSTICS :Mutex<Vec<Diagnostic>> = Mutex::new(Vec::new());
+    //If quick fixes are sent without their edit, to be resolved when picked
+    static ref RESOLVE_EDITS :AtomicBool = AtomicBool::new(false);
+    //How many checks could not run, like LanguageTool being unreachable or a linter failing
+    static ref FAILED_CHECKS :AtomicUsize = AtomicUsize::new(0);
 }
 
 #[derive(Clone)]
 pub struct Diagnostic {
+   uri :Url,
    range :Range<usize>,
    version :isize,
    diagnostics_lsp :tower_lsp::lsp_types::Diagnostic,
-   source_data :DiagnosticSourceData,
-   source :DiagnosticSource,
+   //What the checker needs to make quick fixes, read back with `data`
+   source_data :Arc<dyn Any + Send + Sync>,
+   //The name of the checker
+   source :&'static str,
 
 }
 impl Diagnostic {
+    pub fn new(
+        document :&parse::Document,
+        uri :&Url,
+        range :Range<usize>,
+        mut diagnostics_lsp :tower_lsp::lsp_types::Diagnostic,
+        source :&'static str,
+        source_data :impl Any + Send + Sync,
+        ) -> Option<Self> {
+        diagnostics_lsp.range = document.byte_range_to_lsp_range(&range)?;
+        Some(Diagnostic {
+            uri: uri.clone(),
+            range,
+            version: document.latest_version,
+            diagnostics_lsp,
+            source_data: Arc::new(source_data),
+            source,
+        })
+    }
+    // The data the checker stored with the diagnostic, None if it is of another type
+    pub fn data<T :Any>(&self) -> Option<&T> {
+        self.source_data.downcast_ref::<T>()
+    }
     pub fn corrected_diagnostics_lsp(&self, document :&parse::Document) 
     -> Option<tower_lsp::lsp_types::Diagnostic> {
         let mut ret = self.diagnostics_lsp.clone();
-        let corrected_range = match document.correct_range(self.version, self.range.clone()) {
-            Some(c) => c ,
-            None => {return None}
-        };
-        ret.range = match document.byte_range_to_lsp_range(&corrected_range) {
-            Some(c) => {c},
-            None => {return None},
-        };
+        let corrected_range = document.correct_range(self.version, self.range.clone())?;
+        ret.range = document.byte_range_to_lsp_range(&corrected_range)?;
         Some(ret)
     }
 }
-#[derive(PartialEq, Clone)]
-pub enum DiagnosticSource {
-    LanguageTool,
+
+// Runs the checkers that are cheap enough to run on every change on the changed document and
+// publishes every file they touched, like the files whose references point into the document
+pub async fn check_change(backend :&crate::Backend, uri :&Url) {
+    backend.update_dependencies(uri);
+    let mut touched :Vec<Url> = vec![];
+    for checker in backend.checkers.iter().filter(|c| c.on_change()) {
+        touched.extend(checker::run(backend, checker, uri, None).await);
+    }
+    publish_all(backend, touched).await;
 }
-#[derive(Clone)]
-pub enum DiagnosticSourceData {
-    LanguageTool(language_tool::LTDiagnostic),
-    other,
+// Runs the native checkers on every file in the project of the document, open or not, and
+// publishes the diagnostics of each file. A file a checker already covered while checking
+// another one is not checked again.
+pub async fn check_project(backend :&crate::Backend, uri :&Url) {
+    backend.update_dependencies(uri);
+    let files = backend.project_files(uri);
+    let mut touched :Vec<Url> = vec![];
+    for checker in backend.checkers.iter().filter(|c| c.on_change()) {
+        let mut covered :HashSet<Url> = HashSet::new();
+        for file in &files {
+            if covered.contains(file) {
+                continue;
+            }
+            let checked = checker::run(backend, checker, file, None).await;
+            covered.extend(checked.iter().cloned());
+            touched.extend(checked);
+        }
+    }
+    touched.extend(files);
+    publish_all(backend, touched).await;
 }
-pub enum CodeActionSource {
-    LanguageToolCheckText(language_tool::LTCodeActionCheckText),
-    LanguageToolRemoveDiagnostics(language_tool::LTCodeActionRemoveDiagnostic),
+async fn publish_all(backend :&crate::Backend, mut files :Vec<Url>) {
+    files.sort();
+    files.dedup();
+    for file in files {
+        publish_file_diagnostics(backend, &file).await;
+    }
 }
-
-pub async fn send_diagnostics(client :&tower_lsp::Client, document :&crate::parse::Document, uri :&tower_lsp::lsp_types::Url) {
-    client.publish_diagnostics(uri.clone(), get_lsp_diagnostics(document), None).await;
+// Runs one checker on a file and publishes every file it touched
+pub async fn run_checker(backend :&crate::Backend, name :&str, uri :&Url, range :Option<Range<usize>>) {
+    let checker = match backend.checkers.get(name) {
+        Some(c) => c,
+        None => return,
+    };
+    for touched in checker::run(backend, checker, uri, range).await {
+        publish_file_diagnostics(backend, &touched).await;
+    }
 }
 
-pub async fn code_actions(client :&tower_lsp::Client, document :&crate::parse::Document, params :&tower_lsp::lsp_types::CodeActionParams) 
+pub async fn code_actions(backend :&crate::Backend, document :&crate::parse::Document, params :&tower_lsp::lsp_types::CodeActionParams) 
     -> Vec<tower_lsp::lsp_types::CodeActionOrCommand> {
     let mut code_action_respone :Vec<tower_lsp::lsp_types::CodeActionOrCommand> = vec![];
     let range = match document.lsp_range_to_byte_range(&params.range) {
         Some(c) => {c},
         None => {return Vec::new();}
     };
-    client.log_message(tower_lsp::lsp_types::MessageType::LOG, "LT CODEACTIONS").await;
-    let mut lt_actions = language_tool::code_actions(client, document, params.text_document.uri.clone(), &range).await;
-    code_action_respone.append(&mut lt_actions.0);
-    for l in lt_actions.1 {
-        CODE_ACTIONS.insert(l.0, l.1);
+    let uri = &params.text_document.uri;
+    let config = crate::settings::config_for(uri);
+    for checker in backend.checkers.iter().filter(|c| checker::is_enabled(c.name(), &config)) {
+        code_action_respone.append(&mut checker.code_actions(backend, document, uri, &range).await);
+    }
+    let only = params.context.only.as_ref();
+    code_action_respone.append(&mut fix_all::code_actions(backend, document, uri, &range, only));
+    // A client asking for some kinds, like source.fixAll on save, gets only those
+    if let Some(only) = only {
+        code_action_respone.retain(|c| match c {
+            tower_lsp::lsp_types::CodeActionOrCommand::CodeAction(a) => a.kind.as_ref()
+                .is_some_and(|k| only.iter().any(|x| k.as_str().starts_with(x.as_str()))),
+            tower_lsp::lsp_types::CodeActionOrCommand::Command(_) => false,
+        });
     }
 
     code_action_respone
 }
-pub async fn code_action_resolve(
+pub async fn execute_command(
     params: &tower_lsp::lsp_types::ExecuteCommandParams, 
     backend: &crate::Backend, ) {
-    backend.client.log_message(MessageType::INFO, format!("Code action resolve")).await;
-    let code_action_ref = match CODE_ACTIONS.get(&params.command) {
-        Some(c) => c,
-        None => {
-            backend.client.log_message(MessageType::INFO, format!("Code action not found, returning")).await;
-            return
+    backend.client.log_message(MessageType::INFO, format!("Execute command {}", params.command)).await;
+    if params.command == CHECK_WORKSPACE_COMMAND {
+        workspace::check_workspace(backend, params.work_done_progress_params.work_done_token.clone()).await;
+        return;
+    }
+    if params.command == baseline::WRITE_COMMAND || params.command == baseline::PRUNE_COMMAND {
+        update_baselines(backend, params.command == baseline::WRITE_COMMAND).await;
+        return;
+    }
+    for checker in backend.checkers.iter() {
+        if checker.execute(backend, params).await {
+            return;
+        }
+    }
+    backend.client.log_message(MessageType::INFO, "Code action not found, returning".to_string()).await;
+
+}
+// Runs the checkers that run when a file is opened or saved and publishes every file they touched.
+// A saved bibliography has every open document using it checked again.
+pub async fn check_saved(backend :&crate::Backend, uri :&Url) {
+    if bibliography::is_bibliography(uri) {
+        check_bibliography(backend, uri).await;
+        return;
+    }
+    for checker in backend.checkers.iter().filter(|c| !c.on_change() && !c.on_demand()) {
+        for touched in checker::run(backend, checker, uri, None).await {
+            publish_file_diagnostics(backend, &touched).await;
         }
+    }
+}
+// Validates the bibliographies of a typst document, or of every open typst document when a
+// bibliography file is given, and publishes the result for every file involved
+async fn check_bibliography(backend :&crate::Backend, uri :&Url) {
+    let documents :Vec<Url> = if bibliography::is_bibliography(uri) {
+        backend.document_map.iter()
+            .map(|c| c.key().clone())
+            .filter(|c| !bibliography::is_bibliography(c))
+            .collect()
+    } else {
+        vec![uri.clone()]
+    };
+    for document in documents {
+        run_checker(backend, bibliography::NAME, &document, None).await;
+    }
+}
+// Publishes the stored diagnostics of a file, reading it from disk when it is not open. A client
+// that pulls diagnostics is asked to pull again instead.
+pub async fn publish_file_diagnostics(backend :&crate::Backend, uri :&Url) {
+    // The suggestion hints follow the diagnostics
+    inlay_hints::refresh(backend);
+    if pull::enabled() {
+        pull::refresh(backend);
+        return;
+    }
+    let diagnostics = match backend.with_document(uri, |c| get_lsp_diagnostics(c, uri)) {
+        Some(c) => c,
+        None => return,
     };
-    let code_action :&CodeActionSource = code_action_ref.deref();
-    backend.client.log_message(MessageType::INFO, format!("Code action found")).await;
-    match code_action {
-        CodeActionSource::LanguageToolCheckText(l) => {
-            backend.client.log_message(MessageType::INFO, format!("Check text")).await;
-            let (uri, diagnostics) = language_tool::code_action_check_text(backend, l).await.unwrap();
-            remove_lsp_diagnostics_of_type(DiagnosticSource::LanguageTool);
-            DIAGNOSTICS.lock().unwrap().extend(diagnostics);
-            let working_doc_ref = backend.document_map.get(&uri).unwrap();
-            let working_doc = working_doc_ref.deref().to_owned();
-            send_diagnostics(&backend.client, working_doc, &l.uri).await;
-        },
-        CodeActionSource::LanguageToolRemoveDiagnostics(l) => {
-            language_tool::remove_diagnostics();
-            let working_doc_ref = backend.document_map.get(&l.uri).unwrap();
-            let working_doc = working_doc_ref.deref().to_owned();
-            send_diagnostics(&backend.client, working_doc, &l.uri).await;
-        },
-    }
-    backend.client.log_message(MessageType::INFO, format!("Returning")).await;
+    backend.client.publish_diagnostics(uri.clone(), diagnostics, None).await;
+}
+// A quick fix title and the byte range edits in the document it applies
+pub type QuickFix = (String, Vec<(Range<usize>, String)>);
+// What codeAction/resolve needs to find a quick fix again
+#[derive(Serialize, Deserialize)]
+struct FixData {
+    checker: String,
+    uri: Url,
+    version: isize,
+    range: Range<usize>,
+    title: String,
+}
 
+// Turns the quick fixes stored in the diagnostics of `source` that cover `range` into code actions
+fn quick_fix_actions(
+    document :&parse::Document,
+    uri :&Url,
+    range :&Range<usize>,
+    source :&str,
+    fixes_of :impl Fn(&Diagnostic) -> Vec<QuickFix>,
+    ) -> Vec<tower_lsp::lsp_types::CodeActionOrCommand> {
+    let hovering :Vec<Diagnostic> = DIAGNOSTICS
+        .lock().unwrap()
+        .iter()
+        .filter(|x| x.source == source && &x.uri == uri)
+        .filter(|x| match document.correct_range(x.version, x.range.clone()) {
+            Some(c) => c.start <= range.start && range.end <= c.end,
+            None => false,
+        })
+        .cloned()
+        .collect();
+    let lazy = RESOLVE_EDITS.load(Ordering::Relaxed);
+    let mut out = vec![];
+    for diagnostic in hovering {
+        let corrected = match document.correct_range(diagnostic.version, diagnostic.range.clone()) {
+            Some(c) => c,
+            None => continue,
+        };
+        for (title, fix) in fixes_of(&diagnostic) {
+            // The edit is made in codeAction/resolve when the client picks the fix
+            let (edit, data) = if lazy {
+                let data = FixData {
+                    checker: source.to_string(),
+                    uri: uri.clone(),
+                    version: document.latest_version,
+                    range: corrected.clone(),
+                    title: title.clone(),
+                };
+                (None, serde_json::to_value(data).ok())
+            } else {
+                match fix_edit(document, uri, &diagnostic, &fix) {
+                    Some(c) => (Some(c), None),
+                    None => continue,
+                }
+            };
+            out.push(tower_lsp::lsp_types::CodeActionOrCommand::CodeAction(tower_lsp::lsp_types::CodeAction {
+                title,
+                kind: Some(tower_lsp::lsp_types::CodeActionKind::QUICKFIX),
+                diagnostics: diagnostic.corrected_diagnostics_lsp(document).map(|c| vec![c]),
+                edit,
+                command: None,
+                is_preferred: None,
+                disabled: None,
+                data,
+            }));
+        }
+    }
+    out
+}
+// The edits of a quick fix, None when the document changed so much they no longer apply
+fn fix_edit(document :&parse::Document, uri :&Url, diagnostic :&Diagnostic, fix :&[(Range<usize>, String)])
+    -> Option<tower_lsp::lsp_types::WorkspaceEdit> {
+    let edits :Vec<tower_lsp::lsp_types::TextEdit> = fix.iter()
+        .filter_map(|(edit_range, new_text)| {
+            let corrected = document.correct_range(diagnostic.version, edit_range.clone())?;
+            Some(tower_lsp::lsp_types::TextEdit {
+                range: document.byte_range_to_lsp_range(&corrected)?,
+                new_text: new_text.clone(),
+            })
+        })
+        .collect();
+    if edits.is_empty() || edits.len() != fix.len() {
+        return None;
+    }
+    let mut changes :std::collections::HashMap<Url, Vec<tower_lsp::lsp_types::TextEdit>> = std::collections::HashMap::new();
+    changes.insert(uri.clone(), edits);
+    Some(tower_lsp::lsp_types::WorkspaceEdit {
+        changes: Some(changes),
+        document_changes: None,
+        change_annotations: None,
+    })
+}
+// Remembers if the client fills in the edit of code actions with codeAction/resolve
+pub fn set_resolve_support(capabilities :&tower_lsp::lsp_types::ClientCapabilities) {
+    let supported = capabilities.text_document.as_ref()
+        .and_then(|c| c.code_action.as_ref())
+        .and_then(|c| c.resolve_support.as_ref())
+        .is_some_and(|c| c.properties.iter().any(|x| x == "edit"));
+    RESOLVE_EDITS.store(supported, Ordering::Relaxed);
+}
+// Remembers if the client pulls diagnostics with textDocument/diagnostic
+pub fn set_pull_support(capabilities :&tower_lsp::lsp_types::ClientCapabilities) {
+    pull::set_support(capabilities);
 }
-pub fn get_lsp_diagnostics(document :&crate::parse::Document) -> Vec<tower_lsp::lsp_types::Diagnostic> {
+pub fn pulls_diagnostics() -> bool {
+    pull::enabled()
+}
+pub fn refresh_diagnostics(backend :&crate::Backend) {
+    pull::refresh(backend);
+}
+pub fn document_diagnostic_report(backend :&crate::Backend, uri :&Url, previous_result_id :Option<&str>)
+    -> tower_lsp::lsp_types::DocumentDiagnosticReport {
+    pull::document_report(backend, uri, previous_result_id)
+}
+pub fn workspace_diagnostic_report(backend :&crate::Backend, previous :&[tower_lsp::lsp_types::PreviousResultId])
+    -> tower_lsp::lsp_types::WorkspaceDiagnosticReport {
+    pull::workspace_report(backend, previous)
+}
+// Remembers if the client fills in inlay hint tooltips with inlayHint/resolve and can be asked to
+// get the hints again
+pub fn set_inlay_hint_support(capabilities :&tower_lsp::lsp_types::ClientCapabilities) {
+    inlay_hints::set_support(capabilities);
+}
+pub fn inlay_hints(document :&parse::Document, uri :&Url, range :&Range<usize>)
+    -> Vec<tower_lsp::lsp_types::InlayHint> {
+    inlay_hints::hints(document, uri, range)
+}
+pub fn resolve_inlay_hint(backend :&crate::Backend, hint :tower_lsp::lsp_types::InlayHint) -> tower_lsp::lsp_types::InlayHint {
+    inlay_hints::resolve(backend, hint)
+}
+// The headings, figures, tables, labels and bibliographies of a document as a tree
+pub fn document_symbols(backend :&crate::Backend, document :&parse::Document, uri :&Url)
+    -> Vec<tower_lsp::lsp_types::DocumentSymbol> {
+    outline::document_symbols(backend, document, uri)
+}
+pub fn workspace_symbols(backend :&crate::Backend, query :&str) -> Vec<tower_lsp::lsp_types::SymbolInformation> {
+    outline::workspace_symbols(backend, query)
+}
+pub fn folding_ranges(document :&parse::Document) -> Vec<tower_lsp::lsp_types::FoldingRange> {
+    folding::folding_ranges(document)
+}
+pub fn definition(backend :&crate::Backend, uri :&Url, position :tower_lsp::lsp_types::Position)
+    -> Option<Vec<tower_lsp::lsp_types::Location>> {
+    navigation::definition(backend, uri, position)
+}
+pub fn references(backend :&crate::Backend, uri :&Url, position :tower_lsp::lsp_types::Position, include_declaration :bool)
+    -> Option<Vec<tower_lsp::lsp_types::Location>> {
+    navigation::references(backend, uri, position, include_declaration)
+}
+pub fn prepare_rename(backend :&crate::Backend, uri :&Url, position :tower_lsp::lsp_types::Position)
+    -> Option<tower_lsp::lsp_types::PrepareRenameResponse> {
+    navigation::prepare_rename(backend, uri, position)
+}
+pub fn rename(backend :&crate::Backend, uri :&Url, position :tower_lsp::lsp_types::Position, new_name :&str)
+    -> Result<Option<tower_lsp::lsp_types::WorkspaceEdit>, String> {
+    navigation::rename(backend, uri, position, new_name)
+}
+pub fn selection_ranges(document :&parse::Document, positions :&[tower_lsp::lsp_types::Position])
+    -> Vec<tower_lsp::lsp_types::SelectionRange> {
+    selection::selection_ranges(document, positions)
+}
+// Makes the edit of a quick fix handed out without one. The fix is found again from the stored
+// diagnostic at the range the action was made for, the action is returned as is when it is gone.
+pub fn resolve_code_action(backend :&crate::Backend, mut action :tower_lsp::lsp_types::CodeAction)
+    -> tower_lsp::lsp_types::CodeAction {
+    let data :FixData = match action.data.clone().and_then(|c| serde_json::from_value(c).ok()) {
+        Some(c) => c,
+        None => return action,
+    };
+    let checker = match backend.checkers.get(&data.checker) {
+        Some(c) => c,
+        None => return action,
+    };
+    let edit = backend.with_document(&data.uri, |document| {
+        let range = document.correct_range(data.version, data.range.clone())?;
+        let diagnostic = DIAGNOSTICS.lock().unwrap().iter()
+            .filter(|x| x.source == checker.name() && x.uri == data.uri)
+            .find(|x| document.correct_range(x.version, x.range.clone()) == Some(range.clone()))
+            .cloned()?;
+        let (_, fix) = checker.quick_fixes(&diagnostic).into_iter().find(|c| c.0 == data.title)?;
+        fix_edit(document, &data.uri, &diagnostic, &fix)
+    }).flatten();
+    action.edit = edit;
+    action
+}
+// Writes or prunes the baseline of every workspace folder and republishes the open documents
+async fn update_baselines(backend :&crate::Backend, write :bool) {
+    let roots :Vec<std::path::PathBuf> = backend.project.roots.read().unwrap().clone();
+    for root in roots {
+        let path = root.join(baseline::FILE_NAME);
+        let message = if write {
+            match baseline::write(backend, &path) {
+                Some(c) => format!("Wrote {} issues to {}", c, path.display()),
+                None => format!("Could not write {}", path.display()),
+            }
+        } else {
+            match baseline::prune(backend, &path) {
+                Some(c) => format!("Pruned {} fixed issues from {}", c, path.display()),
+                None => format!("No baseline in {}", root.display()),
+            }
+        };
+        backend.client.show_message(MessageType::INFO, message).await;
+    }
+    let open :Vec<Url> = backend.document_map.iter().map(|c| c.key().clone()).collect();
+    for uri in open {
+        publish_file_diagnostics(backend, &uri).await;
+    }
+}
+// Drops the diagnostics the configuration no longer allows, those of checkers turned off in
+// `checkers` and what each checker filters itself
+pub fn refilter(backend :&crate::Backend) {
+    DIAGNOSTICS.lock().unwrap()
+        .retain(|x| checker::is_enabled(x.source, &crate::settings::config_for(&x.uri)));
+    for checker in backend.checkers.iter() {
+        checker.refilter(backend);
+    }
+}
+// Keeps the managed language tool server running for as long as the language server runs
+pub fn start_language_tool(client :tower_lsp::Client) {
+    language_tool::server::set_client(client);
+    tokio::spawn(language_tool::server::watch());
+}
+pub async fn shutdown_language_tool() {
+    language_tool::server::shutdown().await;
+}
+// Loads the baseline of a workspace folder
+pub fn load_baseline(root :&std::path::Path) {
+    baseline::load(root);
+}
+pub fn unload_baseline(root :&std::path::Path) {
+    baseline::unload(root);
+}
+pub fn load_baseline_file(path :&std::path::Path) -> bool {
+    baseline::load_file(path)
+}
+pub fn write_baseline(backend :&crate::Backend, path :&std::path::Path) -> Option<usize> {
+    baseline::write(backend, path)
+}
+pub fn prune_baseline(backend :&crate::Backend, path :&std::path::Path) -> Option<usize> {
+    baseline::prune(backend, path)
+}
+// Counts a check that could not run
+fn check_failed() {
+    FAILED_CHECKS.fetch_add(1, Ordering::Relaxed);
+}
+pub fn failed_checks() -> usize {
+    FAILED_CHECKS.load(Ordering::Relaxed)
+}
+// Runs every checker on a file, used when there is no editor to ask for language tool checks
+pub async fn check_file(backend :&crate::Backend, uri :&Url) {
+    workspace::check_file(backend, uri).await;
+}
+// The stored diagnostics of every file, with ranges up to date with the documents
+pub fn stored_diagnostics(backend :&crate::Backend) -> Vec<(Url, Vec<tower_lsp::lsp_types::Diagnostic>)> {
+    let mut uris :Vec<Url> = DIAGNOSTICS.lock().unwrap().iter().map(|c| c.uri.clone()).collect();
+    uris.sort();
+    uris.dedup();
+    uris.into_iter()
+        .filter_map(|uri| {
+            let diagnostics = backend.with_document(&uri, |c| get_lsp_diagnostics(c, &uri))?;
+            Some((uri, diagnostics))
+        })
+        .collect()
+}
+pub fn cancel_progress(params :tower_lsp::lsp_types::WorkDoneProgressCancelParams) {
+    workspace::cancel(params);
+}
+pub fn reference_completion(backend :&crate::Backend, uri :&Url, position :tower_lsp::lsp_types::Position)
+    -> Option<Vec<tower_lsp::lsp_types::CompletionItem>> {
+    references::completion(backend, uri, position)
+}
+pub fn get_lsp_diagnostics(document :&crate::parse::Document, uri :&Url) -> Vec<tower_lsp::lsp_types::Diagnostic> {
     let vals: Vec<tower_lsp::lsp_types::Diagnostic> = DIAGNOSTICS
         .lock().unwrap()
         .iter()
+        .filter(|x| &x.uri == uri)
+        .filter(|x| !baseline::is_baselined(document, x))
         .filter_map(|x| {
-            if let Some(c) = x.corrected_diagnostics_lsp(&document) {
-            Some(c)
-            } else {
-            None
-            }
+            x.corrected_diagnostics_lsp(document)
         })
     .collect();
     vals
 }
-pub fn remove_lsp_diagnostics_of_type(typ :DiagnosticSource) {
-    DIAGNOSTICS.lock().unwrap().retain(|x| x.source!=typ);
-}
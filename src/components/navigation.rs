@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+use tower_lsp::lsp_types::{Location, Position, PrepareRenameResponse, TextEdit, Url, WorkspaceEdit};
+use typst_syntax::{LinkedNode, SyntaxKind};
+use super::bibliography;
+use super::references::{self, LabelFile};
+
+// What the cursor is on
+enum Target {
+    //A label in `<name>` or `@name`, found across the project
+    Label(String),
+    //An identifier bound with `#let`, as a parameter or a loop variable in the same file, with the
+    //range of the binding and every identifier that refers to it
+    Binding {
+        definition: Location,
+        uses: Vec<Location>,
+    },
+}
+
+// Where the label or `#let` binding at the cursor is defined. A reference to a bibliography entry
+// goes to the entry.
+pub fn definition(backend :&crate::Backend, uri :&Url, position :Position) -> Option<Vec<Location>> {
+    let (target, _) = target_at(backend, uri, position)?;
+    let name = match target {
+        Target::Label(c) => c,
+        Target::Binding { definition, .. } => return Some(vec![definition]),
+    };
+    let files = references::project_index(backend, uri);
+    let found :Vec<Location> = label_definitions(backend, &files, &name);
+    if !found.is_empty() {
+        return Some(found);
+    }
+    bibliography_entry(backend, &files, &name).map(|c| vec![c])
+}
+// Every use of the label or `#let` binding at the cursor, the definition too if asked for
+pub fn references(backend :&crate::Backend, uri :&Url, position :Position, include_declaration :bool) -> Option<Vec<Location>> {
+    let (target, _) = target_at(backend, uri, position)?;
+    match target {
+        Target::Label(name) => {
+            let files = references::project_index(backend, uri);
+            let mut out = vec![];
+            if include_declaration {
+                out.extend(label_definitions(backend, &files, &name));
+            }
+            out.extend(files.iter()
+                .flat_map(|file| file.refs.iter())
+                .filter(|c| c.name == name)
+                .map(|c| c.location.clone()));
+            Some(out)
+        },
+        Target::Binding { definition, uses } => {
+            Some(uses.into_iter().filter(|c| include_declaration || c != &definition).collect())
+        },
+    }
+}
+// The range of the name at the cursor if it can be renamed
+pub fn prepare_rename(backend :&crate::Backend, uri :&Url, position :Position) -> Option<PrepareRenameResponse> {
+    let (target, range) = target_at(backend, uri, position)?;
+    if let Target::Label(name) = &target {
+        let files = references::project_index(backend, uri);
+        if label_definitions(backend, &files, name).is_empty() {
+            return None;
+        }
+    }
+    Some(PrepareRenameResponse::Range(range.range))
+}
+// Renames the label or `#let` binding at the cursor everywhere it is used. Err tells why the new
+// name can not be used.
+pub fn rename(backend :&crate::Backend, uri :&Url, position :Position, new_name :&str) -> Result<Option<WorkspaceEdit>, String> {
+    let (target, _) = match target_at(backend, uri, position) {
+        Some(c) => c,
+        None => return Ok(None),
+    };
+    let locations = match target {
+        Target::Label(name) => {
+            if !is_label_name(new_name) {
+                return Err(format!("\"{}\" is not a valid label name", new_name));
+            }
+            let files = references::project_index(backend, uri);
+            if label_definitions(backend, &files, &name).is_empty() {
+                return Err(format!("<{}> is not defined in the project, a bibliography entry is renamed in its file", name));
+            }
+            if new_name != name && !label_definitions(backend, &files, new_name).is_empty() {
+                return Err(format!("The label <{}> exists already", new_name));
+            }
+            label_name_locations(backend, &files, &name)
+        },
+        Target::Binding { uses, .. } => {
+            if !is_identifier(new_name) {
+                return Err(format!("\"{}\" is not a valid identifier", new_name));
+            }
+            uses
+        },
+    };
+    let mut changes :HashMap<Url, Vec<TextEdit>> = HashMap::new();
+    for location in locations {
+        changes.entry(location.uri).or_default().push(TextEdit {
+            range: location.range,
+            new_text: new_name.to_string(),
+        });
+    }
+    Ok(Some(WorkspaceEdit {
+        changes: Some(changes),
+        document_changes: None,
+        change_annotations: None,
+    }))
+}
+// The label or binding at the cursor and the range of its name there
+fn target_at(backend :&crate::Backend, uri :&Url, position :Position) -> Option<(Target, Location)> {
+    backend.with_document(uri, |document| {
+        let offset = document.typst_source.line_column_to_byte(position.line as usize, position.character as usize)?;
+        let root = LinkedNode::new(document.typst_source.root());
+        let node = node_at(&root, offset)?;
+        let location = |range :Range<usize>| Some(Location {
+            uri: uri.clone(),
+            range: document.byte_range_to_lsp_range(&range)?,
+        });
+        match node.kind() {
+            SyntaxKind::Label => {
+                let name = node.get().text().trim_start_matches('<').trim_end_matches('>').to_string();
+                let start = node.offset() + 1;
+                Some((Target::Label(name.clone()), location(start..start + name.len())?))
+            },
+            SyntaxKind::RefMarker => {
+                let name = node.get().text().trim_start_matches('@').to_string();
+                let start = node.offset() + 1;
+                Some((Target::Label(name.clone()), location(start..start + name.len())?))
+            },
+            SyntaxKind::Ident => {
+                let mut resolved = vec![];
+                resolve(&root, &mut vec![vec![]], &mut resolved);
+                // Argument names, fields and what typst defines itself are not bound in the file
+                let definition = resolved.iter().find(|c| c.0 == node.range())?.1.clone();
+                let uses :Option<Vec<Location>> = resolved.into_iter()
+                    .filter(|c| c.1 == definition)
+                    .map(|c| location(c.0))
+                    .collect();
+                Some((Target::Binding {
+                    definition: location(definition)?,
+                    uses: uses?,
+                }, location(node.range())?))
+            },
+            _ => None,
+        }
+    }).flatten()
+}
+// The label, reference marker or identifier at the offset
+fn node_at<'a>(node :&LinkedNode<'a>, offset :usize) -> Option<LinkedNode<'a>> {
+    if offset < node.range().start || offset > node.range().end {
+        return None;
+    }
+    match node.kind() {
+        SyntaxKind::Label | SyntaxKind::RefMarker | SyntaxKind::Ident => return Some(node.clone()),
+        SyntaxKind::Raw
+        | SyntaxKind::LineComment
+        | SyntaxKind::BlockComment => return None,
+        _ => {},
+    }
+    node.children().find_map(|c| node_at(&c, offset))
+}
+// Every identifier that refers to a binding of the file with the range of the name it refers to,
+// the names in the bindings refer to themselves. The tree is walked in the order typst evaluates
+// it so an identifier refers to the innermost binding before it.
+fn resolve(node :&LinkedNode, scopes :&mut Vec<Vec<(String, Range<usize>)>>, out :&mut Vec<(Range<usize>, Range<usize>)>) {
+    match node.kind() {
+        SyntaxKind::Ident => {
+            let is_field = node.prev_sibling().map(|c| c.kind()) == Some(SyntaxKind::Dot);
+            let is_argument_name = node.parent_kind() == Some(SyntaxKind::Named) && node.index() == 0;
+            if is_field || is_argument_name {
+                return;
+            }
+            let name = node.get().text();
+            let binding = scopes.iter().rev()
+                .find_map(|scope| scope.iter().rev().find(|c| c.0 == name.as_str()));
+            if let Some(c) = binding {
+                out.push((node.range(), c.1.clone()));
+            }
+        },
+        SyntaxKind::LetBinding => {
+            // `let` pattern `=` value
+            let mut children = node.children().filter(|c| !c.kind().is_trivia()).skip(1);
+            let pattern = match children.next() {
+                Some(c) => c,
+                None => return,
+            };
+            if pattern.kind() == SyntaxKind::Closure {
+                // A function can call itself
+                if let Some(name) = pattern.children().find(|c| c.kind() == SyntaxKind::Ident) {
+                    bind(&name, scopes, out);
+                }
+                resolve(&pattern, scopes, out);
+                return;
+            }
+            // The value is evaluated before the name is bound, `let x = x + 1` uses the x before
+            for child in children {
+                resolve(&child, scopes, out);
+            }
+            bind_pattern(&pattern, scopes, out);
+        },
+        SyntaxKind::Closure => {
+            let params = node.children().find(|c| c.kind() == SyntaxKind::Params);
+            // Default values are evaluated where the closure is made
+            for param in params.iter().flat_map(|c| c.children()).filter(|c| c.kind() == SyntaxKind::Named) {
+                for child in param.children().skip(1) {
+                    resolve(&child, scopes, out);
+                }
+            }
+            scopes.push(vec![]);
+            let mut in_body = false;
+            for child in node.children() {
+                match child.kind() {
+                    SyntaxKind::Params => {
+                        for param in child.children() {
+                            bind_pattern(&param, scopes, out);
+                        }
+                        in_body = true;
+                    },
+                    // The name of `let f(..) = ..` is bound by the binding
+                    _ if !in_body => {},
+                    _ => resolve(&child, scopes, out),
+                }
+            }
+            scopes.pop();
+        },
+        SyntaxKind::ForLoop => {
+            // `for` pattern `in` iterable body
+            let mut children = node.children()
+                .filter(|c| !c.kind().is_trivia() && !matches!(c.kind(), SyntaxKind::For | SyntaxKind::In));
+            let (pattern, iterable, body) = match (children.next(), children.next(), children.next()) {
+                (Some(a), Some(b), Some(c)) => (a, b, c),
+                _ => return,
+            };
+            resolve(&iterable, scopes, out);
+            scopes.push(vec![]);
+            bind_pattern(&pattern, scopes, out);
+            resolve(&body, scopes, out);
+            scopes.pop();
+        },
+        SyntaxKind::CodeBlock | SyntaxKind::ContentBlock => {
+            scopes.push(vec![]);
+            for child in node.children() {
+                resolve(&child, scopes, out);
+            }
+            scopes.pop();
+        },
+        _ => {
+            for child in node.children() {
+                resolve(&child, scopes, out);
+            }
+        },
+    }
+}
+// Binds the names in a `let`, parameter or loop pattern in the innermost scope
+fn bind_pattern(node :&LinkedNode, scopes :&mut Vec<Vec<(String, Range<usize>)>>, out :&mut Vec<(Range<usize>, Range<usize>)>) {
+    match node.kind() {
+        SyntaxKind::Ident => bind(node, scopes, out),
+        // A parameter with a default value
+        SyntaxKind::Named if node.parent_kind() == Some(SyntaxKind::Params) => {
+            if let Some(c) = node.children().find(|c| c.kind() == SyntaxKind::Ident) {
+                bind(&c, scopes, out);
+            }
+        },
+        // `(key: pattern)`, the key is a field of the dictionary
+        SyntaxKind::Named => {
+            if let Some(c) = node.children().filter(|c| !c.kind().is_trivia()).last() {
+                bind_pattern(&c, scopes, out);
+            }
+        },
+        SyntaxKind::Destructuring
+        | SyntaxKind::Parenthesized
+        | SyntaxKind::Spread => {
+            for child in node.children() {
+                bind_pattern(&child, scopes, out);
+            }
+        },
+        _ => {},
+    }
+}
+fn bind(node :&LinkedNode, scopes :&mut [Vec<(String, Range<usize>)>], out :&mut Vec<(Range<usize>, Range<usize>)>) {
+    if let Some(scope) = scopes.last_mut() {
+        scope.push((node.get().text().to_string(), node.range()));
+    }
+    out.push((node.range(), node.range()));
+}
+fn label_definitions(backend :&crate::Backend, files :&[Arc<LabelFile>], name :&str) -> Vec<Location> {
+    files.iter()
+        .flat_map(|file| file.labels.iter().filter(move |c| c.name == name).map(move |c| (&file.uri, c.range.clone())))
+        .filter_map(|(uri, range)| name_location(backend, uri, range, name))
+        .collect()
+}
+// Where the name is in each definition and use of the label, without `<`, `>` and `@`
+fn label_name_locations(backend :&crate::Backend, files :&[Arc<LabelFile>], name :&str) -> Vec<Location> {
+    let mut out = label_definitions(backend, files, name);
+    out.extend(files.iter()
+        .flat_map(|file| file.refs.iter().filter(move |c| c.name == name).map(move |c| (&file.uri, c.range.clone())))
+        .filter_map(|(uri, range)| name_location(backend, uri, range, name)));
+    out
+}
+// The name inside a `<name>` or `@name` that starts at range
+fn name_location(backend :&crate::Backend, uri :&Url, range :Range<usize>, name :&str) -> Option<Location> {
+    let start = range.start + 1;
+    backend.with_document(uri, |c| c.byte_range_to_lsp_range(&(start..start + name.len())))
+        .flatten()
+        .map(|c| Location {
+            uri: uri.clone(),
+            range: c,
+        })
+}
+// The key of the entry in the bibliographies of the project
+fn bibliography_entry(backend :&crate::Backend, files :&[Arc<LabelFile>], key :&str) -> Option<Location> {
+    for uri in references::bibliography_uris(backend, files) {
+        let loaded = match bibliography::parsed(backend, &uri) {
+            Some(c) => c,
+            None => continue,
+        };
+        if let Some(entry) = loaded.entries.iter().find(|c| c.key == key) {
+            let range = backend.with_document(&uri, |c| c.byte_range_to_lsp_range(&entry.key_range)).flatten()?;
+            return Some(Location {
+                uri,
+                range,
+            });
+        }
+    }
+    None
+}
+// The characters typst allows in a label that can also be referenced with `@`
+fn is_label_name(name :&str) -> bool {
+    !name.is_empty()
+        && name.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | ':' | '.'))
+        // A reference ending in punctuation leaves it out of the name
+        && !name.ends_with(['.', ':'])
+}
+fn is_identifier(name :&str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => {},
+        _ => return false,
+    }
+    chars.all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
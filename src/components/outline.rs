@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use tower_lsp::lsp_types::{DocumentSymbol, Location, SymbolInformation, SymbolKind, Url};
+use typst_syntax::{LinkedNode, SyntaxKind, SyntaxNode};
+use super::bibliography::{self, Bibliography};
+use super::{baseline, checker, heading, inlay_hints, references, DIAGNOSTICS};
+use crate::parse;
+
+// A symbol before it is put in the tree, headings hold the symbols up to the next heading of the
+// same or a higher level
+struct Item {
+    //The heading level, None for everything else
+    level: Option<usize>,
+    symbol: DocumentSymbol,
+}
+// A figure, table or bibliography found in the syntax tree
+struct Found {
+    name: String,
+    kind: SymbolKind,
+    range: Range<usize>,
+    children: Vec<DocumentSymbol>,
+}
+
+// The outline of a document: its headings with the figures, tables, labels and bibliographies of
+// each section inside them. A bibliography file lists its entries.
+pub fn document_symbols(backend :&crate::Backend, document :&parse::Document, uri :&Url) -> Vec<DocumentSymbol> {
+    symbols(backend, document, uri, true)
+}
+// The symbols of a document, `details` adds the word and issue counts to the headings
+fn symbols(backend :&crate::Backend, document :&parse::Document, uri :&Url, details :bool) -> Vec<DocumentSymbol> {
+    if bibliography::is_bibliography(uri) {
+        let bibliography = Bibliography::parse(uri, document.typst_source.text());
+        return bibliography.entries.iter()
+            .filter_map(|c| symbol(document, c.key.clone(), Some(c.describe()), SymbolKind::FIELD, &c.range, &c.key_range))
+            .collect();
+    }
+    let sections = heading::sections(document);
+    let mut words = vec![];
+    let mut issues = vec![];
+    if details {
+        let ranges :Vec<Range<usize>> = sections.iter().map(|c| c.1.clone()).collect();
+        words = inlay_hints::words_in(document, &ranges);
+        issues = issue_offsets(document, uri);
+    }
+    let mut items :Vec<Item> = vec![];
+    for (i, (heading, section)) in sections.iter().enumerate() {
+        let name = match &heading.number {
+            Some(c) => format!("{} {}", c, heading.title),
+            None => heading.title.clone(),
+        };
+        let detail = words.get(i).map(|words| {
+            let open = issues.iter().filter(|c| section.contains(*c)).count();
+            format!(
+                "{} {}, {} {}",
+                words,
+                if *words == 1 {"word"} else {"words"},
+                open,
+                if open == 1 {"issue"} else {"issues"},
+            )
+        });
+        if let Some(symbol) = symbol(document, name, detail, SymbolKind::NAMESPACE, section, &heading.body_range) {
+            items.push(Item {
+                level: Some(heading.level),
+                symbol,
+            });
+        }
+    }
+    let mut found = vec![];
+    let mut numbers :HashMap<String, usize> = HashMap::new();
+    walk(backend, document, uri, &LinkedNode::new(document.typst_source.root()), &mut numbers, &mut found);
+    for c in found {
+        if let Some(mut symbol) = symbol(document, c.name, None, c.kind, &c.range, &c.range) {
+            if !c.children.is_empty() {
+                symbol.children = Some(c.children);
+            }
+            items.push(Item {
+                level: None,
+                symbol,
+            });
+        }
+    }
+    for label in references::collect_file(document, uri).labels {
+        let name = format!("<{}>", label.name);
+        if let Some(symbol) = symbol(document, name, label.detail, SymbolKind::KEY, &label.range, &label.range) {
+            items.push(Item {
+                level: None,
+                symbol,
+            });
+        }
+    }
+    tree(items)
+}
+// The symbols of every file in the projects of the open documents whose name matches the query
+pub fn workspace_symbols(backend :&crate::Backend, query :&str) -> Vec<SymbolInformation> {
+    let open :Vec<Url> = backend.document_map.iter().map(|c| c.key().clone()).collect();
+    let mut files :Vec<Url> = open.iter().flat_map(|c| backend.project_files(c)).collect();
+    files.sort();
+    files.dedup();
+    let mut out = vec![];
+    for uri in files {
+        // Without details, counting the words of every file in the project is too slow to do for each query
+        let symbols = match backend.with_document(&uri, |c| symbols(backend, c, &uri, false)) {
+            Some(c) => c,
+            None => continue,
+        };
+        flatten(&uri, &symbols, None, query, &mut out);
+    }
+    out
+}
+#[allow(deprecated)]
+fn flatten(uri :&Url, symbols :&[DocumentSymbol], container :Option<&str>, query :&str, out :&mut Vec<SymbolInformation>) {
+    for symbol in symbols {
+        if matches(&symbol.name, query) {
+            out.push(SymbolInformation {
+                name: symbol.name.clone(),
+                kind: symbol.kind,
+                tags: None,
+                deprecated: None,
+                location: Location {
+                    uri: uri.clone(),
+                    range: symbol.selection_range,
+                },
+                container_name: container.map(|c| c.to_string()),
+            });
+        }
+        if let Some(children) = &symbol.children {
+            flatten(uri, children, Some(&symbol.name), query, out);
+        }
+    }
+}
+// If the letters of the query appear in the name in order, ignoring case
+fn matches(name :&str, query :&str) -> bool {
+    let mut name = name.chars().flat_map(|c| c.to_lowercase());
+    query.chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(|c| c.to_lowercase())
+        .all(|c| name.any(|x| x == c))
+}
+// Puts each symbol in the innermost heading it comes after
+fn tree(mut items :Vec<Item>) -> Vec<DocumentSymbol> {
+    // A heading goes before the label inside it
+    items.sort_by_key(|c| (c.symbol.range.start, c.level.is_none()));
+    let mut roots :Vec<DocumentSymbol> = vec![];
+    let mut open :Vec<(usize, DocumentSymbol)> = vec![];
+    for item in items {
+        match item.level {
+            Some(level) => {
+                while open.last().is_some_and(|c| c.0 >= level) {
+                    close(&mut open, &mut roots);
+                }
+                open.push((level, item.symbol));
+            },
+            None => match open.last_mut() {
+                Some(c) => c.1.children.get_or_insert_with(Vec::new).push(item.symbol),
+                None => roots.push(item.symbol),
+            },
+        }
+    }
+    while !open.is_empty() {
+        close(&mut open, &mut roots);
+    }
+    roots
+}
+fn close(open :&mut Vec<(usize, DocumentSymbol)>, roots :&mut Vec<DocumentSymbol>) {
+    let (_, symbol) = match open.pop() {
+        Some(c) => c,
+        None => return,
+    };
+    match open.last_mut() {
+        Some(c) => c.1.children.get_or_insert_with(Vec::new).push(symbol),
+        None => roots.push(symbol),
+    }
+}
+// Figures and tables numbered by kind like typst does, and the bibliographies with their entries
+fn walk(
+    backend :&crate::Backend,
+    document :&parse::Document,
+    uri :&Url,
+    node :&LinkedNode,
+    numbers :&mut HashMap<String, usize>,
+    out :&mut Vec<Found>,
+    ) {
+    match node.kind() {
+        SyntaxKind::FuncCall => {
+            match parse::func_call_name(node.get()).as_deref() {
+                Some("figure") => {
+                    let kind = figure_kind(node.get());
+                    let number = numbers.entry(kind.to_string()).or_insert(0);
+                    *number += 1;
+                    let mut name = format!("{} {}", kind, number);
+                    if let Some(caption) = parse::named_arg(node.get(), "caption") {
+                        let caption = caption.clone().into_text();
+                        name = format!("{}: {}", name, caption.trim_start_matches('[').trim_end_matches(']').trim());
+                    }
+                    out.push(Found {
+                        name,
+                        kind: if kind == "Table" {SymbolKind::ARRAY} else {SymbolKind::OBJECT},
+                        range: node.range(),
+                        children: vec![],
+                    });
+                    // A table in a figure is the figure
+                    return;
+                },
+                Some("table") => {
+                    out.push(Found {
+                        name: "Table".to_string(),
+                        kind: SymbolKind::ARRAY,
+                        range: node.range(),
+                        children: vec![],
+                    });
+                    return;
+                },
+                Some("bibliography") => {
+                    out.push(Found {
+                        name: "Bibliography".to_string(),
+                        kind: SymbolKind::FILE,
+                        range: node.range(),
+                        children: bibliography_entries(backend, document, uri, node),
+                    });
+                    return;
+                },
+                _ => {},
+            }
+        },
+        SyntaxKind::Heading
+        | SyntaxKind::Raw
+        | SyntaxKind::Equation
+        | SyntaxKind::LineComment
+        | SyntaxKind::BlockComment => return,
+        _ => {},
+    }
+    for child in node.children() {
+        walk(backend, document, uri, &child, numbers, out);
+    }
+}
+// "Table", "Listing" or "Figure" by what the figure shows
+fn figure_kind(node :&SyntaxNode) -> &'static str {
+    fn first_call(node :&SyntaxNode) -> Option<String> {
+        match node.kind() {
+            SyntaxKind::FuncCall => parse::func_call_name(node),
+            SyntaxKind::Raw => Some("raw".to_string()),
+            _ => node.children().find_map(first_call),
+        }
+    }
+    match parse::positional_args(node).first().and_then(|c| first_call(c)).as_deref() {
+        Some("table") => "Table",
+        Some("raw") => "Listing",
+        _ => "Figure",
+    }
+}
+// The entries of the files a `#bibliography(..)` call loads, all at the range of the call since
+// they are in another file
+fn bibliography_entries(backend :&crate::Backend, document :&parse::Document, uri :&Url, node :&LinkedNode) -> Vec<DocumentSymbol> {
+    let mut paths = vec![];
+    for arg in parse::positional_args(node.get()) {
+        match arg.kind() {
+            SyntaxKind::Str => paths.extend(parse::str_value(arg)),
+            SyntaxKind::Array => paths.extend(arg.children().filter_map(parse::str_value)),
+            _ => {},
+        }
+    }
+    let mut out = vec![];
+    for path in paths {
+        let loaded = backend.resolve_path(uri, &path).and_then(|c| bibliography::parsed(backend, &c));
+        let bibliography = match loaded {
+            Some(c) => c,
+            None => continue,
+        };
+        out.extend(bibliography.entries.iter().map(|c| (c.key.clone(), c.describe())));
+    }
+    out.into_iter()
+        .filter_map(|(key, detail)| symbol(document, key, Some(detail), SymbolKind::FIELD, &node.range(), &node.range()))
+        .collect()
+}
+// The start of every shown diagnostic of the document
+fn issue_offsets(document :&parse::Document, uri :&Url) -> Vec<usize> {
+    let config = crate::settings::config_for(uri);
+    DIAGNOSTICS.lock().unwrap().iter()
+        .filter(|x| &x.uri == uri && checker::is_enabled(x.source, &config))
+        .filter(|x| !baseline::is_baselined(document, x))
+        .filter_map(|x| document.correct_range(x.version, x.range.clone()))
+        .map(|c| c.start)
+        .collect()
+}
+fn symbol(
+    document :&parse::Document,
+    name :String,
+    detail :Option<String>,
+    kind :SymbolKind,
+    range :&Range<usize>,
+    selection :&Range<usize>,
+    ) -> Option<DocumentSymbol> {
+    // Clients reject symbols without a name
+    let name = if name.trim().is_empty() {"(untitled)".to_string()} else {name};
+    #[allow(deprecated)]
+    Some(DocumentSymbol {
+        name,
+        detail,
+        kind,
+        tags: None,
+        deprecated: None,
+        range: document.byte_range_to_lsp_range(range)?,
+        selection_range: document.byte_range_to_lsp_range(selection)?,
+        children: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The names of the symbols, children in brackets after their parent
+    fn outline(text :&str) -> String {
+        fn names(symbols :&[DocumentSymbol]) -> String {
+            symbols.iter()
+                .map(|c| match &c.children {
+                    Some(children) => format!("{} [{}]", c.name, names(children)),
+                    None => c.name.clone(),
+                })
+                .collect::<Vec<String>>()
+                .join(", ")
+        }
+        let (service, _socket) = tower_lsp::LspService::new(|client| crate::Backend {
+            client,
+            document_map: dashmap::DashMap::new(),
+            project: crate::project::Project::new(),
+            checkers: super::super::Checkers::builtin(),
+        });
+        let document = parse::Document::new(0, &text.to_string());
+        let uri = Url::parse("file:///tmp/outline.typ").unwrap();
+        names(&symbols(service.inner(), &document, &uri, false))
+    }
+
+    #[test]
+    fn headings_nest_by_level() {
+        let text = "= One\n=== Three\n== Two <two>\nText <text>\n= Four\n";
+        assert_eq!(outline(text), "One [Three, Two [<two>, <text>]], Four");
+    }
+
+    #[test]
+    fn figures_are_numbered_by_kind() {
+        let text = "= Results\n\
+            #figure(image(\"a.png\"), caption: [A cat])\n\
+            #figure(table(columns: 2)[a][b])\n\
+            #figure(```rust\nfn main() {}\n```)\n\
+            #figure(image(\"b.png\")) <dog>\n\
+            #table(columns: 1)[x]\n";
+        assert_eq!(outline(text), "Results [Figure 1: A cat, Table 1, Listing 1, Figure 2, <dog>, Table]");
+    }
+
+    #[test]
+    fn queries_match_fuzzily() {
+        assert!(matches("2.1 Results", "rslt"));
+        assert!(matches("2.1 Results", "RES"));
+        assert!(matches("Figure 1: A cat", "fig cat"));
+        assert!(!matches("Results", "tlr"));
+        assert!(!matches("Results", "resultss"));
+    }
+}
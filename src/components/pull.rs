@@ -0,0 +1,126 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::lazy_static;
+use tower_lsp::lsp_types::{
+    ClientCapabilities,
+    DocumentDiagnosticReport,
+    FullDocumentDiagnosticReport,
+    PreviousResultId,
+    RelatedFullDocumentDiagnosticReport,
+    RelatedUnchangedDocumentDiagnosticReport,
+    UnchangedDocumentDiagnosticReport,
+    Url,
+    WorkspaceDiagnosticReport,
+    WorkspaceDocumentDiagnosticReport,
+    WorkspaceFullDocumentDiagnosticReport,
+    WorkspaceUnchangedDocumentDiagnosticReport,
+};
+use super::debounce::Debounced;
+use super::{baseline, get_lsp_diagnostics, DIAGNOSTICS};
+use crate::parse;
+
+lazy_static! {
+    //If the client pulls the diagnostics, nothing is pushed then
+    static ref PULL :AtomicBool = AtomicBool::new(false);
+    static ref REFRESH :Debounced = Debounced::new();
+}
+
+// Remembers if the client pulls diagnostics. Diagnostics found without an edit, like the
+// LanguageTool ones, only reach a pulling client through a refresh, so clients that can not be
+// asked to refresh and clients that only take pushed diagnostics keep getting publishDiagnostics.
+pub fn set_support(capabilities :&ClientCapabilities) {
+    let pull = capabilities.text_document.as_ref()
+        .is_some_and(|c| c.diagnostic.is_some());
+    let refresh = capabilities.workspace.as_ref()
+        .and_then(|c| c.diagnostic.as_ref())
+        .and_then(|c| c.refresh_support)
+        .unwrap_or(false);
+    PULL.store(pull && refresh, Ordering::Relaxed);
+}
+pub fn enabled() -> bool {
+    PULL.load(Ordering::Relaxed)
+}
+// Asks the client to pull the diagnostics again. Changes close together are sent as one refresh.
+pub fn refresh(backend :&crate::Backend) {
+    if !enabled() {
+        return;
+    }
+    let client = backend.client.clone();
+    REFRESH.send("Diagnostic", async move {client.workspace_diagnostic_refresh().await});
+}
+// The report of textDocument/diagnostic, Unchanged when the client has the result id of the
+// current diagnostics
+pub fn document_report(backend :&crate::Backend, uri :&Url, previous :Option<&str>) -> DocumentDiagnosticReport {
+    let (result_id, items) = match backend.with_document(uri, |c| current(c, uri)) {
+        Some(c) => c,
+        None => (result_id("", &[]), vec![]),
+    };
+    if previous == Some(result_id.as_str()) {
+        return DocumentDiagnosticReport::Unchanged(RelatedUnchangedDocumentDiagnosticReport {
+            related_documents: None,
+            unchanged_document_diagnostic_report: UnchangedDocumentDiagnosticReport {
+                result_id,
+            },
+        });
+    }
+    DocumentDiagnosticReport::Full(RelatedFullDocumentDiagnosticReport {
+        related_documents: None,
+        full_document_diagnostic_report: FullDocumentDiagnosticReport {
+            result_id: Some(result_id),
+            items,
+        },
+    })
+}
+// The report of workspace/diagnostic, every open document and every file with stored diagnostics
+pub fn workspace_report(backend :&crate::Backend, previous :&[PreviousResultId]) -> WorkspaceDiagnosticReport {
+    let mut uris :Vec<Url> = DIAGNOSTICS.lock().unwrap().iter().map(|c| c.uri.clone()).collect();
+    uris.extend(backend.document_map.iter().map(|c| c.key().clone()));
+    // A file the client has a result for that has no diagnostics anymore gets an empty report
+    uris.extend(previous.iter().map(|c| c.uri.clone()));
+    uris.sort();
+    uris.dedup();
+    let mut items = vec![];
+    for uri in uris {
+        let (result_id, diagnostics) = match backend.with_document(&uri, |c| current(c, &uri)) {
+            Some(c) => c,
+            None => continue,
+        };
+        let version = backend.document_map.get(&uri).map(|c| c.latest_version as i64);
+        let unchanged = previous.iter().any(|c| c.uri == uri && c.value == result_id);
+        if unchanged {
+            items.push(WorkspaceDocumentDiagnosticReport::Unchanged(WorkspaceUnchangedDocumentDiagnosticReport {
+                uri,
+                version,
+                unchanged_document_diagnostic_report: UnchangedDocumentDiagnosticReport {
+                    result_id,
+                },
+            }));
+        } else {
+            items.push(WorkspaceDocumentDiagnosticReport::Full(WorkspaceFullDocumentDiagnosticReport {
+                uri,
+                version,
+                full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                    result_id: Some(result_id),
+                    items: diagnostics,
+                },
+            }));
+        }
+    }
+    WorkspaceDiagnosticReport {
+        items,
+    }
+}
+// The diagnostics of a file as the client should show them and their result id
+fn current(document :&parse::Document, uri :&Url) -> (String, Vec<tower_lsp::lsp_types::Diagnostic>) {
+    let items = if crate::config_file::is_config_file(uri) {
+        crate::config_file::diagnostics(document, uri)
+    } else {
+        get_lsp_diagnostics(document, uri)
+    };
+    (result_id(document.typst_source.text(), &items), items)
+}
+// A hash of the text and of what the checkers found in it. The config and baseline are in the
+// diagnostics already, so a change to them gives a new id too.
+fn result_id(text :&str, diagnostics :&[tower_lsp::lsp_types::Diagnostic]) -> String {
+    let found = serde_json::to_string(diagnostics).unwrap_or_default();
+    format!("{:016x}", baseline::fnv1a(&format!("{}\0{}", text, found)))
+}
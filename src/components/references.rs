@@ -0,0 +1,577 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use rust_fuzzy_search::fuzzy_compare;
+use tower_lsp::lsp_types::{
+    CompletionItem,
+    CompletionItemKind,
+    CompletionItemLabelDetails,
+    CompletionTextEdit,
+    DiagnosticRelatedInformation,
+    DiagnosticSeverity,
+    DiagnosticTag,
+    Location,
+    NumberOrString,
+    Position,
+    TextEdit,
+    Url,
+};
+use typst_syntax::{LinkedNode, SyntaxKind, SyntaxNode};
+use crate::components::{Checked, Checker, Diagnostic, QuickFix};
+use crate::parse;
+use crate::project::Revision;
+
+// The lowest fuzzy score for a label to be suggested as replacement of an undefined one
+const SUGGESTION_THRESHOLD :f32 = 0.4;
+pub const NAME :&str = "references";
+
+lazy_static! {
+    //The labels and references of each file and the revision they were collected from
+    static ref LABEL_FILES :DashMap<Url, (Revision, Arc<LabelFile>)> = DashMap::new();
+}
+
+#[derive(Clone)]
+pub struct ReferenceDiagnostic {
+    pub fixes: Vec<QuickFix>,
+}
+// The labels and references of one file
+pub struct LabelFile {
+    pub uri: Url,
+    pub labels: Vec<LabelDef>,
+    pub refs: Vec<RefUse>,
+    pub has_bibliography: bool,
+    //Paths as written in `#bibliography(..)` and the ranges of their strings
+    pub bibliographies: Vec<(String, Range<usize>)>,
+}
+pub struct LabelDef {
+    pub name: String,
+    pub range: Range<usize>,
+    pub location: Location,
+    //What the label is attached to, shown in completions
+    pub detail: Option<String>,
+}
+pub struct RefUse {
+    pub name: String,
+    pub range: Range<usize>,
+    pub location: Location,
+    //True for `@label`, false for labels used in code like `#ref(<label>)` or `#show <label>`
+    pub is_markup: bool,
+    pub has_supplement: bool,
+    //The word right before a markup reference and its range, spacing included
+    pub preceding_word: Option<(Range<usize>, String)>,
+}
+
+// Checks the references of a document against the labels of its project and the keys of its
+// bibliographies, the document must be one of the files
+pub fn check(document :&parse::Document, uri :&Url, files :&[Arc<LabelFile>], keys :&HashSet<String>) -> Vec<Diagnostic> {
+    let config = crate::settings::config_for(uri);
+    let this_file = match files.iter().find(|c| &c.uri == uri) {
+        Some(c) if config.references.enabled => c,
+        _ => return Vec::new(),
+    };
+    let mut defined :HashMap<&str, &LabelDef> = HashMap::new();
+    let mut duplicates :Vec<(&LabelDef, &LabelDef)> = vec![];
+    for file in files {
+        for label in &file.labels {
+            match defined.get(label.name.as_str()) {
+                Some(first) => duplicates.push((label, first)),
+                None => {defined.insert(&label.name, label);},
+            }
+        }
+    }
+    let used :HashSet<&str> = files.iter()
+        .flat_map(|c| c.refs.iter())
+        .map(|c| c.name.as_str())
+        .collect();
+    let has_bibliography = files.iter().any(|c| c.has_bibliography);
+    let headings = super::heading::collect_headings(document);
+
+    let mut out :Vec<Diagnostic> = vec![];
+    for reference in &this_file.refs {
+        // With a bibliography a reference may be a citation too
+        if !defined.contains_key(reference.name.as_str()) && !keys.contains(&reference.name) {
+            let mut fixes = vec![];
+            let mut suggestions :Vec<(f32, &str)> = defined.keys()
+                .copied()
+                .chain(keys.iter().map(|c| c.as_str()))
+                .map(|c| (fuzzy_compare(&reference.name, c), c))
+                .filter(|c| c.0 >= SUGGESTION_THRESHOLD)
+                .collect();
+            suggestions.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+            for (_, name) in suggestions.iter().take(3) {
+                fixes.push((
+                    format!("Change to {}", name),
+                    vec![(name_range(document, reference), name.to_string())],
+                ));
+            }
+            if let Some(heading) = headings.iter().rfind(|c| c.range.end <= reference.range.start) {
+                fixes.push((
+                    format!("Create label <{}> on heading \"{}\"", reference.name, heading.title),
+                    vec![(heading.body_range.end..heading.body_range.end, format!(" <{}>", reference.name))],
+                ));
+            }
+            let message = if has_bibliography {
+                format!("{} is neither a label nor a key in the bibliography", reference.name)
+            } else {
+                format!("Reference to undefined label <{}>", reference.name)
+            };
+            out.extend(new_diagnostic(
+                document,
+                uri,
+                reference.range.clone(),
+                message,
+                "undefined-reference",
+                DiagnosticSeverity::ERROR,
+                fixes,
+            ));
+        }
+        if config.references.double_wording && reference.is_markup && !reference.has_supplement {
+            if let Some((range, word)) = &reference.preceding_word {
+                if config.references.supplement_words.iter().any(|c| c.to_lowercase() == word.to_lowercase()) {
+                    out.extend(new_diagnostic(
+                        document,
+                        uri,
+                        range.start..reference.range.end,
+                        format!("\"{}\" is written twice, typst already adds the supplement before the number of @{}", word, reference.name),
+                        "reference-double-wording",
+                        DiagnosticSeverity::WARNING,
+                        vec![(format!("Remove \"{}\"", word), vec![(range.clone(), String::new())])],
+                    ));
+                }
+            }
+        }
+    }
+    for (label, first) in duplicates {
+        if label.location.uri != *uri {
+            continue;
+        }
+        let mut diagnostic = match new_diagnostic(
+            document,
+            uri,
+            label.range.clone(),
+            format!("Label <{}> is defined more than once", label.name),
+            "duplicate-label",
+            DiagnosticSeverity::ERROR,
+            vec![],
+        ) {
+            Some(c) => c,
+            None => continue,
+        };
+        diagnostic.diagnostics_lsp.related_information = Some(vec![DiagnosticRelatedInformation {
+            location: first.location.clone(),
+            message: format!("<{}> is first defined here", first.name),
+        }]);
+        out.push(diagnostic);
+    }
+    if config.references.unused_labels {
+        for label in &this_file.labels {
+            if used.contains(label.name.as_str()) {
+                continue;
+            }
+            let mut removal = label.range.clone();
+            let before = document.get_chunk_by_range(0..removal.start).unwrap_or_default();
+            removal.start -= before.len() - before.trim_end_matches(' ').len();
+            if let Some(mut c) = new_diagnostic(
+                document,
+                uri,
+                label.range.clone(),
+                format!("Label <{}> is never referenced", label.name),
+                "unused-label",
+                DiagnosticSeverity::HINT,
+                vec![(format!("Remove <{}>", label.name), vec![(removal, String::new())])],
+            ) {
+                c.diagnostics_lsp.tags = Some(vec![DiagnosticTag::UNNECESSARY]);
+                out.push(c);
+            }
+        }
+    }
+    out
+}
+pub struct ReferencesChecker;
+#[tower_lsp::async_trait]
+impl Checker for ReferencesChecker {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+    fn config_keys(&self) -> &'static [&'static str] {
+        &["references"]
+    }
+    fn on_change(&self) -> bool {
+        true
+    }
+    // Checks every file of the project of the document, a change to its labels changes what the
+    // references in the other files point to
+    async fn check(&self, backend :&crate::Backend, uri :&Url, _range :Option<Range<usize>>) -> Checked {
+        if super::bibliography::is_bibliography(uri) {
+            return Checked::file(uri, vec![]);
+        }
+        let files = project_index(backend, uri);
+        // A bibliography that can't be read has no keys, the bibliography checker reports its path
+        let keys :HashSet<String> = bibliography_uris(backend, &files).iter()
+            .filter_map(|c| super::bibliography::parsed(backend, c))
+            .flat_map(|c| c.entries.iter().map(|x| x.key.clone()).collect::<Vec<String>>())
+            .collect();
+        let mut out = vec![];
+        for file in &files {
+            out.extend(backend.with_document(&file.uri, |c| check(c, &file.uri, &files, &keys)).unwrap_or_default());
+        }
+        Checked {
+            diagnostics: out,
+            files: files.iter().map(|c| c.uri.clone()).collect(),
+        }
+    }
+    fn quick_fixes(&self, diagnostic :&Diagnostic) -> Vec<QuickFix> {
+        diagnostic.data::<ReferenceDiagnostic>().map_or(vec![], |c| c.fixes.clone())
+    }
+}
+// Collects the labels of every file in the project of the document, the document itself first
+pub fn project_index(backend :&crate::Backend, uri :&Url) -> Vec<Arc<LabelFile>> {
+    backend.project_files(uri).iter()
+        .filter_map(|file| label_file(backend, file))
+        .collect()
+}
+// The labels and references of a file, only collected again when the file changed
+pub fn label_file(backend :&crate::Backend, uri :&Url) -> Option<Arc<LabelFile>> {
+    backend.with_revision(uri, |document, revision| {
+        if let Some(c) = LABEL_FILES.get(uri) {
+            if c.0 == revision {
+                return c.1.clone();
+            }
+        }
+        let file = Arc::new(collect_file(document, uri));
+        LABEL_FILES.insert(uri.clone(), (revision, file.clone()));
+        file
+    })
+}
+// Forgets the labels of a file, an opened document may start at a version seen before
+pub fn forget(uri :&Url) {
+    LABEL_FILES.remove(uri);
+}
+// The bibliography files of the project that could be resolved, each once
+pub fn bibliography_uris(backend :&crate::Backend, files :&[Arc<LabelFile>]) -> Vec<Url> {
+    let mut out :Vec<Url> = files.iter()
+        .flat_map(|file| file.bibliographies.iter().filter_map(|c| backend.resolve_path(&file.uri, &c.0)))
+        .collect();
+    out.sort();
+    out.dedup();
+    out
+}
+pub fn collect_file(document :&parse::Document, uri :&Url) -> LabelFile {
+    let mut file = LabelFile {
+        uri: uri.clone(),
+        labels: vec![],
+        refs: vec![],
+        has_bibliography: false,
+        bibliographies: vec![],
+    };
+    walk(document, &LinkedNode::new(document.typst_source.root()), &mut file);
+    file
+}
+fn walk(document :&parse::Document, node :&LinkedNode, file :&mut LabelFile) {
+    match node.kind() {
+        SyntaxKind::Label => {
+            let name = node.get().text().trim_start_matches('<').trim_end_matches('>').to_string();
+            let location = match location(document, &file.uri, node.range()) {
+                Some(c) => c,
+                None => return,
+            };
+            // A label in markup names the element before it, anywhere else it is used
+            if node.parent_kind() == Some(SyntaxKind::Markup) {
+                file.labels.push(LabelDef {
+                    name,
+                    range: node.range(),
+                    location,
+                    detail: label_target(node),
+                });
+            } else {
+                file.refs.push(RefUse {
+                    name,
+                    range: node.range(),
+                    location,
+                    is_markup: false,
+                    has_supplement: false,
+                    preceding_word: None,
+                });
+            }
+            return;
+        },
+        SyntaxKind::Ref => {
+            let marker = match node.children().find(|c| c.kind() == SyntaxKind::RefMarker) {
+                Some(c) => c,
+                None => return,
+            };
+            let location = match location(document, &file.uri, node.range()) {
+                Some(c) => c,
+                None => return,
+            };
+            file.refs.push(RefUse {
+                name: marker.get().text().trim_start_matches('@').to_string(),
+                range: node.range(),
+                location,
+                is_markup: true,
+                has_supplement: node.children().any(|c| c.kind() == SyntaxKind::ContentBlock),
+                preceding_word: preceding_word(node),
+            });
+            return;
+        },
+        SyntaxKind::FuncCall if parse::func_call_name(node.get()).as_deref() == Some("bibliography") => {
+            file.has_bibliography = true;
+            let args = node.children().filter(|c| c.kind() == SyntaxKind::Args);
+            for arg in args.flat_map(|c| c.children().collect::<Vec<LinkedNode>>()) {
+                match arg.kind() {
+                    SyntaxKind::Str => file.bibliographies.extend(path_at(&arg)),
+                    SyntaxKind::Array => file.bibliographies.extend(arg.children().filter_map(|c| path_at(&c))),
+                    _ => {},
+                }
+            }
+        },
+        SyntaxKind::Raw
+        | SyntaxKind::LineComment
+        | SyntaxKind::BlockComment => return,
+        _ => {},
+    }
+    for child in node.children() {
+        walk(document, &child, file);
+    }
+}
+fn path_at(node :&LinkedNode) -> Option<(String, Range<usize>)> {
+    Some((parse::str_value(node.get())?, node.range()))
+}
+// The word before `@ref` when they are only separated by spaces, e.g. "Figure" in "see Figure @fig"
+fn preceding_word(node :&LinkedNode) -> Option<(Range<usize>, String)> {
+    let siblings :Vec<&SyntaxNode> = node.parent()?.get().children().take(node.index()).collect();
+    let mut at = siblings.len().checked_sub(1)?;
+    if siblings[at].kind() == SyntaxKind::Space
+        || (siblings[at].kind() == SyntaxKind::Shorthand && siblings[at].text() == "~") {
+        at = at.checked_sub(1)?;
+    }
+    if siblings[at].kind() != SyntaxKind::Text {
+        return None;
+    }
+    let offset = node.offset() - siblings[at..].iter().map(|c| c.len()).sum::<usize>();
+    let text = siblings[at].text();
+    let trimmed = text.trim_end();
+    let word = trimmed.split_whitespace().last()?;
+    let start = offset + trimmed.len() - word.len();
+    Some((start..node.offset(), word.to_string()))
+}
+// What a label in markup is attached to, e.g. "Heading: Introduction" or "Figure: A 555 timer"
+fn label_target(node :&LinkedNode) -> Option<String> {
+    let parent = node.parent()?;
+    if parent.parent_kind() == Some(SyntaxKind::Heading) {
+        let title = parent.get().clone().into_text();
+        return Some(format!("Heading: {}", title.replace(node.get().text().as_str(), "").trim()));
+    }
+    let prev = node.prev_sibling()?;
+    match prev.kind() {
+        SyntaxKind::FuncCall => {
+            let name = parse::func_call_name(prev.get())?;
+            let caption = parse::named_arg(prev.get(), "caption")
+                .map(|c| c.clone().into_text().trim_start_matches('[').trim_end_matches(']').trim().to_string());
+            let mut kind = name.clone();
+            if name == "figure" {
+                // Tables and listings are figures too, name them by their content
+                kind = match parse::positional_args(prev.get()).first().and_then(|c| first_call_name(c)) {
+                    Some(c) if c == "table" => "table".to_string(),
+                    Some(c) if c == "raw" => "listing".to_string(),
+                    _ => "figure".to_string(),
+                };
+            }
+            let mut kind_chars = kind.chars();
+            let kind = match kind_chars.next() {
+                Some(c) => c.to_uppercase().collect::<String>() + kind_chars.as_str(),
+                None => kind,
+            };
+            match caption {
+                Some(c) => Some(format!("{}: {}", kind, c)),
+                None => Some(kind),
+            }
+        },
+        SyntaxKind::Equation => Some("Equation".to_string()),
+        SyntaxKind::Raw => Some("Listing".to_string()),
+        _ => None,
+    }
+}
+fn first_call_name(node :&SyntaxNode) -> Option<String> {
+    if node.kind() == SyntaxKind::FuncCall {
+        return parse::func_call_name(node);
+    }
+    if node.kind() == SyntaxKind::Raw {
+        return Some("raw".to_string());
+    }
+    node.children().find_map(first_call_name)
+}
+// The range of the label name in `@name`, without the marker
+fn name_range(document :&parse::Document, reference :&RefUse) -> Range<usize> {
+    let text = document.get_chunk_by_range(reference.range.clone()).unwrap_or_default();
+    let start = reference.range.start + if text.starts_with('@') {1} else {0};
+    start..start+reference.name.len()
+}
+fn location(document :&parse::Document, uri :&Url, range :Range<usize>) -> Option<Location> {
+    Some(Location {
+        uri: uri.clone(),
+        range: document.byte_range_to_lsp_range(&range)?,
+    })
+}
+fn new_diagnostic(
+    document :&parse::Document,
+    uri :&Url,
+    range :Range<usize>,
+    message :String,
+    code :&str,
+    severity :DiagnosticSeverity,
+    fixes :Vec<QuickFix>,
+    ) -> Option<Diagnostic> {
+    Diagnostic::new(
+        document,
+        uri,
+        range,
+        tower_lsp::lsp_types::Diagnostic {
+            range: Default::default(),
+            severity: Some(severity),
+            code: Some(NumberOrString::String(code.to_string())),
+            code_description: None,
+            source: Some("references".to_string()),
+            message,
+            related_information: None,
+            tags: None,
+            data: None,
+        },
+        NAME,
+        ReferenceDiagnostic {
+            fixes,
+        },
+    )
+}
+// Completes labels and bibliography keys after `@` and inside `#cite(<..>)` or `#ref(<..>)`.
+// Returns None when the cursor is not in such a position.
+pub fn completion(backend :&crate::Backend, uri :&Url, position :Position) -> Option<Vec<CompletionItem>> {
+    let (prefix, prefix_range, context) = {
+        let document_ref = backend.document_map.get(uri)?;
+        let document = document_ref.deref();
+        let cursor = document.typst_source.line_column_to_byte(position.line as usize, position.character as usize)?;
+        let line_start = document.typst_source.line_to_byte(position.line as usize)?;
+        let before = document.get_chunk_by_range(line_start..cursor)?;
+        let name_start = before
+            .rfind(|c :char| !(c.is_alphanumeric() || matches!(c, '_' | '-' | ':' | '.')))
+            .map_or(0, |c| c + 1);
+        let prefix = before[name_start..].to_string();
+        let marker = before[..name_start].chars().last()?;
+        let context = match marker {
+            // An @ right after a word is an email address
+            '@' if !before[..name_start-1].chars().next_back().is_some_and(|c| c.is_alphanumeric()) => CompletionContext::Ref,
+            '<' => {
+                let call = before[..name_start-1].rfind('(').map(|c| &before[..c])?;
+                if before[call.len()..].contains(')') {
+                    return None;
+                }
+                if call.ends_with("cite") {
+                    CompletionContext::Cite
+                } else if call.ends_with("ref") {
+                    CompletionContext::Label
+                } else {
+                    return None;
+                }
+            },
+            _ => return None,
+        };
+        let range = document.byte_range_to_lsp_range(&(cursor-prefix.len()..cursor))?;
+        (prefix, range, context)
+    };
+    let open :Vec<Url> = backend.document_map.iter().map(|c| c.key().clone()).collect();
+    let mut project :Vec<Url> = open.iter().flat_map(|c| backend.project_files(c)).collect();
+    project.sort();
+    project.dedup();
+    let files :Vec<Arc<LabelFile>> = project.iter()
+        .filter_map(|file| label_file(backend, file))
+        .collect();
+
+    let mut items :Vec<CompletionItem> = vec![];
+    let mut seen :HashSet<String> = HashSet::new();
+    if context != CompletionContext::Cite {
+        for label in files.iter().flat_map(|c| c.labels.iter()) {
+            if !label.name.starts_with(&prefix) || !seen.insert(label.name.clone()) {
+                continue;
+            }
+            items.push(completion_item(&label.name, label.detail.clone(), "label", prefix_range));
+        }
+    }
+    if context != CompletionContext::Label {
+        for bibliography_uri in bibliography_uris(backend, &files) {
+            let bibliography = match super::bibliography::parsed(backend, &bibliography_uri) {
+                Some(c) => c,
+                None => continue,
+            };
+            for entry in &bibliography.entries {
+                if !entry.key.starts_with(&prefix) || !seen.insert(entry.key.clone()) {
+                    continue;
+                }
+                items.push(completion_item(&entry.key, Some(entry.describe()), "bibliography", prefix_range));
+            }
+        }
+    }
+    Some(items)
+}
+#[derive(PartialEq)]
+enum CompletionContext {
+    // After @, labels and bibliography keys
+    Ref,
+    // In #cite(<..>), only bibliography keys
+    Cite,
+    // In #ref(<..>), only labels
+    Label,
+}
+fn completion_item(name :&str, detail :Option<String>, description :&str, range :tower_lsp::lsp_types::Range) -> CompletionItem {
+    CompletionItem {
+        label: name.to_string(),
+        label_details: Some(CompletionItemLabelDetails {
+            detail: None,
+            description: Some(description.to_string()),
+        }),
+        kind: Some(CompletionItemKind::REFERENCE),
+        detail,
+        filter_text: Some(name.to_string()),
+        text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+            range,
+            new_text: name.to_string(),
+        })),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(text :&str, keys :&[&str]) -> Vec<String> {
+        let uri = Url::parse("file:///tmp/references-test/main.typ").unwrap();
+        let document = parse::Document::new(0, &text.to_string());
+        let files = vec![Arc::new(collect_file(&document, &uri))];
+        let keys :HashSet<String> = keys.iter().map(|c| c.to_string()).collect();
+        check(&document, &uri, &files, &keys).iter()
+            .filter_map(|c| match &c.diagnostics_lsp.code {
+                Some(NumberOrString::String(code)) => Some(code.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn references_resolve_to_labels_and_keys() {
+        let text = "= Intro <intro>\nSee @intro and @knuth.\n#bibliography(\"refs.bib\")\n";
+        assert!(codes(text, &["knuth"]).is_empty());
+        assert_eq!(codes(text, &[]), vec!["undefined-reference"]);
+        assert_eq!(codes("= Intro <intro>\nSee @intro and @missing.\n", &["knuth"]), vec!["undefined-reference"]);
+    }
+
+    #[test]
+    fn bibliography_paths_with_ranges() {
+        let text = "#bibliography((\"a.bib\", \"b.yml\"), style: \"apa\")";
+        let document = parse::Document::new(0, &text.to_string());
+        let file = collect_file(&document, &Url::parse("file:///tmp/main.typ").unwrap());
+        let paths :Vec<(&str, &str)> = file.bibliographies.iter().map(|c| (c.0.as_str(), &text[c.1.clone()])).collect();
+        assert_eq!(paths, vec![("a.bib", "\"a.bib\""), ("b.yml", "\"b.yml\"")]);
+    }
+}
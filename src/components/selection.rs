@@ -0,0 +1,226 @@
+use std::ops::Range;
+use tower_lsp::lsp_types::{Position, SelectionRange};
+use typst_syntax::{LinkedNode, SyntaxKind};
+use super::heading;
+use crate::parse;
+
+// The ranges a selection grows through from each position: the word, the markup around it like
+// emphasis or a link, the sentence, the paragraph, each section containing it and the document
+pub fn selection_ranges(document :&parse::Document, positions :&[Position]) -> Vec<SelectionRange> {
+    positions.iter()
+        .map(|position| {
+            let offset = document.utf16_position_to_byte(position);
+            let chain = match offset {
+                Some(c) => chain(document, c),
+                None => vec![],
+            };
+            let mut out :Option<SelectionRange> = None;
+            for range in chain.iter().rev() {
+                let range = match (document.byte_to_utf16_position(range.start), document.byte_to_utf16_position(range.end)) {
+                    (Some(start), Some(end)) => tower_lsp::lsp_types::Range { start, end },
+                    _ => continue,
+                };
+                out = Some(SelectionRange {
+                    range,
+                    parent: out.map(Box::new),
+                });
+            }
+            // The spec wants a range for every position, an empty one when there is nothing to select
+            out.unwrap_or(SelectionRange {
+                range: tower_lsp::lsp_types::Range {
+                    start: *position,
+                    end: *position,
+                },
+                parent: None,
+            })
+        })
+        .collect()
+}
+// The ranges from the innermost out, each containing the one before
+fn chain(document :&parse::Document, offset :usize) -> Vec<Range<usize>> {
+    let text = document.typst_source.text();
+    let root = LinkedNode::new(document.typst_source.root());
+    let paragraph = paragraph(&root, offset);
+    let mut candidates :Vec<Range<usize>> = vec![];
+    candidates.extend(word(text, offset));
+    candidates.extend(inline_markup(&root, offset));
+    if let Some(c) = &paragraph {
+        candidates.extend(sentence(text, c, offset));
+        candidates.push(c.clone());
+    }
+    candidates.extend(sections(document, offset));
+    candidates.push(0..text.len());
+    let mut out :Vec<Range<usize>> = vec![];
+    for range in candidates {
+        let grows = match out.last() {
+            Some(c) => range.start <= c.start && c.end <= range.end && &range != c,
+            None => range.start <= offset && offset <= range.end,
+        };
+        if grows {
+            out.push(range);
+        }
+    }
+    out
+}
+// The word at the offset, apostrophes included so "don't" is one word
+fn word(text :&str, offset :usize) -> Option<Range<usize>> {
+    let is_word = |c :char| c.is_alphanumeric() || c == '\'' || c == '’';
+    let start = text[..offset].char_indices().rev()
+        .take_while(|c| is_word(c.1))
+        .last()
+        .map_or(offset, |c| c.0);
+    let end = offset + text[offset..].chars().take_while(|c| is_word(*c)).map(|c| c.len_utf8()).sum::<usize>();
+    if start == end {
+        return None;
+    }
+    Some(start..end)
+}
+// The innermost emphasis, strong text, link, reference, raw text or equation at the offset
+fn inline_markup(node :&LinkedNode, offset :usize) -> Option<Range<usize>> {
+    let inner = node.children()
+        .filter(|c| c.range().start <= offset && offset <= c.range().end)
+        .find_map(|c| inline_markup(&c, offset));
+    if inner.is_some() {
+        return inner;
+    }
+    match node.kind() {
+        SyntaxKind::Emph
+        | SyntaxKind::Strong
+        | SyntaxKind::Link
+        | SyntaxKind::Ref
+        | SyntaxKind::Raw
+        | SyntaxKind::Equation => Some(node.range()),
+        _ => None,
+    }
+}
+// The sentence of the paragraph at the offset. A sentence ends at . ! or ? followed by a space,
+// closing quotes and brackets after the mark are part of it.
+fn sentence(text :&str, paragraph :&Range<usize>, offset :usize) -> Option<Range<usize>> {
+    let part = text.get(paragraph.clone())?;
+    let mut start = 0;
+    let mut chars = part.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if !matches!(c, '.' | '!' | '?') {
+            continue;
+        }
+        let mut end = i + c.len_utf8();
+        while let Some((j, next)) = chars.peek().copied() {
+            if !matches!(next, '.' | '!' | '?' | '"' | '\'' | '”' | '’' | ')' | ']' | '*' | '_') {
+                break;
+            }
+            end = j + next.len_utf8();
+            chars.next();
+        }
+        if !chars.peek().is_none_or(|c| c.1.is_whitespace()) {
+            continue;
+        }
+        let range = paragraph.start + start..paragraph.start + end;
+        if range.start <= offset && offset <= range.end {
+            return Some(range);
+        }
+        start = end + part[end..].len() - part[end..].trim_start().len();
+    }
+    let range = paragraph.start + start..paragraph.end;
+    if range.start <= offset {
+        return Some(range);
+    }
+    None
+}
+// The paragraph at the offset: the run of markup between paragraph breaks and headings in the
+// innermost markup containing it. In a heading it is the heading text.
+fn paragraph(root :&LinkedNode, offset :usize) -> Option<Range<usize>> {
+    let markup = innermost_markup(root, offset)?;
+    let mut groups :Vec<Range<usize>> = vec![];
+    let mut current :Option<Range<usize>> = None;
+    for child in markup.children() {
+        match child.kind() {
+            SyntaxKind::Parbreak => {
+                groups.extend(current.take());
+            },
+            SyntaxKind::Heading => {
+                groups.extend(current.take());
+                groups.push(child.range());
+            },
+            SyntaxKind::Space => {},
+            _ => {
+                current = match current {
+                    Some(c) => Some(c.start..child.range().end),
+                    None => Some(child.range()),
+                };
+            },
+        }
+    }
+    groups.extend(current);
+    groups.into_iter().find(|c| c.start <= offset && offset <= c.end)
+}
+// The markup of emphasis and strong text is part of the paragraph around it
+fn innermost_markup<'a>(node :&LinkedNode<'a>, offset :usize) -> Option<LinkedNode<'a>> {
+    let inner = node.children()
+        .filter(|c| c.range().start <= offset && offset <= c.range().end)
+        .filter(|c| !matches!(c.kind(), SyntaxKind::Emph | SyntaxKind::Strong))
+        .find_map(|c| innermost_markup(&c, offset));
+    match inner {
+        Some(c) => Some(c),
+        None if node.kind() == SyntaxKind::Markup => Some(node.clone()),
+        None => None,
+    }
+}
+// Every heading section containing the offset from the innermost out
+fn sections(document :&parse::Document, offset :usize) -> Vec<Range<usize>> {
+    let mut out :Vec<Range<usize>> = heading::sections(document).into_iter()
+        .map(|c| c.1)
+        .filter(|c| c.start <= offset && offset <= c.end)
+        .collect();
+    out.sort_by_key(|c| c.end - c.start);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(text :&str) -> parse::Document {
+        parse::Document::new(0, &text.to_string())
+    }
+
+    #[test]
+    fn chain_grows_strictly() {
+        let text = "Preface.\n\n= Intro\n\nSome text. A _very nice_ sentence here! Last one.\n\nNext paragraph.\n";
+        let found = chain(&document(text), text.find("nice").unwrap() + 1);
+        let texts :Vec<&str> = found.iter().map(|c| &text[c.clone()]).collect();
+        assert_eq!(texts, vec![
+            "nice",
+            "_very nice_",
+            "A _very nice_ sentence here!",
+            "Some text. A _very nice_ sentence here! Last one.",
+            text[text.find("= Intro").unwrap()..].trim_end(),
+            text,
+        ]);
+        for pair in found.windows(2) {
+            assert!(pair[1].start <= pair[0].start && pair[0].end <= pair[1].end && pair[0] != pair[1]);
+        }
+    }
+
+    #[test]
+    fn sentences() {
+        let text = "First one. Second (with a quote.) Third \"said!\" and more";
+        let paragraph = 0..text.len();
+        let at = |word :&str| sentence(text, &paragraph, text.find(word).unwrap()).map(|c| &text[c]);
+        assert_eq!(at("First"), Some("First one."));
+        assert_eq!(at("quote"), Some("Second (with a quote.)"));
+        assert_eq!(at("said"), Some("Third \"said!\""));
+        // The last one needs no full stop
+        assert_eq!(at("more"), Some("and more"));
+    }
+
+    #[test]
+    fn positions_count_utf16() {
+        // The emoji is two UTF-16 code units but one char
+        let text = "😀 emoji word here.";
+        let found = selection_ranges(&document(text), &[Position::new(0, 10)]);
+        assert_eq!(found[0].range.start, Position::new(0, 9));
+        assert_eq!(found[0].range.end, Position::new(0, 13));
+        assert_eq!(document(text).utf16_position_to_byte(&Position::new(0, 2)), Some(4));
+        assert_eq!(document(text).utf16_position_to_byte(&Position::new(0, 40)), None);
+    }
+}
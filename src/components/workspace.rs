@@ -0,0 +1,153 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use dashmap::DashMap;
+use futures::StreamExt;
+use lazy_static::lazy_static;
+use tower_lsp::lsp_types::{
+    NumberOrString,
+    ProgressParams,
+    ProgressParamsValue,
+    ProgressToken,
+    Url,
+    WorkDoneProgress,
+    WorkDoneProgressBegin,
+    WorkDoneProgressCancelParams,
+    WorkDoneProgressCreateParams,
+    WorkDoneProgressEnd,
+    WorkDoneProgressReport,
+};
+use tower_lsp::lsp_types::notification::Progress;
+use tower_lsp::lsp_types::request::WorkDoneProgressCreate;
+
+pub const CHECK_WORKSPACE_COMMAND :&str = "typst-grammar.checkWorkspace";
+
+lazy_static! {
+    //Progress tokens of the running workspace checks, set to true when the client cancels them
+    static ref RUNNING :DashMap<ProgressToken, bool> = DashMap::new();
+}
+
+// Checks every .typ file in the workspace folders, open or not, and publishes the diagnostics.
+// Reports the progress to the client, which may cancel it between files.
+pub async fn check_workspace(backend :&crate::Backend, work_done_token :Option<ProgressToken>) {
+    // The files of each workspace folder, a file in nested folders is in the first one
+    let folders :Vec<Vec<Url>> = {
+        let roots = backend.project.roots.read().unwrap();
+        let mut seen :HashSet<PathBuf> = HashSet::new();
+        let mut folders = vec![];
+        for root in roots.iter() {
+            let mut paths :Vec<PathBuf> = vec![];
+            find_typst_files(root, &mut paths);
+            paths.sort();
+            paths.retain(|c| seen.insert(c.clone()));
+            folders.push(paths.into_iter().filter_map(|c| Url::from_file_path(c).ok()).collect());
+        }
+        folders
+    };
+    let files :Vec<Url> = folders.iter().flatten().cloned().collect();
+    let token = match work_done_token {
+        Some(c) => Some(c),
+        None => create_progress(backend).await,
+    };
+    if let Some(token) = &token {
+        RUNNING.insert(token.clone(), false);
+        send_progress(backend, token, WorkDoneProgress::Begin(WorkDoneProgressBegin {
+            title: "Checking workspace".to_string(),
+            cancellable: Some(true),
+            message: Some(format!("0/{} files", files.len())),
+            percentage: Some(0),
+        })).await;
+    }
+    // The project index has to know every file before the references can be checked
+    for file in &files {
+        backend.update_dependencies(file);
+    }
+    // One folder after the other, each with the concurrency of its config. Owned urls, a closure
+    // over borrowed ones makes the future of execute_command not Send.
+    let mut checks = futures::stream::iter(folders.into_iter().filter(|c| !c.is_empty()))
+        .flat_map(|folder| {
+            let concurrency = crate::settings::config_for(&folder[0]).workspace.concurrency.max(1);
+            futures::stream::iter(folder)
+                .map(|file| async move {check_file(backend, &file).await})
+                .buffer_unordered(concurrency)
+        });
+    let mut done = 0;
+    let mut cancelled = false;
+    while let Some(file) = checks.next().await {
+        super::publish_file_diagnostics(backend, &file).await;
+        done += 1;
+        if let Some(token) = &token {
+            if RUNNING.get(token).is_some_and(|c| *c) {
+                cancelled = true;
+                break;
+            }
+            send_progress(backend, token, WorkDoneProgress::Report(WorkDoneProgressReport {
+                cancellable: Some(true),
+                message: Some(format!("{}/{} files", done, files.len())),
+                percentage: Some((done * 100 / files.len()) as u32),
+            })).await;
+        }
+    }
+    if let Some(token) = &token {
+        RUNNING.remove(token);
+        let message = if cancelled {
+            format!("Cancelled after {}/{} files", done, files.len())
+        } else {
+            format!("Checked {} files", done)
+        };
+        send_progress(backend, token, WorkDoneProgress::End(WorkDoneProgressEnd {
+            message: Some(message),
+        })).await;
+    }
+}
+// Marks a workspace check as cancelled, it stops after the files being checked right now
+pub fn cancel(params :WorkDoneProgressCancelParams) {
+    if let Some(mut c) = RUNNING.get_mut(&params.token) {
+        *c = true;
+    }
+}
+// Runs every checker on a file. The other files a checker touched are published, the file itself
+// is left to the caller.
+pub async fn check_file(backend :&crate::Backend, uri :&Url) -> Url {
+    for checker in backend.checkers.iter() {
+        for touched in super::checker::run(backend, checker, uri, None).await {
+            if &touched != uri {
+                super::publish_file_diagnostics(backend, &touched).await;
+            }
+        }
+    }
+    uri.clone()
+}
+fn find_typst_files(dir :&Path, out :&mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(c) => c,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if path.is_dir() {
+            if !hidden {
+                find_typst_files(&path, out);
+            }
+        } else if path.extension().is_some_and(|c| c == "typ") {
+            out.push(path);
+        }
+    }
+}
+async fn create_progress(backend :&crate::Backend) -> Option<ProgressToken> {
+    let token = NumberOrString::String(format!("typst-grammar/{}", uuid::Uuid::new_v4()));
+    match backend.client.send_request::<WorkDoneProgressCreate>(WorkDoneProgressCreateParams {
+        token: token.clone(),
+    }).await {
+        Ok(_) => Some(token),
+        // The client does not support progress, check without it
+        Err(_) => None,
+    }
+}
+async fn send_progress(backend :&crate::Backend, token :&ProgressToken, value :WorkDoneProgress) {
+    backend.client.send_notification::<Progress>(ProgressParams {
+        token: token.clone(),
+        value: ProgressParamsValue::WorkDone(value),
+    }).await;
+}
@@ -1,18 +1,168 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
-use std::fs;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
 pub struct RootConfig {
-    #[serde(default)]
+    /// Check the text with a LanguageTool server
     pub lt_enabled: bool, 
-    #[serde(default)]
+    /// The LanguageTool server, with http:// or https://
     pub lt_api_hostname: String, 
-    #[serde(default)]
+    /// The port of the LanguageTool server, empty to use the default port of the URL like for
+    /// https://api.languagetoolplus.com
     pub lt_api_port: String, 
+    /// The account for LanguageTool Premium, needs lt_api_key
+    pub lt_username: Option<String>,
+    /// The API key of the LanguageTool Premium account
+    pub lt_api_key: Option<String>,
+    /// "picky" also reports style issues that are left out by default
+    pub lt_level: LtLevel,
+    /// The native language of the writer, e.g. "de-DE", enables false friend checks
+    pub lt_mother_tongue: Option<String>,
+    /// The variants used when the language is detected, e.g. ["en-GB", "pt-BR"]
+    pub lt_preferred_variants: Vec<String>,
+    /// Words language tool should not report as misspelled
+    pub lt_dictionary: Vec<String>,
+    /// Language tool rule ids that are never reported, e.g. "WHITESPACE_RULE"
+    pub lt_disabled_rules: Vec<String>,
+    /// Language tool rule ids to turn on that are off by default
+    pub lt_enabled_rules: Vec<String>,
+    /// Language tool rule categories to turn on, e.g. "TYPOGRAPHY"
+    pub lt_enabled_categories: Vec<String>,
+    /// Only use lt_enabled_rules and lt_enabled_categories, nothing else is checked
+    pub lt_enabled_only: bool,
+    /// The most characters sent in one request, the maxTextLength of the server. Longer paragraphs
+    /// are split after a sentence.
+    pub lt_max_request_chars: usize,
+    /// The severity of language tool issues by issue type or category id, e.g.
+    /// {"style": "hint", "TYPOGRAPHY": "information"}, a category wins over an issue type. Unlisted
+    /// misspellings are errors, style and typographical issues information and the rest warnings.
+    pub lt_severity: BTreeMap<String, Severity>,
+    /// A LanguageTool server the language server starts and watches itself, read from the workspace config
+    pub lt_server: LtServerConfig,
 
-    #[serde(default)]
+    /// Complete words from the word list
     pub completion_enabled: bool, 
+    /// Turns checkers on or off by name: languagetool, heading, references, bibliography and external.
+    /// Checkers that are not listed are on.
+    pub checkers: BTreeMap<String, bool>,
+    /// Linters run on the prose of each file when it is opened or saved, see ExternalCheckerConfig
+    pub external: Vec<ExternalCheckerConfig>,
+    /// Let the fix all actions apply the first suggestion of issues with several
+    pub fix_all_ambiguous: bool,
+
+    pub inlay_hints: InlayHintsConfig,
+    pub heading: HeadingConfig,
+    pub references: ReferencesConfig,
+    pub bibliography: BibliographyConfig,
+    pub workspace: WorkspaceConfig,
+}
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LtServerConfig {
+    /// Start a local LanguageTool server on lt_api_port instead of connecting to a running one
+    pub managed: bool,
+    /// The languagetool-server.jar of a LanguageTool download
+    pub jar: String,
+    /// The java binary that runs the jar
+    pub java: String,
+    /// More arguments for the server, e.g. ["--config", "server.properties"]
+    pub args: Vec<String>,
+    /// How long to wait for the server to answer after starting it
+    pub startup_timeout_secs: u64,
+    /// How often to check that the server is still running
+    pub health_check_secs: u64,
+}
+// A linter that reads {"file", "text", "segments"} as JSON on stdin, with the prose of the file as
+// text, and writes a JSON list of matches to stdout:
+// [{"offset", "length", "message", "replacements", "rule_id", "severity"}], offsets in chars of text
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExternalCheckerConfig {
+    /// Shown as the source of its diagnostics
+    pub name: String,
+    /// The program to run, looked up in PATH
+    pub command: String,
+    pub args: Vec<String>,
+    /// The linter is killed when it runs longer than this
+    pub timeout_secs: u64,
+}
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct InlayHintsConfig {
+    /// Show the first language tool suggestion after the flagged text, e.g. "→ their"
+    pub suggestions: bool,
+    /// Show the number of words at the end of each paragraph
+    pub word_counts: bool,
+    /// Show the Flesch reading ease of each section after its heading
+    pub readability: bool,
+}
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkspaceConfig {
+    /// How many files the workspace check sends to the checkers at the same time
+    pub concurrency: usize,
+}
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReferencesConfig {
+    /// Check references and labels
+    pub enabled: bool,
+    /// Hint about labels nothing refers to
+    pub unused_labels: bool,
+    /// Flags "see Figure @fig", typst already renders the supplement before the number
+    pub double_wording: bool,
+    /// The words double_wording looks for before a reference
+    pub supplement_words: Vec<String>,
+}
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct BibliographyConfig {
+    /// Check the .bib and hayagriva files the documents use
+    pub enabled: bool,
+    /// Report entries that are never cited
+    pub unused_entries: bool,
+    /// Spell check title and abstract fields with language tool, needs lt_enabled
+    pub spell_check: bool,
+}
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LtLevel {
+    Default,
+    Picky,
+}
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HeadingCase {
+    Title,
+    Sentence,
+    Ignore,
+}
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeadingConfig {
+    /// Check the headings
+    pub enabled: bool,
+    /// The case expected for each heading level, index 0 is level 1. Levels deeper than the list
+    /// use the last entry.
+    pub case_by_level: Vec<HeadingCase>,
+    /// Words that keep their capitalization in sentence case, e.g. "Typst" or "Stockholm"
+    pub proper_nouns: Vec<String>,
+    /// Report headings ending with . , : or ;
+    pub trailing_punctuation: bool,
+    /// Report sibling headings with the same title
+    pub duplicates: bool,
 }
 
 impl Default for RootConfig {
@@ -21,33 +171,301 @@ impl Default for RootConfig {
             lt_enabled: true,
             lt_api_hostname: "http://127.0.0.1".to_string(),
             lt_api_port: "8081".to_string(),
+            lt_username: None,
+            lt_api_key: None,
+            lt_level: LtLevel::Default,
+            lt_mother_tongue: None,
+            lt_preferred_variants: vec![],
+            lt_dictionary: vec![],
+            lt_disabled_rules: vec![],
+            lt_enabled_rules: vec![],
+            lt_enabled_categories: vec![],
+            lt_enabled_only: false,
+            lt_max_request_chars: 10000,
+            lt_severity: BTreeMap::new(),
+            lt_server: LtServerConfig::default(),
 
             completion_enabled: true,
+            checkers: BTreeMap::new(),
+            external: vec![],
+            fix_all_ambiguous: false,
 
+            inlay_hints: InlayHintsConfig::default(),
+            heading: HeadingConfig::default(),
+            references: ReferencesConfig::default(),
+            bibliography: BibliographyConfig::default(),
+            workspace: WorkspaceConfig::default(),
         }
     }
 }
-impl RootConfig {
-    pub fn init_from_file(json_file_path: &Path) -> Option<Self> {
-        let file_str :String;
-        if let Ok(filet) = fs::read_to_string(json_file_path) {
-            println!("Reading config from {}", json_file_path.display());
-            file_str = filet;
-        } else if let Ok(filet) = fs::read_to_string("config.json") {
-            println!("Reading config from {}", "config.json");
-            file_str = filet;
-        } else {
-            println!("Did not find a config file");
-            return None;
+impl Default for ExternalCheckerConfig {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            command: String::new(),
+            args: vec![],
+            timeout_secs: 10,
         }
-        let u: RootConfig = match serde_json::from_str(&file_str) {
+    }
+}
+impl Default for HeadingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            case_by_level: vec![HeadingCase::Title, HeadingCase::Sentence],
+            proper_nouns: vec![],
+            trailing_punctuation: true,
+            duplicates: true,
+        }
+    }
+}
+impl Default for ReferencesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            unused_labels: true,
+            double_wording: true,
+            supplement_words: [
+                "figure", "fig.", "table", "tab.", "section", "sec.", "chapter", "equation", "eq.",
+                "listing", "appendix", "figur", "tabell", "avsnitt", "kapitel", "ekvation",
+            ].iter().map(|c| c.to_string()).collect(),
+        }
+    }
+}
+impl Default for BibliographyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            unused_entries: true,
+            spell_check: true,
+        }
+    }
+}
+impl Default for LtServerConfig {
+    fn default() -> Self {
+        Self {
+            managed: false,
+            jar: String::new(),
+            java: "java".to_string(),
+            args: vec![],
+            startup_timeout_secs: 60,
+            health_check_secs: 30,
+        }
+    }
+}
+impl Default for InlayHintsConfig {
+    fn default() -> Self {
+        Self {
+            suggestions: true,
+            word_counts: true,
+            readability: true,
+        }
+    }
+}
+impl Default for WorkspaceConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 4,
+        }
+    }
+}
+impl HeadingConfig {
+    pub fn case_for_level(&self, level :usize) -> HeadingCase {
+        if self.case_by_level.is_empty() {
+            return HeadingCase::Ignore;
+        }
+        let index = if level == 0 {0} else {level-1};
+        self.case_by_level[index.min(self.case_by_level.len()-1)]
+    }
+}
+impl RootConfig {
+    // The configuration described by a merged settings value, see `Layers`
+    pub fn from_value(settings :serde_json::Value) -> Self {
+        match serde_json::from_value(settings) {
             Ok(c) => c,
             Err(e) => {
-                println!("Parse error: {}", e);
-                return None;
+                log::error!("Invalid configuration, using the defaults: {}", e);
+                RootConfig::default()
+            }
+        }
+    }
+}
+// The JSON schema of the config files, with the `overrides` of project configs
+pub fn schema() -> serde_json::Value {
+    let mut schema = serde_json::to_value(schemars::schema_for!(RootConfig)).unwrap();
+    schema["title"] = serde_json::json!("typst-grammar configuration");
+    let mut properties = schema["properties"].clone();
+    properties["files"] = serde_json::json!({
+        "description": "Globs relative to the config file, the settings apply to the files they match",
+        "anyOf": [
+            { "type": "string" },
+            { "type": "array", "items": { "type": "string" } },
+        ],
+    });
+    schema["properties"]["overrides"] = serde_json::json!({
+        "description": "Settings for some of the files, only in project configs",
+        "type": "array",
+        "items": {
+            "type": "object",
+            "required": ["files"],
+            "properties": properties,
+            "additionalProperties": false,
+        },
+    });
+    schema["properties"]["$schema"] = serde_json::json!({ "type": "string" });
+    schema
+}
+// The file names of a project config, looked for in the directory of a document and its ancestors
+pub const PROJECT_FILE_NAMES :[&str; 2] = [".typst-grammar.toml", ".typst-grammar.json"];
+
+// The defaults with the user config in the XDG config directory on top
+pub fn user_settings() -> serde_json::Value {
+    let mut merged = serde_json::to_value(RootConfig::default()).unwrap();
+    if let Some(c) = user_config_file().and_then(|c| read_value(&c, false)) {
+        let mut errors = vec![];
+        merge_checked(&mut merged, &c, &mut errors);
+        for error in errors {
+            log::error!("In the user config: {}", error);
+        }
+    }
+    merged
+}
+// A project config and the settings of its `[[overrides]]` tables, which apply to the files
+// matching their `files` globs. The globs are relative to the directory of the config.
+pub struct ProjectConfig {
+    pub path: PathBuf,
+    pub settings: serde_json::Value,
+    pub overrides: Vec<(Vec<glob::Pattern>, serde_json::Value)>,
+}
+impl ProjectConfig {
+    pub fn read(dir :&Path) -> Option<Self> {
+        let path = PROJECT_FILE_NAMES.iter().map(|c| dir.join(c)).find(|c| c.is_file())?;
+        let mut settings = read_value(&path, true)?;
+        let mut overrides = vec![];
+        let listed = settings.as_object_mut().and_then(|c| c.remove("overrides"));
+        if let Some(serde_json::Value::Array(listed)) = listed {
+            for mut entry in listed {
+                let files = entry.as_object_mut().and_then(|c| c.remove("files"));
+                let globs :Vec<String> = match files {
+                    Some(serde_json::Value::String(c)) => vec![c],
+                    Some(serde_json::Value::Array(c)) => c.iter().filter_map(|c| c.as_str().map(|c| c.to_string())).collect(),
+                    _ => {
+                        log::error!("An override in {} has no files", path.display());
+                        continue;
+                    },
+                };
+                let patterns :Vec<glob::Pattern> = globs.iter()
+                    .filter_map(|c| match glob::Pattern::new(c) {
+                        Ok(c) => Some(c),
+                        Err(e) => {
+                            log::error!("Bad glob {} in {}: {}", c, path.display(), e);
+                            None
+                        },
+                    })
+                    .collect();
+                overrides.push((patterns, entry));
             }
+        }
+        Some(ProjectConfig {
+            path,
+            settings,
+            overrides,
+        })
+    }
+    // The settings for a file below the directory of the config, overrides applied
+    pub fn settings_for(&self, file :&Path, errors :&mut Vec<String>) -> serde_json::Value {
+        let mut out = serde_json::Value::Object(Default::default());
+        merge_checked(&mut out, &self.settings, errors);
+        let relative = match self.path.parent().and_then(|c| file.strip_prefix(c).ok()) {
+            Some(c) => c,
+            None => return out,
         };
-        Some(u)
+        for (patterns, settings) in &self.overrides {
+            if patterns.iter().any(|c| c.matches_path(relative)) {
+                merge_checked(&mut out, settings, errors);
+            }
+        }
+        out
     }
 }
 
+// $XDG_CONFIG_HOME/typst-grammar/config.json, with ~/.config as the default config directory
+fn user_config_file() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME").filter(|c| !c.is_empty()) {
+        Some(c) => PathBuf::from(c),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("typst-grammar").join("config.json"))
+}
+// Reads a json or, for .toml files, toml config
+pub fn read_value(file :&Path, required :bool) -> Option<serde_json::Value> {
+    let text = match fs::read_to_string(file) {
+        Ok(c) => c,
+        Err(e) => {
+            if required {
+                log::error!("Could not read the config {}: {}", file.display(), e);
+            }
+            return None;
+        }
+    };
+    let parsed :Result<serde_json::Value, String> = if file.extension().is_some_and(|c| c == "toml") {
+        toml::from_str(&text).map_err(|e| e.to_string())
+    } else {
+        serde_json::from_str(&text).map_err(|e| e.to_string())
+    };
+    match parsed {
+        Ok(c) => {
+            log::info!("Reading config from {}", file.display());
+            Some(c)
+        },
+        Err(e) => {
+            log::error!("Parse error in {}: {}", file.display(), e);
+            None
+        }
+    }
+}
+// Merges the keys of `over` into `base` one at a time, keys that are unknown or have the wrong
+// type are skipped and described in `errors`
+pub fn merge_checked(base :&mut serde_json::Value, over :&serde_json::Value, errors :&mut Vec<String>) {
+    let settings = match over {
+        serde_json::Value::Object(c) => c,
+        serde_json::Value::Null => return,
+        _ => {
+            errors.push("The settings must be an object".to_string());
+            return;
+        },
+    };
+    let defaults = serde_json::to_value(RootConfig::default()).unwrap();
+    for (key, value) in settings {
+        if key == "$schema" {
+            continue;
+        }
+        if defaults.get(key).is_none() {
+            errors.push(format!("Unknown setting `{}`", key));
+            continue;
+        }
+        let mut tried = defaults.clone();
+        merge(&mut tried, serde_json::json!({ key.clone(): value.clone() }));
+        if let Err(e) = serde_json::from_value::<RootConfig>(tried) {
+            errors.push(format!("Invalid value for `{}`: {}", key, e));
+            continue;
+        }
+        merge(base, serde_json::json!({ key.clone(): value.clone() }));
+    }
+}
+// Overrides the keys of `base` with the ones set in `over`, sections are merged key by key
+pub fn merge(base :&mut serde_json::Value, over :serde_json::Value) {
+    match (base, over) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(over)) => {
+            for (key, value) in over {
+                match base.get_mut(&key) {
+                    Some(c) => merge(c, value),
+                    None => {
+                        base.insert(key, value);
+                    },
+                }
+            }
+        },
+        (base, over) => *base = over,
+    }
+}
@@ -0,0 +1,633 @@
+use std::ops::Range;
+use serde_json::Value;
+use tower_lsp::lsp_types::{
+    CompletionItem,
+    CompletionItemKind,
+    CompletionTextEdit,
+    Diagnostic,
+    DiagnosticSeverity,
+    Documentation,
+    Position,
+    TextEdit,
+    Url,
+};
+use crate::{config, parse};
+
+// Diagnostics and key completion for the config files when they are open in the editor
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum Kind {
+    Null,
+    Bool,
+    Number,
+    String,
+    Array,
+    Object,
+}
+// A key in the config file. Array items do not add to the path, so the keys of an override
+// are found at ["overrides", key]
+struct Span {
+    path: Vec<String>,
+    key: Range<usize>,
+    value: Range<usize>,
+    kind: Kind,
+}
+struct Scanned {
+    spans: Vec<Span>,
+    //The tables/objects and the text they cover, to know where the cursor is when completing
+    tables: Vec<(Vec<String>, Range<usize>)>,
+}
+
+// If the file is a config of this server: a project config, the user config or the --config file
+pub fn is_config_file(uri :&Url) -> bool {
+    let path = match uri.to_file_path() {
+        Ok(c) => c,
+        Err(_) => return false,
+    };
+    let name = path.file_name().map(|c| c.to_string_lossy().to_string()).unwrap_or_default();
+    if config::PROJECT_FILE_NAMES.contains(&name.as_str()) {
+        return true;
+    }
+    if name == "config.json" && path.parent().is_some_and(|c| c.ends_with("typst-grammar")) {
+        return true;
+    }
+    crate::CONFIG_FILE.read().unwrap().as_ref().is_some_and(|c| c == &path)
+}
+fn is_toml(uri :&Url) -> bool {
+    uri.path().ends_with(".toml")
+}
+// Parses the config file and reports syntax errors, unknown keys, values of the wrong type and
+// bad LanguageTool addresses
+pub fn diagnostics(document :&parse::Document, uri :&Url) -> Vec<Diagnostic> {
+    let text = document.typst_source.text();
+    let mut out :Vec<Diagnostic> = vec![];
+    let mut push = |range :Range<usize>, severity :DiagnosticSeverity, message :String| {
+        if let Some(range) = document.byte_range_to_lsp_range(&range) {
+            out.push(Diagnostic {
+                range,
+                severity: Some(severity),
+                code: None,
+                code_description: None,
+                source: Some("typst-grammar-config".to_string()),
+                message,
+                related_information: None,
+                tags: None,
+                data: None,
+            });
+        }
+    };
+    let parsed :Result<Value, (Range<usize>, String)> = if is_toml(uri) {
+        toml::from_str(text).map_err(|e| (e.span().unwrap_or(0..0), e.message().to_string()))
+    } else {
+        serde_json::from_str(text).map_err(|e| {
+            let at = document.typst_source.line_column_to_byte(e.line().saturating_sub(1), e.column().saturating_sub(1))
+                .unwrap_or(text.len());
+            (at..at, e.to_string())
+        })
+    };
+    let value = match parsed {
+        Ok(c) => c,
+        Err((range, message)) => {
+            push(range, DiagnosticSeverity::ERROR, message);
+            return out;
+        },
+    };
+    let scanned = if is_toml(uri) {scan_toml(text)} else {scan_json(text)};
+    let defaults = serde_json::to_value(config::RootConfig::default()).unwrap();
+    let mut reported :Vec<String> = vec![];
+    for span in &scanned.spans {
+        let path :Vec<&str> = match span.path.first().map(|c| c.as_str()) {
+            Some("$schema") => continue,
+            Some("overrides") if span.path.len() == 1 => {
+                if span.kind != Kind::Array {
+                    push(span.value.clone(), DiagnosticSeverity::ERROR, "`overrides` must be a list of tables".to_string());
+                }
+                continue;
+            },
+            Some("overrides") if span.path.get(1).map(|c| c.as_str()) == Some("files") => {
+                if span.kind != Kind::String && span.kind != Kind::Array {
+                    push(span.value.clone(), DiagnosticSeverity::ERROR, "`files` must be a glob or a list of globs".to_string());
+                }
+                continue;
+            },
+            Some("overrides") => span.path[1..].iter().map(|c| c.as_str()).collect(),
+            _ => span.path.iter().map(|c| c.as_str()).collect(),
+        };
+        let name = path.join(".");
+        let parent = match lookup(&defaults, &path[..path.len()-1]) {
+            // A map like `checkers`, its keys are not known up front
+            Some(Value::Object(c)) if c.is_empty() => continue,
+            Some(Value::Object(c)) => c,
+            // Below a value that is already reported
+            _ => continue,
+        };
+        let key = path[path.len()-1];
+        let expected = match parent.get(key) {
+            Some(c) => c,
+            None => {
+                let message = match suggest(key, parent.keys()) {
+                    Some(c) => format!("Unknown key `{}`, did you mean `{}`?", name, c),
+                    None => format!("Unknown key `{}`", name),
+                };
+                push(span.key.clone(), DiagnosticSeverity::WARNING, message);
+                reported.push(name);
+                continue;
+            },
+        };
+        // A setting that is unset by default can be any type, deserializing finds the wrong ones
+        if !expected.is_null() && kind_of(expected) != span.kind {
+            push(span.value.clone(), DiagnosticSeverity::ERROR, format!(
+                "`{}` must be {}, not {}", name, kind_name(kind_of(expected)), kind_name(span.kind)
+            ));
+            reported.push(name);
+            continue;
+        }
+        let string = text.get(span.value.clone()).map(unquote).unwrap_or_default();
+        match name.as_str() {
+            "lt_api_hostname" => {
+                let valid = Url::parse(&string).is_ok_and(|c| c.scheme() == "http" || c.scheme() == "https");
+                if !valid {
+                    push(span.value.clone(), DiagnosticSeverity::ERROR, format!(
+                        "`{}` is not a URL like http://127.0.0.1", string
+                    ));
+                    reported.push(name);
+                }
+            },
+            "lt_api_port" if !string.is_empty() && string.parse::<u16>().map_or(true, |c| c == 0) => {
+                push(span.value.clone(), DiagnosticSeverity::ERROR, format!(
+                    "`{}` is not a port, it must be a number from 1 to 65535 or empty", string
+                ));
+                reported.push(name);
+            },
+            _ => {},
+        }
+    }
+    // Whatever the checks above missed, like unknown heading cases, is found by deserializing
+    let mut errors :Vec<String> = vec![];
+    let mut settings = value.clone();
+    if let Some(c) = settings.as_object_mut() {
+        c.remove("overrides");
+    }
+    config::merge_checked(&mut Value::Object(Default::default()), &settings, &mut errors);
+    if let Some(Value::Array(overrides)) = value.get("overrides") {
+        for entry in overrides {
+            let mut entry = entry.clone();
+            if let Some(c) = entry.as_object_mut() {
+                c.remove("files");
+            }
+            config::merge_checked(&mut Value::Object(Default::default()), &entry, &mut errors);
+        }
+    }
+    for error in errors {
+        let key = error.split('`').nth(1).unwrap_or("").to_string();
+        if reported.iter().any(|c| c == &key || c.starts_with(&format!("{}.", key))) || error.starts_with("Unknown") {
+            continue;
+        }
+        let range = scanned.spans.iter()
+            .find(|c| c.path.last() == Some(&key))
+            .map_or(0..0, |c| c.key.clone());
+        push(range, DiagnosticSeverity::ERROR, error);
+    }
+    out
+}
+// Completes the keys of the table the cursor is in
+pub fn completion(document :&parse::Document, uri :&Url, position :Position) -> Option<Vec<CompletionItem>> {
+    let text = document.typst_source.text();
+    let offset = document.typst_source.line_column_to_byte(position.line as usize, position.character as usize)?;
+    let toml = is_toml(uri);
+    let scanned = if toml {scan_toml(text)} else {scan_json(text)};
+    let line_start = text[..offset].rfind('\n').map_or(0, |c| c+1);
+    let before = &text[line_start..offset];
+    let word_start = offset - before.chars().rev()
+        .take_while(|c| c.is_alphanumeric() || *c == '_')
+        .map(|c| c.len_utf8())
+        .sum::<usize>();
+    let mut start = word_start;
+    let mut end = offset;
+    if toml {
+        // Only before the = of a line
+        if before.contains('=') || before.trim_start().starts_with('[') {
+            return None;
+        }
+    } else {
+        if text[..word_start].ends_with('"') {
+            start -= 1;
+        }
+        let previous = text[..start].trim_end().chars().last();
+        if previous != Some('{') && previous != Some(',') {
+            return None;
+        }
+        if text[offset..].starts_with('"') {
+            end += 1;
+        }
+    }
+    let (mut path, _) = scanned.tables.iter()
+        .filter(|(_, range)| range.start < offset && offset <= range.end)
+        .max_by_key(|(_, range)| range.start)?
+        .clone();
+    let in_override = path.first().map(|c| c.as_str()) == Some("overrides");
+    if in_override {
+        path.remove(0);
+    }
+    let defaults = serde_json::to_value(config::RootConfig::default()).unwrap();
+    let keys = match lookup(&defaults, &path.iter().map(|c| c.as_str()).collect::<Vec<&str>>()) {
+        Some(Value::Object(c)) => c.clone(),
+        _ => return None,
+    };
+    let present :Vec<&Vec<String>> = scanned.spans.iter().map(|c| &c.path).collect();
+    let range = document.byte_range_to_lsp_range(&(start..end))?;
+    let schema = config::schema();
+    let mut out :Vec<CompletionItem> = vec![];
+    for (key, default) in keys {
+        let mut key_path = path.clone();
+        key_path.push(key.clone());
+        if present.contains(&&key_path) && !in_override {
+            continue;
+        }
+        let kind = kind_of(&default);
+        let new_text = match (toml, kind) {
+            (true, Kind::Object) if path.is_empty() && before.trim().is_empty() => format!("[{}]", key),
+            (true, Kind::Object) => continue,
+            (true, _) => format!("{} = ", key),
+            (false, Kind::Object) => format!("\"{}\": {{}}", key),
+            (false, _) => format!("\"{}\": ", key),
+        };
+        let key_names :Vec<&str> = key_path.iter().map(|c| c.as_str()).collect();
+        out.push(CompletionItem {
+            label: key.clone(),
+            kind: Some(CompletionItemKind::PROPERTY),
+            detail: Some(match kind {
+                Kind::Object => kind_name(kind).to_string(),
+                _ => format!("{}, default {}", kind_name(kind), default),
+            }),
+            documentation: describe(&schema, &key_names).map(Documentation::String),
+            filter_text: Some(if toml {key.clone()} else {format!("\"{}", key)}),
+            text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                range,
+                new_text,
+            })),
+            ..Default::default()
+        });
+    }
+    Some(out)
+}
+fn lookup<'a>(value :&'a Value, path :&[&str]) -> Option<&'a Value> {
+    let mut at = value;
+    for key in path {
+        at = at.get(key)?;
+    }
+    Some(at)
+}
+// The description of a key in the schema, following the references to the sections
+fn describe(schema :&Value, path :&[&str]) -> Option<String> {
+    let mut at = schema;
+    for key in path {
+        at = resolve_ref(schema, at);
+        at = at.get("properties")?.get(key)?;
+    }
+    if let Some(c) = at.get("description").and_then(|c| c.as_str()) {
+        return Some(c.to_string());
+    }
+    resolve_ref(schema, at).get("description").and_then(|c| c.as_str()).map(|c| c.to_string())
+}
+fn resolve_ref<'a>(schema :&'a Value, at :&'a Value) -> &'a Value {
+    let reference = at.get("$ref")
+        .or_else(|| at.get("allOf").and_then(|c| c.get(0)).and_then(|c| c.get("$ref")))
+        .and_then(|c| c.as_str());
+    match reference.and_then(|c| c.strip_prefix("#/definitions/")) {
+        Some(name) => schema.get("definitions").and_then(|c| c.get(name)).unwrap_or(at),
+        None => at,
+    }
+}
+fn suggest<'a>(key :&str, known :impl Iterator<Item = &'a String>) -> Option<&'a String> {
+    known
+        .map(|c| (rust_fuzzy_search::fuzzy_compare(key, c), c))
+        .filter(|(score, _)| *score > 0.5)
+        // Of keys as similar, like lt_enabled and lt_enabled_rules, the one closest in length
+        .max_by(|a, b| a.0.total_cmp(&b.0)
+            .then_with(|| key.len().abs_diff(b.1.len()).cmp(&key.len().abs_diff(a.1.len()))))
+        .map(|(_, c)| c)
+}
+fn kind_of(value :&Value) -> Kind {
+    match value {
+        Value::Null => Kind::Null,
+        Value::Bool(_) => Kind::Bool,
+        Value::Number(_) => Kind::Number,
+        Value::String(_) => Kind::String,
+        Value::Array(_) => Kind::Array,
+        Value::Object(_) => Kind::Object,
+    }
+}
+fn kind_name(kind :Kind) -> &'static str {
+    match kind {
+        Kind::Null => "null",
+        Kind::Bool => "a boolean",
+        Kind::Number => "a number",
+        Kind::String => "a string",
+        Kind::Array => "a list",
+        Kind::Object => "a table",
+    }
+}
+fn unquote(text :&str) -> String {
+    text.trim().trim_matches('"').trim_matches('\'').to_string()
+}
+
+fn scan_json(text :&str) -> Scanned {
+    let mut scanner = JsonScanner {
+        text,
+        bytes: text.as_bytes(),
+        at: 0,
+        out: Scanned {
+            spans: vec![],
+            tables: vec![],
+        },
+    };
+    scanner.value(&[]);
+    scanner.out
+}
+struct JsonScanner<'a> {
+    text: &'a str,
+    bytes: &'a [u8],
+    at: usize,
+    out: Scanned,
+}
+impl<'a> JsonScanner<'a> {
+    // Stops at the first syntax error, what was read before it is kept
+    fn value(&mut self, path :&[String]) -> Option<Kind> {
+        self.skip_whitespace();
+        match self.bytes.get(self.at)? {
+            b'{' => self.object(path),
+            b'[' => {
+                self.at += 1;
+                loop {
+                    self.skip_whitespace();
+                    match self.bytes.get(self.at)? {
+                        b']' => {
+                            self.at += 1;
+                            return Some(Kind::Array);
+                        },
+                        b',' => self.at += 1,
+                        _ => {
+                            self.value(path)?;
+                        },
+                    }
+                }
+            },
+            b'"' => {
+                self.string()?;
+                Some(Kind::String)
+            },
+            _ => {
+                let start = self.at;
+                while self.bytes.get(self.at).is_some_and(|c| c.is_ascii_alphanumeric() || matches!(c, b'-' | b'+' | b'.')) {
+                    self.at += 1;
+                }
+                match &self.text[start..self.at] {
+                    "true" | "false" => Some(Kind::Bool),
+                    "null" => Some(Kind::Null),
+                    "" => None,
+                    _ => Some(Kind::Number),
+                }
+            },
+        }
+    }
+    fn object(&mut self, path :&[String]) -> Option<Kind> {
+        let start = self.at;
+        self.at += 1;
+        let end = self.members(path);
+        self.out.tables.push((path.to_vec(), start..end.unwrap_or(self.text.len())));
+        end.map(|_| Kind::Object)
+    }
+    fn members(&mut self, path :&[String]) -> Option<usize> {
+        loop {
+            self.skip_whitespace();
+            match self.bytes.get(self.at)? {
+                b'}' => {
+                    self.at += 1;
+                    return Some(self.at);
+                },
+                b',' => self.at += 1,
+                b'"' => {
+                    let key_start = self.at;
+                    let key = self.string()?;
+                    let key_range = key_start..self.at;
+                    self.skip_whitespace();
+                    if self.bytes.get(self.at) != Some(&b':') {
+                        return None;
+                    }
+                    self.at += 1;
+                    self.skip_whitespace();
+                    let value_start = self.at;
+                    let mut key_path = path.to_vec();
+                    key_path.push(key);
+                    let kind = self.value(&key_path)?;
+                    self.out.spans.push(Span {
+                        path: key_path,
+                        key: key_range,
+                        value: value_start..self.at,
+                        kind,
+                    });
+                },
+                _ => return None,
+            }
+        }
+    }
+    fn string(&mut self) -> Option<String> {
+        let start = self.at + 1;
+        self.at += 1;
+        loop {
+            match self.bytes.get(self.at)? {
+                b'\\' => self.at += 2,
+                b'"' => {
+                    self.at += 1;
+                    return Some(self.text[start..self.at-1].to_string());
+                },
+                _ => self.at += 1,
+            }
+        }
+    }
+    fn skip_whitespace(&mut self) {
+        while self.bytes.get(self.at).is_some_and(|c| c.is_ascii_whitespace()) {
+            self.at += 1;
+        }
+    }
+}
+// A line based reader, enough for the flat tables of the config
+fn scan_toml(text :&str) -> Scanned {
+    let mut out = Scanned {
+        spans: vec![],
+        tables: vec![(vec![], 0..text.len())],
+    };
+    let mut table :Vec<String> = vec![];
+    let mut line_start = 0;
+    let mut open_value :Option<(usize, i32)> = None;
+    for line in text.split_inclusive('\n') {
+        let start = line_start;
+        line_start += line.len();
+        let content = line.trim_end();
+        let trimmed = content.trim_start();
+        let indent = content.len() - trimmed.len();
+        // The lines of an array spanning several lines
+        if let Some((span, depth)) = open_value.take() {
+            let depth = depth + bracket_depth(content);
+            out.spans[span].value.end = start + content.len();
+            if depth > 0 {
+                open_value = Some((span, depth));
+            }
+            continue;
+        }
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if trimmed.starts_with('[') {
+            let array = trimmed.starts_with("[[");
+            let name = trimmed.trim_start_matches('[').split(']').next().unwrap_or("").trim();
+            table = name.split('.').map(unquote).collect();
+            if let Some(last) = out.tables.last_mut() {
+                last.1.end = start;
+            }
+            out.tables.push((table.clone(), start..text.len()));
+            let name_start = start + indent + content[indent..].find(name).unwrap_or(0);
+            out.spans.push(Span {
+                path: table.clone(),
+                key: name_start..name_start+name.len(),
+                value: name_start..name_start+name.len(),
+                kind: if array {Kind::Array} else {Kind::Object},
+            });
+            continue;
+        }
+        let equals = match trimmed.find('=') {
+            Some(c) => c,
+            None => continue,
+        };
+        let key = trimmed[..equals].trim();
+        let value = trimmed[equals+1..].trim();
+        let key_start = start + indent;
+        let value_start = start + content.len() - value.len();
+        let mut path = table.clone();
+        path.extend(key.split('.').map(unquote));
+        let kind = match value.chars().next() {
+            Some('"') | Some('\'') => Kind::String,
+            Some('[') => Kind::Array,
+            Some('{') => Kind::Object,
+            Some('t') | Some('f') => Kind::Bool,
+            _ => Kind::Number,
+        };
+        out.spans.push(Span {
+            path,
+            key: key_start..key_start+key.len(),
+            value: value_start..start+content.len(),
+            kind,
+        });
+        let depth = bracket_depth(value);
+        if depth > 0 {
+            open_value = Some((out.spans.len()-1, depth));
+        }
+    }
+    out
+}
+fn bracket_depth(text :&str) -> i32 {
+    let mut depth = 0;
+    let mut in_string = false;
+    for c in text.chars() {
+        match c {
+            '"' => in_string = !in_string,
+            '[' if !in_string => depth += 1,
+            ']' if !in_string => depth -= 1,
+            '#' if !in_string => break,
+            _ => {},
+        }
+    }
+    depth
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(name :&str, text :&str) -> Vec<String> {
+        let document = parse::Document::new(0, &text.to_string());
+        let uri = Url::parse(&format!("file:///tmp/{}", name)).unwrap();
+        diagnostics(&document, &uri).into_iter().map(|c| c.message).collect()
+    }
+    fn paths(scanned :&Scanned) -> Vec<String> {
+        scanned.spans.iter().map(|c| c.path.join(".")).collect()
+    }
+
+    #[test]
+    fn unknown_keys() {
+        assert_eq!(messages(".typst-grammar.toml", "lt_enabeld = true\n"), vec![
+            "Unknown key `lt_enabeld`, did you mean `lt_enabled`?",
+        ]);
+        assert_eq!(messages(".typst-grammar.json", "{\"heading\": {\"enabled\": true, \"nothing\": 1}}"), vec![
+            "Unknown key `heading.nothing`",
+        ]);
+    }
+
+    #[test]
+    fn values_of_the_wrong_type() {
+        assert_eq!(messages(".typst-grammar.toml", "lt_enabled = \"yes\"\nlt_max_request_chars = 5000\n"), vec![
+            "`lt_enabled` must be a boolean, not a string",
+        ]);
+        assert_eq!(messages(".typst-grammar.json", "{\"lt_dictionary\": \"word\"}"), vec![
+            "`lt_dictionary` must be a list, not a string",
+        ]);
+    }
+
+    #[test]
+    fn keys_of_overrides() {
+        let text = "lt_enabled = true\n\n[[overrides]]\nfiles = \"drafts/*.typ\"\nlt_enabeld = false\n";
+        assert_eq!(paths(&scan_toml(text)), vec!["lt_enabled", "overrides", "overrides.files", "overrides.lt_enabeld"]);
+        assert_eq!(messages(".typst-grammar.toml", text), vec![
+            "Unknown key `lt_enabeld`, did you mean `lt_enabled`?",
+        ]);
+        let text = "{\"overrides\": [{\"files\": 1, \"heading\": {\"enabled\": false}}]}";
+        assert_eq!(paths(&scan_json(text)), vec![
+            "overrides.files", "overrides.heading.enabled", "overrides.heading", "overrides",
+        ]);
+        assert_eq!(messages(".typst-grammar.json", text), vec!["`files` must be a glob or a list of globs"]);
+    }
+
+    #[test]
+    fn multi_line_toml_arrays() {
+        let text = "lt_dictionary = [\n    \"typst\",\n    \"[not a table]\",\n]\nlt_enabled = true\n";
+        let scanned = scan_toml(text);
+        assert_eq!(paths(&scanned), vec!["lt_dictionary", "lt_enabled"]);
+        assert_eq!(scanned.spans[0].kind, Kind::Array);
+        assert!(text[scanned.spans[0].value.clone()].ends_with(']'));
+        assert!(messages(".typst-grammar.toml", text).is_empty());
+    }
+
+    #[test]
+    fn bad_addresses() {
+        assert_eq!(messages(".typst-grammar.toml", "lt_api_hostname = \"localhost\"\nlt_api_port = \"99999\"\n"), vec![
+            "`localhost` is not a URL like http://127.0.0.1",
+            "`99999` is not a port, it must be a number from 1 to 65535 or empty",
+        ]);
+        assert!(messages(".typst-grammar.toml", "lt_api_hostname = \"https://lt.example.org\"\nlt_api_port = \"\"\n").is_empty());
+    }
+
+    #[test]
+    fn completes_the_keys_of_the_table() {
+        let uri = Url::parse("file:///tmp/.typst-grammar.json").unwrap();
+        let text = "{\n  \"heading\": {\n    \"enabled\": true,\n    \n  }\n}";
+        let document = parse::Document::new(0, &text.to_string());
+        let labels :Vec<String> = completion(&document, &uri, Position::new(3, 4)).unwrap()
+            .into_iter().map(|c| c.label).collect();
+        assert!(labels.contains(&String::from("case_by_level")));
+        assert!(!labels.contains(&String::from("enabled")));
+        assert!(!labels.contains(&String::from("lt_enabled")));
+
+        let uri = Url::parse("file:///tmp/.typst-grammar.toml").unwrap();
+        let text = "lt_enabled = true\n\n";
+        let document = parse::Document::new(0, &text.to_string());
+        let items = completion(&document, &uri, Position::new(1, 0)).unwrap();
+        let edit = |label :&str| match &items.iter().find(|c| c.label == label)?.text_edit {
+            Some(CompletionTextEdit::Edit(c)) => Some(c.new_text.clone()),
+            _ => None,
+        };
+        assert_eq!(edit("lt_enabled"), None);
+        assert_eq!(edit("lt_api_port").as_deref(), Some("lt_api_port = "));
+        assert_eq!(edit("heading").as_deref(), Some("[heading]"));
+    }
+}
@@ -3,6 +3,10 @@ mod components;
 mod word_query;
 mod semantic_token;
 mod parse;
+mod project;
+mod cli;
+mod settings;
+mod config_file;
 use std::ops::{Deref, DerefMut};
 use dashmap::DashMap;
 use tower_lsp::jsonrpc::Result;
@@ -13,18 +17,54 @@ use parse::Backend;
 use lazy_static::lazy_static;
 use std::process::Command;
 use std::env;
-use std::path::Path;
+use std::path::PathBuf;
 use std::sync::RwLock;
+use clap::{Parser, Subcommand};
 
 lazy_static! {
     static ref WORD_LIST: Vec<String> = word_query::file_to_array("en_wordlist.txt");
     static ref CONFIG: RwLock<config::RootConfig> = RwLock::new(config::RootConfig::default());
+    //The file given with --config, read again when the workspace root is known
+    static ref CONFIG_FILE: RwLock<Option<PathBuf>> = RwLock::new(None);
 }
 
 
+impl Backend {
+    async fn cancel_progress(&self, params: WorkDoneProgressCancelParams) {
+        components::cancel_progress(params);
+    }
+    async fn publish_config_diagnostics(&self, uri: &Url) {
+        let diagnostics = match self.with_document(uri, |c| config_file::diagnostics(c, uri)) {
+            Some(c) => c,
+            None => return,
+        };
+        if components::pulls_diagnostics() {
+            components::refresh_diagnostics(self);
+            return;
+        }
+        self.client.publish_diagnostics(uri.clone(), diagnostics, None).await;
+    }
+}
+
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
-    async fn initialize(&self, _params: InitializeParams) -> Result<InitializeResult>{
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult>{
+        {
+            let mut roots = self.project.roots.write().unwrap();
+            if let Some(folders) = &params.workspace_folders {
+                roots.extend(folders.iter().filter_map(|c| c.uri.to_file_path().ok()));
+            } else if let Some(root) = params.root_uri.as_ref().and_then(|c| c.to_file_path().ok()) {
+                roots.push(root);
+            }
+            for root in roots.iter() {
+                components::load_baseline(root);
+            }
+        }
+        // The config is read again with the workspace config and editor settings in initialized
+        self.init_settings(&params);
+        components::set_resolve_support(&params.capabilities);
+        components::set_pull_support(&params.capabilities);
+        components::set_inlay_hint_support(&params.capabilities);
         Ok(InitializeResult {
             server_info: Some(ServerInfo {
                 name: "Typst grammar lsp".to_string(),
@@ -32,13 +72,21 @@ impl LanguageServer for Backend {
             }),
             offset_encoding: None,
             capabilities: ServerCapabilities {
-                inlay_hint_provider: Some(OneOf::Left(true)),
-                text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::INCREMENTAL,
+                inlay_hint_provider: Some(OneOf::Right(InlayHintServerCapabilities::Options(InlayHintOptions {
+                    work_done_progress_options: Default::default(),
+                    resolve_provider: Some(true),
+                }))),
+                text_document_sync: Some(TextDocumentSyncCapability::Options(
+                    TextDocumentSyncOptions {
+                        open_close: Some(true),
+                        change: Some(TextDocumentSyncKind::INCREMENTAL),
+                        save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+                        ..Default::default()
+                    }
                 )),
                 completion_provider: Some(CompletionOptions {
                     resolve_provider: Some(false),
-                    trigger_characters: Some(vec![".".to_string()]),
+                    trigger_characters: Some(vec![".".to_string(), "@".to_string(), "<".to_string()]),
                     all_commit_characters: None,
                     work_done_progress_options: WorkDoneProgressOptions{
                         work_done_progress: None,
@@ -48,7 +96,11 @@ impl LanguageServer for Backend {
                     }),
                 }),
                 execute_command_provider: Some(ExecuteCommandOptions {
-                    commands: vec!["dummy.do_something".to_string()],
+                    commands: [
+                        components::CHECK_WORKSPACE_COMMAND.to_string(),
+                        components::WRITE_BASELINE_COMMAND.to_string(),
+                        components::PRUNE_BASELINE_COMMAND.to_string(),
+                    ].into_iter().chain(self.checkers.commands()).collect(),
                     work_done_progress_options: Default::default(),
                 }),
 
@@ -87,7 +139,14 @@ impl LanguageServer for Backend {
                 // definition: Some(GotoCapability::default()),
                 definition_provider: Some(OneOf::Left(true)),
                 references_provider: Some(OneOf::Left(true)),
-                rename_provider: Some(OneOf::Left(true)),
+                rename_provider: Some(OneOf::Right(RenameOptions {
+                    prepare_provider: Some(true),
+                    work_done_progress_options: Default::default(),
+                })),
+                document_symbol_provider: Some(OneOf::Left(true)),
+                workspace_symbol_provider: Some(OneOf::Left(true)),
+                folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+                selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
                 ..ServerCapabilities {
                     position_encoding: None,
                     text_document_sync: None,
@@ -110,7 +169,11 @@ impl LanguageServer for Backend {
                     document_highlight_provider: None,
                     document_symbol_provider: None,
                     workspace_symbol_provider: None,
-                    code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+                    code_action_provider: Some(CodeActionProviderCapability::Options(CodeActionOptions {
+                        code_action_kinds: Some(vec![CodeActionKind::QUICKFIX, CodeActionKind::SOURCE_FIX_ALL]),
+                        work_done_progress_options: Default::default(),
+                        resolve_provider: Some(true),
+                    })),
                     code_lens_provider: None,
                     document_formatting_provider: None,
                     document_range_formatting_provider: None,
@@ -129,38 +192,62 @@ impl LanguageServer for Backend {
                     inlay_hint_provider: None,
                     linked_editing_range_provider: None,
                     experimental: None,
-                    diagnostic_provider: None,
+                    // Only for clients that pull, the others keep getting pushed diagnostics
+                    diagnostic_provider: if components::pulls_diagnostics() {
+                        Some(DiagnosticServerCapabilities::Options(DiagnosticOptions {
+                            identifier: Some("typst-grammar".to_string()),
+                            inter_file_dependencies: true,
+                            workspace_diagnostics: true,
+                            work_done_progress_options: Default::default(),
+                        }))
+                    } else {
+                        None
+                    },
                 }
             },
         })
     }
-    async fn initialized(&self, params: InitializedParams) {
+    async fn initialized(&self, _params: InitializedParams) {
         self.client
             .log_message(MessageType::INFO, "initialized!")
             .await;
+        self.register_settings().await;
+        components::start_language_tool(self.client.clone());
+    }
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        self.settings_changed(params.settings).await;
+    }
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        self.workspace_folders_changed(params).await;
+    }
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        self.config_files_changed(params).await;
     }
     async fn shutdown(&self) -> Result<()>{
+        components::shutdown_language_tool().await;
         Ok(())
     }
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
-        self.create_document(&params.text_document.uri, params.text_document.version as isize, &params.text_document.text);
+        let uri = &params.text_document.uri;
+        self.create_document(uri, params.text_document.version as isize, &params.text_document.text);
         self.client
             .log_message(MessageType::INFO, "file opened!")
             .await;
+        self.forget_disk_document(uri);
+        components::forget_labels(uri);
+        if config_file::is_config_file(uri) {
+            self.publish_config_diagnostics(uri).await;
+            return;
+        }
+        components::check_project(&self, uri).await;
+        components::check_saved(&self, uri).await;
     }
     async fn did_save(&self, params: DidSaveTextDocumentParams) {
-        return;
-        let text = match params.text {
-            Some(c) => {c},
-            None => {
-                self.client
-                    .log_message(MessageType::INFO, "file saved no text!")
-                    .await;
-                return;
-            }
-        };
-        let uri = &params.text_document.uri;
-        self.create_document(uri, 0, &text)
+        if config_file::is_config_file(&params.text_document.uri) {
+            self.reload_config().await;
+            return;
+        }
+        components::check_saved(&self, &params.text_document.uri).await;
     }
     async fn did_close(&self, _: DidCloseTextDocumentParams) {
         self.client
@@ -169,15 +256,21 @@ impl LanguageServer for Backend {
     }
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
         let uri = &params.text_document.uri;
-        let mut working_doc_ref = match __self.document_map.get_mut(&uri) {
-            Some(c) => {c},
-            None => {return},
-        };
-        let working_doc :&mut parse::Document =  working_doc_ref.deref_mut();
-        for change in params.content_changes {
-            working_doc.change(params.text_document.version, &change);
+        {
+            let mut working_doc_ref = match __self.document_map.get_mut(uri) {
+                Some(c) => {c},
+                None => {return},
+            };
+            let working_doc :&mut parse::Document =  working_doc_ref.deref_mut();
+            for change in params.content_changes {
+                working_doc.change(params.text_document.version, &change);
+            }
         }
-        components::send_diagnostics(&self.client, working_doc, &uri).await;
+        if config_file::is_config_file(uri) {
+            self.publish_config_diagnostics(uri).await;
+            return;
+        }
+        components::check_change(self, uri).await;
     }
     async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
 
@@ -240,15 +333,26 @@ impl LanguageServer for Backend {
         }))
     }
     async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
+        let config_uri = &params.text_document_position.text_document.uri;
+        if config_file::is_config_file(config_uri) {
+            let items = self.with_document(config_uri, |c| {
+                config_file::completion(c, config_uri, params.text_document_position.position)
+            }).flatten();
+            return Ok(items.map(CompletionResponse::Array));
+        }
+        // Labels and citation keys after `@`, `#cite(<` and `#ref(<`, the setting is for the dictionary
+        let uri = params.text_document_position.text_document.uri.clone();
+        if let Some(c) = components::reference_completion(&self, &uri, params.text_document_position.position) {
+            return Ok(Some(CompletionResponse::Array(c)));
+        }
         {
-            if !CONFIG.read().unwrap().completion_enabled {
+            if !settings::config_for(&params.text_document_position.text_document.uri).completion_enabled {
                 return Ok(None);
             }
         }
         self.client
-            .log_message(MessageType::INFO, format!("Completion!"))
+            .log_message(MessageType::INFO, "Completion!".to_string())
             .await;
-        let uri = params.text_document_position.text_document.uri;
         let working_doc_ref = match __self.document_map.get(&uri) {
             Some(c) => {c},
             None => {return 
@@ -298,32 +402,174 @@ impl LanguageServer for Backend {
     async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
         self.client.log_message(tower_lsp::lsp_types::MessageType::LOG, "CODE ACTION").await;
         let uri = &params.text_document.uri;
-        let working_doc_ref = match __self.document_map.get(&uri) {
+        let working_doc_ref = match __self.document_map.get(uri) {
             Some(c) => {c},
             None => {return Ok(None)
             },
         };
         let working_doc :&parse::Document = working_doc_ref.deref();
         self.client.log_message(tower_lsp::lsp_types::MessageType::LOG, "COMPONENTS").await;
-        let x = components::code_actions(&self.client, working_doc, &params).await;
+        let x = components::code_actions(&self, working_doc, &params).await;
         Ok(Some(x))
     }
+    async fn code_action_resolve(&self, params: CodeAction) -> Result<CodeAction> {
+        Ok(components::resolve_code_action(&self, params))
+    }
+    async fn goto_definition(&self, params: GotoDefinitionParams) -> Result<Option<GotoDefinitionResponse>> {
+        let position = &params.text_document_position_params;
+        let found = components::definition(&self, &position.text_document.uri, position.position);
+        Ok(match found {
+            Some(c) if c.len() == 1 => Some(GotoDefinitionResponse::Scalar(c[0].clone())),
+            Some(c) if !c.is_empty() => Some(GotoDefinitionResponse::Array(c)),
+            _ => None,
+        })
+    }
+    async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
+        let position = &params.text_document_position;
+        Ok(components::references(
+            &self,
+            &position.text_document.uri,
+            position.position,
+            params.context.include_declaration,
+        ))
+    }
+    async fn prepare_rename(&self, params: TextDocumentPositionParams) -> Result<Option<PrepareRenameResponse>> {
+        Ok(components::prepare_rename(&self, &params.text_document.uri, params.position))
+    }
+    async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
+        let position = &params.text_document_position;
+        match components::rename(&self, &position.text_document.uri, position.position, &params.new_name) {
+            Ok(c) => Ok(c),
+            Err(e) => Err(tower_lsp::jsonrpc::Error::invalid_params(e)),
+        }
+    }
+    async fn document_symbol(&self, params: DocumentSymbolParams) -> Result<Option<DocumentSymbolResponse>> {
+        let uri = &params.text_document.uri;
+        if config_file::is_config_file(uri) {
+            return Ok(None);
+        }
+        let symbols = self.with_document(uri, |c| components::document_symbols(&self, c, uri));
+        Ok(symbols.map(DocumentSymbolResponse::Nested))
+    }
+    async fn symbol(&self, params: WorkspaceSymbolParams) -> Result<Option<Vec<SymbolInformation>>> {
+        Ok(Some(components::workspace_symbols(&self, &params.query)))
+    }
+    async fn folding_range(&self, params: FoldingRangeParams) -> Result<Option<Vec<FoldingRange>>> {
+        let uri = &params.text_document.uri;
+        if config_file::is_config_file(uri) {
+            return Ok(None);
+        }
+        Ok(self.with_document(uri, components::folding_ranges))
+    }
+    async fn selection_range(&self, params: SelectionRangeParams) -> Result<Option<Vec<SelectionRange>>> {
+        let uri = &params.text_document.uri;
+        if config_file::is_config_file(uri) {
+            return Ok(None);
+        }
+        Ok(self.with_document(uri, |c| components::selection_ranges(c, &params.positions)))
+    }
+    async fn inlay_hint(&self, params: InlayHintParams) -> Result<Option<Vec<InlayHint>>> {
+        let uri = &params.text_document.uri;
+        if config_file::is_config_file(uri) {
+            return Ok(None);
+        }
+        let hints = self.with_document(uri, |c| {
+            let range = c.lsp_range_to_byte_range(&params.range)?;
+            Some(components::inlay_hints(c, uri, &range))
+        }).flatten();
+        Ok(hints)
+    }
+    async fn inlay_hint_resolve(&self, params: InlayHint) -> Result<InlayHint> {
+        Ok(components::resolve_inlay_hint(&self, params))
+    }
+    async fn diagnostic(&self, params: DocumentDiagnosticParams) -> Result<DocumentDiagnosticReportResult> {
+        let report = components::document_diagnostic_report(
+            &self,
+            &params.text_document.uri,
+            params.previous_result_id.as_deref(),
+        );
+        Ok(DocumentDiagnosticReportResult::Report(report))
+    }
+    async fn workspace_diagnostic(&self, params: WorkspaceDiagnosticParams) -> Result<WorkspaceDiagnosticReportResult> {
+        let report = components::workspace_diagnostic_report(&self, &params.previous_result_ids);
+        Ok(WorkspaceDiagnosticReportResult::Report(report))
+    }
     async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<serde_json::Value>> {
-        crate::components::code_action_resolve(&params, &self).await;
+        crate::components::execute_command(&params, &self).await;
         Ok(None)
     }
 }
 
+#[derive(Parser)]
+#[command(name = "typst-lsp", version, about = "A grammar language server for typst")]
+struct Args {
+    /// A config file that overrides the user and workspace configs
+    #[arg(long, global = true, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Talk to the editor over stdin and stdout, the only transport there is
+    #[arg(long)]
+    stdio: bool,
+    /// Write the log to this file instead of stderr
+    #[arg(long, global = true, value_name = "FILE")]
+    log_file: Option<PathBuf>,
+    /// off, error, warn, info, debug or trace, overrides RUST_LOG
+    #[arg(long, global = true, value_name = "LEVEL")]
+    log_level: Option<log::LevelFilter>,
+    /// Print the effective configuration as json and exit
+    #[arg(long)]
+    print_config: bool,
+    /// Print the JSON schema of the config files and exit
+    #[arg(long)]
+    print_schema: bool,
+    #[command(subcommand)]
+    command: Option<SubCommand>,
+}
+#[derive(Subcommand)]
+enum SubCommand {
+    /// Check files without an editor and print the issues, for CI
+    Check(cli::CheckArgs),
+}
+
+// Logs to stderr or the log file, stdout is the connection to the editor
+fn init_logging(args :&Args) {
+    let mut builder = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"));
+    if let Some(level) = args.log_level {
+        builder.filter_level(level);
+    }
+    builder.target(env_logger::Target::Stderr);
+    if let Some(path) = &args.log_file {
+        match std::fs::OpenOptions::new().create(true).append(true).open(path) {
+            Ok(c) => {
+                builder.target(env_logger::Target::Pipe(Box::new(c)));
+            },
+            Err(e) => eprintln!("Could not open the log file {}: {}", path.display(), e),
+        }
+    }
+    builder.init();
+}
+
 #[tokio::main]
 async fn main() {
-    env_logger::init();
-    let args: Vec<String> = env::args().collect();
+    let args = Args::parse();
+    init_logging(&args);
+    let workspace = env::current_dir().ok();
     {
-        let mut config = CONFIG.write().unwrap();
-        if let Some(c) = config::RootConfig::init_from_file(Path::new(&args[0])){
-            *config = c;
+        *CONFIG_FILE.write().unwrap() = args.config.clone();
+        for error in settings::load_config(workspace.as_deref()) {
+            log::error!("{}", error);
         }
     }
+    if args.print_schema {
+        println!("{}", serde_json::to_string_pretty(&config::schema()).unwrap());
+        return;
+    }
+    if args.print_config {
+        println!("{}", serde_json::to_string_pretty(CONFIG.read().unwrap().deref()).unwrap());
+        return;
+    }
+    if let Some(SubCommand::Check(c)) = args.command {
+        std::process::exit(cli::run(c).await);
+    }
 
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
@@ -331,7 +577,10 @@ async fn main() {
     let (service, socket) = LspService::build(|client| Backend {
         client,
         document_map: DashMap::new(),
+        project: project::Project::new(),
+        checkers: components::Checkers::builtin(),
     })
+    .custom_method("window/workDoneProgress/cancel", Backend::cancel_progress)
     .finish();
 
     Server::new(stdin, stdout, socket).serve(service).await;
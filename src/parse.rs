@@ -1,4 +1,4 @@
-use std::{char, collections::{BTreeMap, HashMap}, usize};
+use std::collections::BTreeMap;
 use dashmap::DashMap;
 use tower_lsp::{lsp_types::{Position, Url}, Client};
 use typst_syntax::Source;
@@ -8,12 +8,12 @@ use typst_syntax::{SyntaxKind, SyntaxNode};
 pub struct Backend {
     pub client: Client,
     pub document_map: DashMap<Url, Document>,
+    pub project: crate::project::Project,
+    //The checkers diagnostics come from, see components::Checkers::builtin
+    pub checkers: crate::components::Checkers,
 }
 pub struct Document {
     pub typst_source: Source,
-    pub text_chunks: Vec<Range<usize>>,
-    pub diagnostics: Vec<crate::components::Diagnostic>,
-    pub diagnostics_lsp: Vec<tower_lsp::lsp_types::Diagnostic>,
     //the source changes made, usefull for making the ranges stored up-to-date. The key is the
     //version of the document.
     pub source_change: BTreeMap<isize, Vec<SourceChange>>,
@@ -35,14 +35,8 @@ impl Backend {
 impl Document {
     pub fn new(version :isize, in_str :&String) -> Self {
         let typst_source = Source::detached(in_str);
-        let typst_root_node  = typst_source.root();
-        let dirty_ranges = parse_recursive(&typst_source, in_str, typst_root_node);
-        let clean_ranges = cleanup_range(dirty_ranges);
         Document {
             typst_source,
-            text_chunks: clean_ranges,
-            diagnostics_lsp: vec!{},
-            diagnostics: vec!{},
             source_change: BTreeMap::new(),
             latest_version: version,
         }
@@ -53,7 +47,7 @@ impl Document {
         self.typst_source.edit(range.clone(), &change.text);
         self.source_change
             .entry(version as isize)
-            .or_insert(Vec::new()).push(SourceChange {
+            .or_default().push(SourceChange {
                 range: range.clone(),
                 delta: change.text.len() as isize - (range.end as isize - range.start as isize)
             });
@@ -84,26 +78,14 @@ impl Document {
     }
     //Return (line, character) or none if outside of source
     pub fn range_to_line_character(&self, r :usize) -> Option<(usize, usize)> {
-        let l = match self.typst_source.byte_to_line(r) {
-            Some(c) => c,
-            None => return None,
-        };
-        let c = match self.typst_source.byte_to_column(r) {
-            Some(c) => c,
-            None => return None,
-        };
+        let l = self.typst_source.byte_to_line(r)?;
+        let c = self.typst_source.byte_to_column(r)?;
         Some((l,c))
     }
     pub fn lsp_range_to_byte_range(&self, range :&tower_lsp::lsp_types::Range) -> Option<Range<usize>> {
         Some(Range{
-            start: match self.typst_source.line_column_to_byte(range.start.line as usize, range.start.character as usize) {
-                Some(c) => {c},
-                None => {return None}
-            },
-            end: match self.typst_source.line_column_to_byte(range.end.line as usize, range.end.character as usize) {
-                Some(c) => {c},
-                None => {return None}
-            },
+            start: self.typst_source.line_column_to_byte(range.start.line as usize, range.start.character as usize)?,
+            end: self.typst_source.line_column_to_byte(range.end.line as usize, range.end.character as usize)?,
         })
     }
     pub fn byte_range_to_lsp_range(&self, range :&Range<usize>) -> Option<tower_lsp::lsp_types::Range> {
@@ -133,36 +115,26 @@ impl Document {
         )
 
     }
-    pub fn get_chunk_at_pos(&self, pos :&tower_lsp::lsp_types::Position) -> Option<String> {
-        let byte_index = match self.typst_source.line_column_to_byte(pos.line as usize, pos.character as usize) {
-            Some(c) => c,
-            None => return None,
-        };
-        let range = match self.chunk_by_byte_index(byte_index) {
-            Some(c) => c,
-            None => return None,
-        };
-
-        match self.typst_source.get(range) {
-            Some(c) => Some(c.to_string()),
-            None => None
+    //The byte offset of a position whose character counts UTF-16 code units, the LSP default
+    pub fn utf16_position_to_byte(&self, position :&Position) -> Option<usize> {
+        let line_start = self.typst_source.line_to_byte(position.line as usize)?;
+        let utf16 = self.typst_source.byte_to_utf16(line_start)? + position.character as usize;
+        let byte = self.typst_source.utf16_to_byte(utf16)?;
+        // Past the end of the line
+        if self.typst_source.byte_to_line(byte) != Some(position.line as usize) {
+            return None;
         }
+        Some(byte)
     }
-    pub fn get_chunk_by_range(&self, range :Range<usize>) -> Option<String> {
-        match self.typst_source.get(range) {
-            Some(c) => Some(c.to_string()),
-            None => None
-        }
+    pub fn byte_to_utf16_position(&self, byte :usize) -> Option<Position> {
+        let line = self.typst_source.byte_to_line(byte)?;
+        let line_start = self.typst_source.line_to_byte(line)?;
+        let character = self.typst_source.byte_to_utf16(byte)? - self.typst_source.byte_to_utf16(line_start)?;
+        Some(Position::new(line as u32, character as u32))
     }
-    fn chunk_by_byte_index(&self, byte_index :usize) -> Option<Range<usize>> {
-        for r in &self.text_chunks {
-            if r.start <= byte_index && byte_index <= r.end {
-                return Some(r.clone());
-            }
-        }
-        None
+    pub fn get_chunk_by_range(&self, range :Range<usize>) -> Option<String> {
+        self.typst_source.get(range).map(|c| c.to_string())
     }
-    // Finds and returns the word a position pos. If none is found, None is returned
     pub fn find_word(&self, pos :Position) -> Option<String> {
         let char_num :usize = match pos.character.try_into() {
             Ok(c) => {c},
@@ -182,28 +154,25 @@ impl Document {
             return None 
         }
         let mut start = "".to_string();
-        let end :String;
-        if char_num >= 1 {
-            if (line_chars[char_num-1] as char).is_alphabetic() {
-                start = match line[..char_num].split_whitespace().last() {
-                    Some(c) => {c.to_string()},
-                    None => {"".to_string()},
-                };
-            }
+        if char_num >= 1 && (line_chars[char_num-1] as char).is_alphabetic() {
+            start = match line[..char_num].split_whitespace().last() {
+                Some(c) => {c.to_string()},
+                None => {"".to_string()},
+            };
         }
-        if char_num+1 < line_chars.len() && (line_chars[char_num+1] as char).is_alphabetic() {
-            end = match line[char_num..].split_whitespace().next() {
+        let end = if char_num+1 < line_chars.len() && (line_chars[char_num+1] as char).is_alphabetic() {
+            match line[char_num..].split_whitespace().next() {
                 Some(c) => {c.to_string()},
                 None => {
                     (line_chars[char_num] as char).to_string()
                 },
-            };
+            }
         } else {
-            end = (line_chars[char_num] as char).to_string()
-        }
+            (line_chars[char_num] as char).to_string()
+        };
         let mut word = start+&end;
         word = word.chars().filter(|&c| c.is_alphabetic()).collect();
-        if word == "" {
+        if word.is_empty() {
             return None;
         }
 
@@ -211,124 +180,60 @@ impl Document {
     }
 
 }
-fn find_line(doc :String, pos :Position) -> Option<String> {
-    let line_num :usize = match pos.line.try_into() {
-        Ok(c) => {c},
-        Err(_) => {return None},
-    };
-    let lines :Vec<&str> = doc.lines().collect();
-    let line = match lines.get(line_num) {
-        Some(c) => {c},
-        None => {return None},
-    }.to_string();
-    return Some(line);
-}
-pub async fn mark_text(uri :&Url, working_doc :&Document, client :&Client) {
-    let mut diagnostics :Vec<tower_lsp::lsp_types::Diagnostic> = vec![];
-    for a in &working_doc.text_chunks {
-        let b = working_doc.typst_source.get(a.clone()).unwrap();
-        let (line1, character1) = working_doc.range_to_line_character(a.start).unwrap();
-        let (line2, character2) = working_doc.range_to_line_character(a.end).unwrap();
-        let r = tower_lsp::lsp_types::Range {
-            start: Position {
-                line: line1 as u32,
-                character: character1 as u32,
-            },
-            end: Position {
-                line: line2 as u32,
-                character: character2 as u32,
-            }
-        };
-
-        //let message = format!("startLine: {}, startChar: {} | endLine: {}, endChar: {} ", c.range.start.line, c.range.start.character, c.range.end.line, c.range.end.character);
-        let message = format!("startLine: {}, startChar: {} | endLine: {}, endChar: {} | content: \"{}\"", 
-                              r.start.line, 
-                              r.start.character, 
-                              r.end.line, 
-                              r.end.character, 
-                              b
-                              );
-        diagnostics.push(
-            tower_lsp::lsp_types::Diagnostic {
-                range: r,
-                severity: None,
-                code: None,
-                code_description: None,
-                source: None,
-                message,
-                related_information: None,
-                tags: None,
-                data: None
-            }
-            );
+// Returns the name of the function called by a FuncCall node, e.g. "heading" for
+// `#heading[..]` or "bar" for `#foo.bar()`
+pub fn func_call_name(node :&SyntaxNode) -> Option<String> {
+    let callee = node.children().next()?;
+    match callee.kind() {
+        SyntaxKind::Ident => Some(callee.text().to_string()),
+        SyntaxKind::FieldAccess => callee.children()
+            .rfind(|c| c.kind() == SyntaxKind::Ident)
+            .map(|c| c.text().to_string()),
+        _ => None,
     }
-    client.publish_diagnostics(uri.clone(), diagnostics, None).await;
-}
-fn cleanup_range(mut ranges :Vec<Range<usize>>) -> Vec<Range<usize>> {
-    ranges.sort_by_key(|range| range.start);
-    ranges = merge_range_neighbors(ranges);
-    ranges.sort_by_key(|range| range.start);
-    ranges = merge_range_neighbors(ranges);
-    ranges.retain(|r| (r.end - r.start) > 2);
-
-    ranges
 }
-fn merge_range_neighbors(ranges :Vec<Range<usize>>) -> Vec<Range<usize>> {
-    let mut out_ranges :Vec<Range<usize>> = vec!{};
-    if ranges.len() == 0 { return vec!{};}
-    let mut working_range :Range<usize> = ranges[0].clone();
-    for r in ranges {
-        if !within(if r.start == 0 {0} else {r.start-1}, &working_range) {
-            out_ranges.push(working_range);
-            working_range = r.clone();
-            continue
+// Returns the value node of the named argument `name` in a FuncCall or SetRule node
+pub fn named_arg<'a>(node :&'a SyntaxNode, name :&str) -> Option<&'a SyntaxNode> {
+    let args = node.children().find(|c| c.kind() == SyntaxKind::Args)?;
+    for arg in args.children() {
+        if arg.kind() != SyntaxKind::Named {
+            continue;
         }
-        working_range.end = if r.end > working_range.end {r.end} else {working_range.end};
-
+        let mut arg_children = arg.children();
+        match arg_children.next() {
+            Some(c) if c.kind() == SyntaxKind::Ident && c.text() == name => {},
+            _ => continue,
+        }
+        return arg.children()
+            .rfind(|c| !matches!(c.kind(), SyntaxKind::Ident | SyntaxKind::Colon | SyntaxKind::Space));
     }
-    out_ranges
-
-}
-fn within(a :usize, b :&Range<usize>) -> bool {
-    b.start <= a && a <= b.end
+    None
 }
-
-// Return a vec of the ranges that is text, needs cleaning
-fn parse_recursive(typst_source :&Source, in_str :&String, this_node :&SyntaxNode) -> Vec<Range<usize>> {
-    let mut chunks = vec!{};
-    let children = this_node.children();
-    let this_range = nod_to_range_unsafe(typst_source, this_node);
-    let mut cursor = this_range.start;
-    let this_is_text = is_text_kind(this_node);
-
-    if children.len() == 0 && this_is_text {
-        chunks.push(this_range);
-    }
-
-    for child in children {
-        let child_range = nod_to_range_unsafe(typst_source, child);
-        if this_is_text {
-            chunks.push(Range{start: cursor, end: child_range.start});
+// The positional arguments of a FuncCall or SetRule node, trailing content blocks included
+pub fn positional_args(node :&SyntaxNode) -> Vec<&SyntaxNode> {
+    let mut out = vec![];
+    for child in node.children() {
+        if child.kind() != SyntaxKind::Args {
+            continue;
+        }
+        for arg in child.children() {
+            match arg.kind() {
+                SyntaxKind::Named
+                | SyntaxKind::LeftParen
+                | SyntaxKind::RightParen
+                | SyntaxKind::Comma
+                | SyntaxKind::Space => {},
+                _ => out.push(arg),
+            }
         }
-        cursor = child_range.end;
-        chunks.append(&mut parse_recursive(typst_source, in_str, child));
     }
-    chunks
+    out
 }
-fn nod_to_range_unsafe(source :&Source, node :&SyntaxNode)  -> Range<usize> {
-    source.range(node.span()).unwrap()
-}
-fn is_text_kind(node :&SyntaxNode) -> bool {
-    match node.kind() {
-            SyntaxKind::Markup => false,
-            SyntaxKind::Text => true,
-            SyntaxKind::Space => false,
-            SyntaxKind::Strong => true,
-            SyntaxKind::Emph => true,
-            SyntaxKind::Raw => true,
-            SyntaxKind::Link => false,
-            SyntaxKind::Heading => false,
-            _ => false,
+// The value of a string literal node without the quotes
+pub fn str_value(node :&SyntaxNode) -> Option<String> {
+    if node.kind() != SyntaxKind::Str {
+        return None;
     }
-
+    let text = node.text();
+    Some(text.trim_start_matches('"').trim_end_matches('"').to_string())
 }
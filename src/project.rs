@@ -0,0 +1,224 @@
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::ops::Deref;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use dashmap::DashMap;
+use tower_lsp::lsp_types::Url;
+use typst_syntax::{SyntaxKind, SyntaxNode};
+use crate::parse::{self, Backend, Document};
+
+// The files reachable through `#include` and `#import` from the documents open in the editor
+pub struct Project {
+    //The workspace folders, paths starting with / are resolved against the one containing the file
+    pub roots: RwLock<Vec<PathBuf>>,
+    //Dependencies that are not open in the editor, read from disk
+    pub disk_documents: DashMap<Url, DiskDocument>,
+    //The files each file includes or imports
+    pub dependencies: DashMap<Url, Vec<Url>>,
+    //The revision of each file its dependencies were read from
+    pub dependency_revisions: DashMap<Url, Revision>,
+}
+// A file read from disk and when it was modified then, it is read again once that changes
+pub struct DiskDocument {
+    pub modified: SystemTime,
+    pub document: Arc<Document>,
+}
+// Tells the texts of a document apart, what is found in one can be kept until it changes
+#[derive(Clone, Copy, PartialEq)]
+pub enum Revision {
+    //The version of a document open in the editor
+    Open(isize),
+    //The modification time of a document read from disk
+    Disk(SystemTime),
+}
+
+impl Project {
+    pub fn new() -> Self {
+        Project {
+            roots: RwLock::new(vec![]),
+            disk_documents: DashMap::new(),
+            dependencies: DashMap::new(),
+            dependency_revisions: DashMap::new(),
+        }
+    }
+}
+impl Backend {
+    // Runs f with the document, the open one if there is one, otherwise it is read from disk
+    pub fn with_document<R>(&self, uri :&Url, f :impl FnOnce(&Document) -> R) -> Option<R> {
+        self.with_revision(uri, |c, _| f(c))
+    }
+    // Like with_document, f also gets the revision of the document
+    pub fn with_revision<R>(&self, uri :&Url, f :impl FnOnce(&Document, Revision) -> R) -> Option<R> {
+        if let Some(c) = self.document_map.get(uri) {
+            return Some(f(c.deref(), Revision::Open(c.latest_version)));
+        }
+        let path = uri.to_file_path().ok()?;
+        let modified = match fs::metadata(&path).and_then(|c| c.modified()) {
+            Ok(c) => c,
+            Err(_) => {
+                self.project.disk_documents.remove(uri);
+                return None;
+            },
+        };
+        let fresh = self.project.disk_documents.get(uri).is_some_and(|c| c.modified == modified);
+        if !fresh {
+            let text = match fs::read_to_string(&path) {
+                Ok(c) => c,
+                Err(_) => return None,
+            };
+            self.project.disk_documents.insert(uri.clone(), DiskDocument {
+                modified,
+                document: Arc::new(Document::new(0, &text)),
+            });
+        }
+        // Not run under the map guard, f may read other files from disk which inserts into the map
+        let document = self.project.disk_documents.get(uri)?.document.clone();
+        Some(f(&document, Revision::Disk(modified)))
+    }
+    // Resolves a path written in a typst file. Relative paths start in the directory of the file,
+    // absolute ones in the workspace folder containing it. Packages like "@preview/.." are skipped.
+    pub fn resolve_path(&self, from :&Url, path :&str) -> Option<Url> {
+        if path.starts_with('@') {
+            return None;
+        }
+        let file = from.to_file_path().ok()?;
+        if let Some(stripped) = path.strip_prefix('/') {
+            let roots = self.project.roots.read().unwrap();
+            let root = match roots.iter().filter(|c| file.starts_with(c)).max_by_key(|c| c.as_os_str().len()) {
+                Some(c) => c.clone(),
+                None => file.parent()?.to_path_buf(),
+            };
+            return Url::from_file_path(normalize(&root.join(stripped))).ok();
+        }
+        Url::from_file_path(normalize(&file.parent()?.join(path))).ok()
+    }
+    // Re-reads the includes and imports of the file and of every file it reaches
+    pub fn update_dependencies(&self, uri :&Url) {
+        let mut queue :VecDeque<Url> = VecDeque::from([uri.clone()]);
+        let mut visited :HashSet<Url> = HashSet::new();
+        while let Some(at) = queue.pop_front() {
+            if !visited.insert(at.clone()) {
+                continue;
+            }
+            // Files seen before are looked at again too, their includes may have changed on disk.
+            // Their syntax tree is only walked again when they changed.
+            let known = self.project.dependency_revisions.get(&at).map(|c| *c);
+            let read = self.with_revision(&at, |c, revision| {
+                if known == Some(revision) {
+                    return (revision, None);
+                }
+                (revision, Some(module_paths(c.typst_source.root())))
+            });
+            let (revision, paths) = match read {
+                Some(c) => c,
+                None => continue,
+            };
+            let paths = match paths {
+                Some(c) => c,
+                None => {
+                    if let Some(c) = self.project.dependencies.get(&at) {
+                        queue.extend(c.iter().cloned());
+                    }
+                    continue;
+                },
+            };
+            let dependencies :Vec<Url> = paths.iter()
+                .filter_map(|c| self.resolve_path(&at, c))
+                .collect();
+            queue.extend(dependencies.iter().cloned());
+            self.project.dependencies.insert(at.clone(), dependencies);
+            self.project.dependency_revisions.insert(at, revision);
+        }
+    }
+    // Forgets a file read from disk, e.g. because it is now open or changed on disk
+    pub fn forget_disk_document(&self, uri :&Url) {
+        self.project.disk_documents.remove(uri);
+        self.project.dependency_revisions.remove(uri);
+    }
+    // Every file of the projects the file belongs to, the file itself first. A project is all files
+    // reachable from a file that no other file includes or imports.
+    pub fn project_files(&self, uri :&Url) -> Vec<Url> {
+        let mut tops :Vec<Url> = vec![];
+        let mut queue :VecDeque<Url> = VecDeque::from([uri.clone()]);
+        let mut visited :HashSet<Url> = HashSet::new();
+        while let Some(at) = queue.pop_front() {
+            if !visited.insert(at.clone()) {
+                continue;
+            }
+            let parents :Vec<Url> = self.project.dependencies.iter()
+                .filter(|c| c.value().contains(&at))
+                .map(|c| c.key().clone())
+                .collect();
+            if parents.is_empty() {
+                tops.push(at.clone());
+            }
+            queue.extend(parents);
+        }
+        if tops.is_empty() {
+            // Every ancestor is part of a cycle
+            tops.push(uri.clone());
+        }
+        let mut out :Vec<Url> = vec![uri.clone()];
+        let mut queue :VecDeque<Url> = VecDeque::from(tops);
+        let mut visited :HashSet<Url> = HashSet::new();
+        while let Some(at) = queue.pop_front() {
+            if !visited.insert(at.clone()) {
+                continue;
+            }
+            if let Some(dependencies) = self.project.dependencies.get(&at) {
+                queue.extend(dependencies.iter().cloned());
+            }
+            if &at != uri {
+                out.push(at);
+            }
+        }
+        out
+    }
+}
+// Paths as written in `#include ".."` and `#import "..": ..`
+pub fn module_paths(node :&SyntaxNode) -> Vec<String> {
+    let mut out = vec![];
+    match node.kind() {
+        SyntaxKind::ModuleInclude | SyntaxKind::ModuleImport => {
+            out.extend(node.children().find_map(parse::str_value));
+            return out;
+        },
+        SyntaxKind::Raw
+        | SyntaxKind::LineComment
+        | SyntaxKind::BlockComment => return out,
+        _ => {},
+    }
+    for child in node.children() {
+        out.extend(module_paths(child));
+    }
+    out
+}
+// Drops the . and .. of a path without looking at the file system, the uri of `ch/../a.typ` has to
+// be the one of `a.typ` the editor opens
+fn normalize(path :&Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            // Above the root is the root
+            Component::ParentDir => {
+                out.pop();
+            },
+            _ => out.push(component),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalized_paths() {
+        assert_eq!(normalize(Path::new("/book/src/../ch/./a.typ")), PathBuf::from("/book/ch/a.typ"));
+        assert_eq!(normalize(Path::new("/book/../../a.typ")), PathBuf::from("/a.typ"));
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::HashSet;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use serde_json::Value;
+use tower_lsp::lsp_types::{
+    ConfigurationItem,
+    DidChangeWatchedFilesParams,
+    DidChangeWatchedFilesRegistrationOptions,
+    DidChangeWorkspaceFoldersParams,
+    FileSystemWatcher,
+    GlobPattern,
+    InitializeParams,
+    MessageType,
+    Registration,
+    Url,
+};
+use crate::parse::Backend;
+use crate::{components, config, CONFIG, CONFIG_FILE};
+
+// The section of the editor settings the server reads, `"typst-grammar": {..}`
+pub const SECTION :&str = "typst-grammar";
+
+lazy_static! {
+    //The settings from the editor, they override the config files
+    static ref CLIENT_SETTINGS :RwLock<Value> = RwLock::new(Value::Null);
+    //The defaults and the user config
+    static ref USER_SETTINGS :RwLock<Value> = RwLock::new(config::user_settings());
+    //The file given with --config, read once per reload
+    static ref EXPLICIT_SETTINGS :RwLock<Value> = RwLock::new(Value::Null);
+    //The project config in each directory, None when the directory has none
+    static ref PROJECT_CONFIGS :DashMap<PathBuf, Option<Arc<config::ProjectConfig>>> = DashMap::new();
+    //The configuration of each file, cleared when any config changes
+    static ref RESOLVED :DashMap<Url, Arc<config::RootConfig>> = DashMap::new();
+    //If the editor answers workspace/configuration requests
+    static ref CAN_PULL :AtomicBool = AtomicBool::new(false);
+    //If the editor wants didChangeConfiguration registered at runtime
+    static ref CAN_REGISTER :AtomicBool = AtomicBool::new(false);
+    //If the editor can watch the config files for the server
+    static ref CAN_WATCH :AtomicBool = AtomicBool::new(false);
+}
+
+// The configuration of a file: the defaults, the user config, the nearest project config with
+// the overrides matching the file, the --config file and the editor settings, each overriding the
+// keys set by the ones before
+pub fn config_for(uri :&Url) -> Arc<config::RootConfig> {
+    if let Some(c) = RESOLVED.get(uri) {
+        return c.clone();
+    }
+    let dir = uri.to_file_path().ok().and_then(|c| c.parent().map(|c| c.to_path_buf()));
+    let resolved = Arc::new(resolve(dir.as_deref(), uri.to_file_path().ok().as_deref()));
+    RESOLVED.insert(uri.clone(), resolved.clone());
+    resolved
+}
+fn resolve(dir :Option<&Path>, file :Option<&Path>) -> config::RootConfig {
+    let mut settings = USER_SETTINGS.read().unwrap().clone();
+    let mut errors :Vec<String> = vec![];
+    if let Some((dir, project)) = dir.and_then(|c| Some((c, nearest_project_config(c)?))) {
+        let project_settings = project.settings_for(file.unwrap_or(dir), &mut errors);
+        config::merge(&mut settings, project_settings);
+        for error in errors.drain(..) {
+            log::error!("In {}: {}", project.path.display(), error);
+        }
+    }
+    config::merge(&mut settings, EXPLICIT_SETTINGS.read().unwrap().clone());
+    // Problems with the editor settings are shown once in reload_config
+    config::merge_checked(&mut settings, &CLIENT_SETTINGS.read().unwrap(), &mut errors);
+    config::RootConfig::from_value(settings)
+}
+fn nearest_project_config(dir :&Path) -> Option<Arc<config::ProjectConfig>> {
+    for ancestor in dir.ancestors() {
+        let found = match PROJECT_CONFIGS.get(ancestor) {
+            Some(c) => c.clone(),
+            None => {
+                let read = config::ProjectConfig::read(ancestor).map(Arc::new);
+                PROJECT_CONFIGS.insert(ancestor.to_path_buf(), read.clone());
+                read
+            },
+        };
+        if found.is_some() {
+            return found;
+        }
+    }
+    None
+}
+// Reads the config files again and rebuilds CONFIG, the configuration used for what does not
+// belong to a file, with the project config of `root`. Returns the problems with the editor
+// settings.
+pub fn load_config(root :Option<&Path>) -> Vec<String> {
+    *USER_SETTINGS.write().unwrap() = config::user_settings();
+    let explicit = CONFIG_FILE.read().unwrap().clone();
+    let mut errors :Vec<String> = vec![];
+    let mut explicit_settings = Value::Object(Default::default());
+    if let Some(c) = explicit.and_then(|c| config::read_value(&c, true)) {
+        config::merge_checked(&mut explicit_settings, &c, &mut errors);
+        for error in errors.drain(..) {
+            log::error!("In the --config file: {}", error);
+        }
+    }
+    *EXPLICIT_SETTINGS.write().unwrap() = explicit_settings;
+    PROJECT_CONFIGS.clear();
+    RESOLVED.clear();
+    config::merge_checked(&mut Value::Object(Default::default()), &CLIENT_SETTINGS.read().unwrap(), &mut errors);
+    *CONFIG.write().unwrap() = resolve(root, None);
+    errors
+}
+
+impl Backend {
+    // Remembers the initializationOptions and what the editor supports
+    pub fn init_settings(&self, params :&InitializeParams) {
+        if let Some(c) = &params.initialization_options {
+            *CLIENT_SETTINGS.write().unwrap() = section(c.clone());
+        }
+        if let Some(workspace) = params.capabilities.workspace.as_ref() {
+            CAN_PULL.store(workspace.configuration == Some(true), Ordering::Relaxed);
+            let register = workspace.did_change_configuration.as_ref()
+                .and_then(|c| c.dynamic_registration)
+                .unwrap_or(false);
+            CAN_REGISTER.store(register, Ordering::Relaxed);
+            let watch = workspace.did_change_watched_files.as_ref()
+                .and_then(|c| c.dynamic_registration)
+                .unwrap_or(false);
+            CAN_WATCH.store(watch, Ordering::Relaxed);
+        }
+    }
+    // Asks to be told about settings and config file changes and reads the current settings
+    pub async fn register_settings(&self) {
+        let mut registrations :Vec<Registration> = vec![];
+        if CAN_REGISTER.load(Ordering::Relaxed) {
+            registrations.push(Registration {
+                id: "typst-grammar-configuration".to_string(),
+                method: "workspace/didChangeConfiguration".to_string(),
+                register_options: None,
+            });
+        }
+        if CAN_WATCH.load(Ordering::Relaxed) {
+            let mut patterns :Vec<String> = config::PROJECT_FILE_NAMES.iter().map(|c| format!("**/{}", c)).collect();
+            patterns.push(format!("**/{}", components::BASELINE_FILE_NAME));
+            let options = DidChangeWatchedFilesRegistrationOptions {
+                watchers: patterns.into_iter()
+                    .map(|c| FileSystemWatcher {
+                        glob_pattern: GlobPattern::String(c),
+                        kind: None,
+                    })
+                    .collect(),
+            };
+            registrations.push(Registration {
+                id: "typst-grammar-config-files".to_string(),
+                method: "workspace/didChangeWatchedFiles".to_string(),
+                register_options: serde_json::to_value(options).ok(),
+            });
+        }
+        if !registrations.is_empty() {
+            if let Err(e) = self.client.register_capability(registrations).await {
+                log::warn!("Could not register for configuration changes: {}", e);
+            }
+        }
+        self.pull_settings().await;
+        self.reload_config().await;
+    }
+    // New settings pushed by didChangeConfiguration. Clients that pull send null or an empty
+    // object here and are asked for the settings instead.
+    pub async fn settings_changed(&self, settings :Value) {
+        let pushed = section(settings);
+        let empty = pushed.is_null() || pushed.as_object().is_some_and(|c| c.is_empty());
+        if empty && CAN_PULL.load(Ordering::Relaxed) {
+            self.pull_settings().await;
+        } else {
+            *CLIENT_SETTINGS.write().unwrap() = pushed;
+        }
+        self.reload_config().await;
+    }
+    // A config or baseline file was created, changed or deleted
+    pub async fn config_files_changed(&self, params :DidChangeWatchedFilesParams) {
+        let mut baselines :HashSet<PathBuf> = HashSet::new();
+        for change in params.changes {
+            let path = match change.uri.to_file_path() {
+                Ok(c) => c,
+                Err(_) => continue,
+            };
+            if path.file_name().is_some_and(|c| c == components::BASELINE_FILE_NAME) {
+                baselines.extend(path.parent().map(|c| c.to_path_buf()));
+            }
+        }
+        for dir in baselines {
+            components::load_baseline(&dir);
+        }
+        self.reload_config().await;
+    }
+    // Workspace folders were added or removed, their baselines and configs are (un)loaded
+    pub async fn workspace_folders_changed(&self, params :DidChangeWorkspaceFoldersParams) {
+        {
+            let mut roots = self.project.roots.write().unwrap();
+            for removed in &params.event.removed {
+                if let Ok(c) = removed.uri.to_file_path() {
+                    roots.retain(|x| x != &c);
+                    components::unload_baseline(&c);
+                }
+            }
+            for added in &params.event.added {
+                if let Ok(c) = added.uri.to_file_path() {
+                    components::load_baseline(&c);
+                    if !roots.contains(&c) {
+                        roots.push(c);
+                    }
+                }
+            }
+        }
+        self.reload_config().await;
+    }
+    async fn pull_settings(&self) {
+        if !CAN_PULL.load(Ordering::Relaxed) {
+            return;
+        }
+        let item = ConfigurationItem {
+            scope_uri: None,
+            section: Some(SECTION.to_string()),
+        };
+        match self.client.configuration(vec![item]).await {
+            Ok(c) => {
+                if let Some(settings) = c.into_iter().next().filter(|c| !c.is_null()) {
+                    *CLIENT_SETTINGS.write().unwrap() = settings;
+                }
+            },
+            Err(e) => log::warn!("Could not read the editor settings: {}", e),
+        }
+    }
+    // Builds the configuration again from the config files and the editor settings, reports the
+    // settings that were skipped and re-runs the checkers the change affects
+    pub async fn reload_config(&self) {
+        let open :Vec<Url> = self.document_map.iter().map(|c| c.key().clone()).collect();
+        let old :Vec<Value> = open.iter()
+            .map(|c| serde_json::to_value(config_for(c).deref()).unwrap())
+            .collect();
+        let root = self.project.roots.read().unwrap().first().cloned();
+        for error in load_config(root.as_deref()) {
+            self.client.show_message(MessageType::ERROR, format!("typst-grammar: {}", error)).await;
+        }
+        let new :Vec<Value> = open.iter()
+            .map(|c| serde_json::to_value(config_for(c).deref()).unwrap())
+            .collect();
+        let changed = |key :&str| old.iter().zip(new.iter()).any(|(a, b)| a.get(key) != b.get(key));
+        components::refilter(self);
+        // A checker turned back on is run again like one whose settings changed
+        let rerun :Vec<&'static str> = self.checkers.iter()
+            .filter(|c| !c.on_demand())
+            .filter(|c| changed("checkers") || c.config_keys().iter().any(|x| changed(x)))
+            .map(|c| c.name())
+            .collect();
+        for name in rerun {
+            for uri in &open {
+                components::run_checker(self, name, uri, None).await;
+            }
+        }
+        for uri in &open {
+            components::publish_file_diagnostics(self, uri).await;
+        }
+    }
+}
+// Editors send either the whole settings or only the server's section
+fn section(settings :Value) -> Value {
+    match settings {
+        Value::Object(mut c) if c.contains_key(SECTION) => c.remove(SECTION).unwrap_or(Value::Null),
+        c => c,
+    }
+}
@@ -22,10 +22,10 @@ pub fn file_to_array(file_path: &str) -> Vec<String> {
     }
     result.sort();
 
-    return result;
+    result
 }
 //Returns a vec with the elements matching the query. OBS, v must be lowercase and sorted.
-pub fn query( query_in :&String, v :&Vec<String>) -> Vec<String> {
+pub fn query( query_in :&str, v :&[String]) -> Vec<String> {
     let query = query_in.trim().to_lowercase();
     let mut at = v.len()/2;
     let mut upper = v.len();
@@ -33,11 +33,9 @@ pub fn query( query_in :&String, v :&Vec<String>) -> Vec<String> {
     loop {
         match is_equal(&query, &v[at]) {
             Equality::Equal => {
-                let start_index = go_back(&v, &query, at);
-                let end_index = go_forward(start_index, &v, &query, at);
-                if end_index-start_index > MAX_VEC_LENGTH {
-                    return v[start_index..start_index+MAX_VEC_LENGTH].to_vec();
-                }
+                let start_index = go_back(v, &query, at);
+                // At most MAX_VEC_LENGTH words
+                let end_index = go_forward(start_index, v, &query, at);
                 return v[start_index..end_index].to_vec();
             }
             Equality::QueryLesser => {
@@ -52,27 +50,28 @@ pub fn query( query_in :&String, v :&Vec<String>) -> Vec<String> {
         }
     }
 }
-fn go_back(v :&Vec<String>, query :&String, mut index :usize) -> usize {
+fn go_back(v :&[String], query :&str, mut index :usize) -> usize {
     while index > 0 {
         index-=1;
-        if is_equal(&query, &v[index]) != Equality::Equal {
+        if is_equal(query, &v[index]) != Equality::Equal {
             index+=1;
             break;
         }
     }
-    return index;
+    index
 }
-fn go_forward(start :usize, v :&Vec<String>, query :&String, mut index :usize) -> usize {
-    while index-start < MAX_VEC_LENGTH && index <= v.len() {
-        index+=1;
-        if is_equal(&query, &v[index]) != Equality::Equal {
+// The end of the matching words from index, exclusive
+fn go_forward(start :usize, v :&[String], query :&str, mut index :usize) -> usize {
+    while index-start < MAX_VEC_LENGTH && index < v.len() {
+        if is_equal(query, &v[index]) != Equality::Equal {
             break;
         }
+        index+=1;
     }
-    return index;
+    index
 }
-fn is_equal(query_in :&String, res :&String) -> Equality{
-    let mut query = query_in.clone();
+fn is_equal(query_in :&str, res :&str) -> Equality{
+    let mut query = query_in.to_string();
     if query.len() > res.len() {
         query = query[0..res.len()].to_string();
     }
@@ -80,7 +79,7 @@ fn is_equal(query_in :&String, res :&String) -> Equality{
     let mut r_chars = res.chars();
 
     for c in q_chars {
-        let to = r_chars.nth(0).unwrap();
+        let to = r_chars.next().unwrap();
         if c == to  {
             continue;
         } else if (c as u8) > (to as u8) {
@@ -88,5 +87,5 @@ fn is_equal(query_in :&String, res :&String) -> Equality{
         }
         return Equality::QueryLesser
     }
-    return Equality::Equal;
+    Equality::Equal
 }